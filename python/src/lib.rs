@@ -0,0 +1,101 @@
+// pyo3 0.22 macros expand to conversions clippy considers useless
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pythonize::{depythonize, pythonize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use updvcspins::errors::Error;
+use updvcspins::events::NoopObserver;
+use updvcspins::git;
+use updvcspins::makepkg::{self, Backend, ResolvedPin};
+use updvcspins::rewrite::{self as rewriter, Options};
+use updvcspins::source::{Input, Source};
+
+fn to_py_err(err: Error) -> PyErr {
+    PyValueError::new_err(format!("{:#}", err))
+}
+
+fn read_pkgbuild(path: &Path) -> PyResult<Vec<u8>> {
+    Ok(fs::read(path)?)
+}
+
+/// Parse a makepkg source string like `name::git+https://...#tag=v1?signed`
+#[pyfunction]
+fn parse_source(py: Python<'_>, source: &str) -> PyResult<PyObject> {
+    let input = source.parse::<Input>().map_err(to_py_err)?;
+    Ok(pythonize(py, &input)?.unbind())
+}
+
+/// List the entries of `vcspins=` in a PKGBUILD
+#[pyfunction]
+fn list_pins(py: Python<'_>, pkgbuild: PathBuf) -> PyResult<PyObject> {
+    let pkgbuild = read_pkgbuild(&pkgbuild)?;
+    let backend = Backend::bash(&pkgbuild).map_err(to_py_err)?;
+    let pins = makepkg::list_pins(&pkgbuild, &backend).map_err(to_py_err)?;
+    Ok(pythonize(py, &pins)?.unbind())
+}
+
+/// List the entries of `source=` in a PKGBUILD
+#[pyfunction]
+fn list_sources(py: Python<'_>, pkgbuild: PathBuf) -> PyResult<PyObject> {
+    let sources = makepkg::list_sources(&read_pkgbuild(&pkgbuild)?).map_err(to_py_err)?;
+    Ok(pythonize(py, &sources)?.unbind())
+}
+
+/// Resolve the tag of a git source string against a local repository
+#[pyfunction]
+fn resolve(py: Python<'_>, source: &str, repo: PathBuf) -> PyResult<PyObject> {
+    let source = match source.parse::<Input>().map_err(to_py_err)?.take_source() {
+        Source::Git(git) => git,
+        _ => return Err(PyValueError::new_err("Only git sources can be resolved")),
+    };
+    let options = git::Options {
+        fetch: false,
+        non_interactive: true,
+        partial_clone: false,
+        ls_remote: false,
+    };
+    let pin = git::run(source, &repo, &options, &NoopObserver).map_err(to_py_err)?;
+    Ok(pythonize(py, &pin)?.unbind())
+}
+
+/// Rewrite the content of a PKGBUILD with resolved pins, keyed by source filename
+#[pyfunction]
+#[pyo3(signature = (pkgbuild, sources, pins, pin_commit=false))]
+fn rewrite(
+    pkgbuild: &str,
+    sources: Vec<String>,
+    pins: &Bound<'_, PyAny>,
+    pin_commit: bool,
+) -> PyResult<String> {
+    let sources = sources
+        .iter()
+        .map(|s| s.parse::<Input>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(to_py_err)?;
+    let mut sources = BTreeMap::from([("source".to_string(), sources)]);
+    let pins: BTreeMap<String, ResolvedPin> = depythonize(pins)?;
+    let options = Options {
+        pin_commit,
+        ..Default::default()
+    };
+
+    let mut out = Vec::new();
+    rewriter::rewrite(pkgbuild.as_bytes(), &mut out, &mut sources, &pins, &options)
+        .map_err(to_py_err)?;
+    String::from_utf8(out).map_err(|err| PyValueError::new_err(err.to_string()))
+}
+
+#[pymodule]
+#[pyo3(name = "updvcspins")]
+fn updvcspins_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(parse_source, m)?)?;
+    m.add_function(wrap_pyfunction!(list_pins, m)?)?;
+    m.add_function(wrap_pyfunction!(list_sources, m)?)?;
+    m.add_function(wrap_pyfunction!(resolve, m)?)?;
+    m.add_function(wrap_pyfunction!(rewrite, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,46 @@
+# Run with `maturin develop && python -m unittest discover tests` in the python folder
+import os
+import tempfile
+import unittest
+
+import updvcspins
+
+
+class TestBindings(unittest.TestCase):
+    def test_parse_source(self):
+        source = updvcspins.parse_source("foo::git+https://example.com/bar.git#tag=v1?signed")
+        source, name = source["url-with-filename"]
+        self.assertEqual(name, "foo")
+        git = source["git"]
+        self.assertEqual(git["url"], "git+https://example.com/bar.git")
+        self.assertEqual(git["tag"], "v1")
+        self.assertTrue(git["signed"])
+
+    def test_parse_invalid(self):
+        with self.assertRaises(ValueError):
+            updvcspins.parse_source("git+https://example.com/bar.git#revision=1")
+
+    def test_list_sources(self):
+        with tempfile.TemporaryDirectory() as dir:
+            path = os.path.join(dir, "PKGBUILD")
+            with open(path, "w") as f:
+                f.write('source=("git+https://example.com/foo.git#tag=v1" "fix.patch")\n')
+            sources = updvcspins.list_sources(path)
+        self.assertEqual(sources[1], {"url": {"file": "fix.patch"}})
+
+    def test_rewrite(self):
+        pkgbuild = 'source=("git+https://example.com/foo.git#tag=v1")\n'
+        pins = {
+            "foo": {
+                "commit_hash": "c0ffee",
+                "tag_hash": None,
+                "annotation": None,
+                "source": updvcspins.parse_source("git+https://example.com/foo.git#tag=v2")["url"],
+            }
+        }
+        out = updvcspins.rewrite(pkgbuild, ["git+https://example.com/foo.git#tag=v1"], pins)
+        self.assertEqual(out, 'source=(\n    "git+https://example.com/foo.git#commit=c0ffee"\n)\n')
+
+
+if __name__ == "__main__":
+    unittest.main()
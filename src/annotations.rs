@@ -0,0 +1,62 @@
+use std::path::Path;
+
+/// The severity of an annotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Notice,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn command(&self) -> &'static str {
+        match self {
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// Escape data of a workflow command, the message can span multiple lines
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a property of a workflow command, like data but `:` and `,` are reserved too
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// A GitHub Actions workflow command, eg. `::warning file=PKGBUILD::message`
+pub fn github(level: Level, file: Option<&Path>, message: &str) -> String {
+    let mut command = format!("::{}", level.command());
+    if let Some(file) = file {
+        command.push_str(" file=");
+        command.push_str(&escape_property(&file.to_string_lossy()));
+    }
+    format!("{}::{}", command, escape_data(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github() {
+        assert_eq!(
+            github(
+                Level::Warning,
+                Some(Path::new("foo,bar/PKGBUILD")),
+                "up: newer tag available: v2"
+            ),
+            "::warning file=foo%2Cbar/PKGBUILD::up: newer tag available: v2"
+        );
+        assert_eq!(
+            github(Level::Error, None, "Failed to verify\n100% sure"),
+            "::error::Failed to verify%0A100%25 sure"
+        );
+    }
+}
@@ -1,6 +1,9 @@
-// use clap::{ArgAction, Parser, Subcommand};
-use clap::{ArgAction, Parser};
+use crate::keyring;
+use crate::rekor;
+use crate::rewrite::{ArrayLayout, Quote};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 // use strum::VariantNames;
 
 #[derive(Debug, Parser)]
@@ -8,16 +11,411 @@ pub struct Args {
     /// Turn debugging information on
     #[arg(short, long, global = true, action(ArgAction::Count))]
     pub verbose: u8,
-    /// Path to PKGBUILD
-    #[arg(short, long, default_value = "PKGBUILD")]
+    /// Format of log messages on stderr, `json` prints one object per line
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Plain)]
+    pub log_format: LogFormat,
+    /// Exit with an error if this or a more severe condition occurred, `changes` includes outdated
+    /// pins found by `check`
+    #[arg(long, global = true, value_enum, value_name = "CONDITION", default_value_t = FailOn::Errors)]
+    pub fail_on: FailOn,
+    /// Print warnings, errors and outdated pins as annotations of a CI system, eg. `github` for
+    /// GitHub Actions
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with = "log_format"
+    )]
+    pub annotations: Option<Annotations>,
+    /// Set the log level of a module, eg. `git=trace`, on top of the level selected with -v
+    #[arg(long = "log-filter", value_name = "MODULE=LEVEL", global = true)]
+    pub log_filters: Vec<String>,
+    /// Path to PKGBUILD, use `-` to read from stdin and write to stdout
+    #[arg(short, long, global = true, default_value = "PKGBUILD")]
     pub pkgbuild: PathBuf,
+    /// Process each PKGBUILD listed in this file, one path per line, use `-` for stdin
+    /// (--interactive still asks for confirmation on the terminal)
+    #[arg(long, value_name = "FILE", global = true, conflicts_with = "pkgbuild")]
+    pub paths_from: Option<PathBuf>,
+    /// Process all PKGBUILDs found in this directory and its subdirectories
+    #[arg(short = 'r', long, value_name = "DIR", global = true, conflicts_with_all = ["pkgbuild", "paths_from"])]
+    pub recursive: Option<PathBuf>,
+    /// Limit how many directories deep --recursive searches, 0 only checks the directory itself
+    #[arg(long, value_name = "N", global = true, requires = "recursive")]
+    pub max_depth: Option<usize>,
+    /// Skip directories during --recursive, eg. `.git` or `**/trunk`, can be used multiple times
+    #[arg(
+        long = "exclude",
+        value_name = "GLOB",
+        global = true,
+        requires = "recursive"
+    )]
+    pub excludes: Vec<String>,
+    /// Directory the repositories are cloned into or already located in, eg. a folder of mirrors
+    /// shared by multiple packages (defaults to the folder of the PKGBUILD)
+    #[arg(short = 'C', long, visible_alias = "repos-dir", global = true)]
+    pub directory: Option<PathBuf>,
+    /// Read sources with `makepkg --printsrcinfo` instead of evaluating variables with bash
+    #[arg(long, global = true)]
+    pub srcinfo: bool,
+    /// Only regenerate .SRCINFO from the PKGBUILD as it is, without updating any pins
+    #[arg(long)]
+    pub write_srcinfo_only: bool,
+    /// Don't print the summary of the updated pins at the end of the run
+    #[arg(long)]
+    pub no_summary: bool,
     /// Attempt update but do not write to PKGBUILD
     #[arg(short = 'n', long)]
     pub dry_run: bool,
-    /// Write updated PKGBUILD to this path
-    #[arg(short, long)]
+    /// Write updated PKGBUILD (or .SRCINFO with --write-srcinfo-only) to this path, use `-` for stdout
+    #[arg(short, long, conflicts_with_all = ["paths_from", "recursive"])]
     pub output: Option<PathBuf>,
+    /// Print the changes to stderr, `compact` only lists changed values, eg. `_commit: 0123 → 4567`
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub diff: Option<DiffFormat>,
+    /// Show the changes and ask for confirmation before writing, in the format of --diff
+    #[arg(short, long)]
+    pub interactive: bool,
+    /// Assume yes for all confirmations
+    #[arg(short, long)]
+    pub yes: bool,
+    /// Never prompt or wait for input on a terminal, fail instead
+    #[arg(long, conflicts_with_all = ["interactive", "pick_tag"])]
+    pub no_confirm: bool,
+    /// Keep a copy of the previous file as PKGBUILD.orig
+    #[arg(long)]
+    pub backup: bool,
+    /// Fetch updates for repositories that have already been cloned
+    #[arg(long, global = true)]
+    pub fetch: bool,
+    /// Panic if anything attempts to access the network, eg. for reproducibility audits
+    #[arg(long, global = true)]
+    pub no_network: bool,
+    /// Resolve refs with `git ls-remote` instead of cloning, features that inspect the history are unavailable
+    #[arg(long, conflicts_with_all = [
+        "pick_tag", "vcs_pkgver", "describe_pkgver", "run_pkgver", "changelog", "sync_submodules",
+        "verify_signatures", "require_tags", "commit_overrides", "paths", "as_of", "osv",
+        "interactive",
+    ])]
+    pub ls_remote: bool,
+    /// Strategies to resolve refs of repositories on matching hosts, tried in order,
+    /// eg. `github.com=api,ls-remote` or `*.internal=clone`
+    #[arg(
+        long = "resolve-order",
+        value_name = "HOST=STRATEGY,...",
+        global = true
+    )]
+    pub resolve_orders: Vec<String>,
+    /// Clone repositories without blobs (`--filter=blob:none`), the mirrors can't be used as
+    /// SRCDEST by makepkg afterwards, so only use this with a --directory dedicated to updvcspins
+    #[arg(long, global = true)]
+    pub partial_clone: bool,
+    /// Reuse tags resolved by previous runs that are younger than this, eg. `10m`
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub cache_ttl: Option<Duration>,
+    /// Print the time spent in each phase at the end of the run (resolving includes fetching)
+    #[arg(long)]
+    pub timings: bool,
+    /// Number of repositories to resolve in parallel
+    #[arg(short, long, global = true, default_value = "4", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
+    /// Pin sources of submodules to the commits recorded in the pinned superproject
+    #[arg(long)]
+    pub sync_submodules: bool,
+    /// Rewrite source urls of repositories that permanently moved to their new location
+    #[arg(long)]
+    pub fix_redirects: bool,
+    /// Only update the pins of these repositories, by source filename
+    #[arg(long = "repo", value_name = "NAME")]
+    pub repos: Vec<String>,
+    /// Pin a repository to this commit instead of resolving its ref, eg. for an untagged fix
+    #[arg(long = "commit", value_name = "NAME=HASH")]
+    pub commit_overrides: Vec<String>,
+    /// Interactively pick the tag to pin from the tags in the repository, only tags with the
+    /// --tag-prefix of the repository are listed
+    #[arg(long)]
+    pub pick_tag: bool,
     /// Pin commits instead of tag object hashes
     #[arg(long)]
     pub pin_commit: bool,
+    /// Pin tag object hashes in sources while writing the commit hash to `_commit=` variables
+    #[arg(long, conflicts_with = "pin_commit")]
+    pub pin_both: bool,
+    /// Write a comment with the resolved tag and date above each pinned source, refreshed on every update
+    #[arg(long)]
+    pub source_comments: bool,
+    /// Layout of source arrays that are regenerated
+    #[arg(long, global = true, value_enum, value_name = "LAYOUT", default_value_t = ArrayLayout::Lines)]
+    pub array_layout: ArrayLayout,
+    /// Indent the entries of regenerated source arrays by this many spaces [default: 4, or aligned with the parenthesis for `wrapped`]
+    #[arg(long, global = true, value_name = "N")]
+    pub array_indent: Option<usize>,
+    /// Quotes for the entries of regenerated source arrays, entries with variables are always double quoted
+    #[arg(long, global = true, value_enum, value_name = "QUOTE", default_value_t = Quote::Double)]
+    pub array_quotes: Quote,
+    /// Set pkgver= to r<commit count>.g<short hash> of the pinned commit (for -git packages)
+    #[arg(long)]
+    pub vcs_pkgver: bool,
+    /// Set pkgver= based on `git describe --long --tags` of the pinned commit, eg. 1.2.3.r4.g0123abc
+    #[arg(long, conflicts_with = "vcs_pkgver")]
+    pub describe_pkgver: bool,
+    /// Strip this prefix from tag names to get the version, use `NAME=PREFIX` for a single repository
+    #[arg(long = "tag-prefix", value_name = "[NAME=]PREFIX", global = true)]
+    pub tag_prefixes: Vec<String>,
+    /// Pin branches to their newest commit that's not later than this time, eg. `2024-01-31T00:00:00Z`
+    #[arg(long, value_name = "TIME", value_parser = humantime::parse_rfc3339_weak)]
+    pub as_of: Option<SystemTime>,
+    /// Pin branches to the newest commit that touches this path instead of their tip, eg. for a
+    /// component of a monorepo, use `NAME=PATH` for a single repository
+    #[arg(long = "path", value_name = "[NAME=]PATH")]
+    pub paths: Vec<String>,
+    /// Set pkgver= by running the pkgver() function of the PKGBUILD against the pinned commits
+    #[arg(long, conflicts_with_all = ["vcs_pkgver", "describe_pkgver"])]
+    pub run_pkgver: bool,
+    /// Increment epoch= if the new pkgver compares lower than the current one, instead of failing
+    #[arg(long)]
+    pub bump_epoch: bool,
+    /// Print the upstream commits between the previous and the new pin
+    #[arg(long)]
+    pub changelog: bool,
+    /// Open the compare page of the forge for changed pins in the browser, or the release page of
+    /// the tag for new ones
+    #[arg(long)]
+    pub open: bool,
+    /// Append an entry to this file in the folder of the PKGBUILD for every update, eg. `CHANGELOG.md`
+    #[arg(long, value_name = "FILE")]
+    pub changelog_file: Option<PathBuf>,
+    /// Print the GitHub/GitLab release notes of the resolved tags, authenticated with GITHUB_TOKEN if set
+    #[arg(long)]
+    pub release_notes: bool,
+    /// Treat a host as a self-hosted `github` (Enterprise) or `gitlab` instance, optionally with
+    /// the base url of its API, eg. `git.corp=gitlab:https://git.corp/api/v4`
+    #[arg(long = "forge", value_name = "HOST=KIND[:URL]")]
+    pub forges: Vec<String>,
+    /// Read the API token for a forge host from this file
+    #[arg(long = "forge-token", value_name = "HOST=FILE")]
+    pub forge_tokens: Vec<String>,
+    /// Point tarballs generated by the forge (or attached to its releases) at the pinned tag and
+    /// update their checksums
+    #[arg(long)]
+    pub update_assets: bool,
+    /// Query OSV.dev for vulnerabilities fixed by the update or still present in the new pin
+    #[arg(long)]
+    pub osv: bool,
+    /// Run `makepkg --verifysource` on the updated PKGBUILD before writing it
+    #[arg(long, conflicts_with = "partial_clone")]
+    pub verify_sources: bool,
+    /// Run namcap on the updated PKGBUILD and list its warnings in the summary
+    #[arg(long)]
+    pub lint: bool,
+    /// Enforce the rules of this JSON policy file (allowed hosts, https, required signatures)
+    #[arg(long, value_name = "FILE")]
+    pub policy: Option<PathBuf>,
+    /// Require pinned tags to be `annotated` or `signed`, use `NAME=KIND` for a single repository
+    #[arg(long = "require-tag", value_name = "[NAME=]KIND")]
+    pub require_tags: Vec<String>,
+    /// Add `?signed` to the source of this pin, so makepkg verifies its signature
+    #[arg(long = "require-signed", value_name = "NAME")]
+    pub require_signed: Vec<String>,
+    /// Remove `?signed` from the source of this pin
+    #[arg(long = "unsigned", value_name = "NAME")]
+    pub unsigned: Vec<String>,
+    /// Verify the signatures of pinned tags and commits of `?signed` sources before writing them
+    #[arg(long)]
+    pub verify_signatures: bool,
+    /// Trust the ssh keys in this allowed signers file (see ssh-keygen(1)) for --verify-signatures
+    #[arg(long, value_name = "FILE", requires = "verify_signatures")]
+    pub allowed_signers: Option<PathBuf>,
+    /// Also trust signatures by valid keys of the archlinux-keyring (/etc/pacman.d/gnupg)
+    #[arg(long, requires = "verify_signatures")]
+    pub archlinux_keyring: bool,
+    /// Also trust signatures by valid keys of the gpg keyring in this directory, can be used multiple times
+    #[arg(
+        long = "trusted-keyring",
+        value_name = "DIR",
+        requires = "verify_signatures"
+    )]
+    pub trusted_keyrings: Vec<PathBuf>,
+    /// Download signing keys listed in validpgpkeys= that are missing locally into a temporary keyring
+    #[arg(long, requires = "verify_signatures")]
+    pub auto_key_retrieve: bool,
+    /// Add the key of a valid signature to validpgpkeys= if it's not listed yet, --interactive offers it instead
+    #[arg(long)]
+    pub add_signer_key: bool,
+    /// Keyserver to receive keys from with --auto-key-retrieve or `keys import`
+    #[arg(long, value_name = "URL", global = true, default_value = keyring::DEFAULT_KEYSERVER)]
+    pub keyserver: String,
+    /// Keyring of updvcspins, used by --verify-signatures if it exists
+    /// (defaults to ~/.local/share/updvcspins/gnupg)
+    #[arg(long, value_name = "DIR", global = true)]
+    pub keyring: Option<PathBuf>,
+    /// Only warn instead of failing if a previously pinned commit was removed by a force-push
+    #[arg(long)]
+    pub allow_rewritten_history: bool,
+    /// Write the resolved pins to vcspins.lock next to the PKGBUILD
+    #[arg(long)]
+    pub write_lock: bool,
+    /// Only verify that the PKGBUILD and the resolved pins still match vcspins.lock, this never
+    /// updates anything, use --write-lock to move the pins and the lockfile forward
+    #[arg(long, conflicts_with = "write_lock")]
+    pub locked: bool,
+    /// Append a JSON line for every written pin to this file, recording where it came from
+    #[arg(long, value_name = "FILE")]
+    pub provenance_log: Option<PathBuf>,
+    /// Sign a statement about every written pin with this ssh key and submit it to Rekor,
+    /// failed submissions are only warned about and recorded in --provenance-log
+    #[arg(long, value_name = "KEY")]
+    pub rekor_key: Option<PathBuf>,
+    /// Url of the Rekor transparency log
+    #[arg(long, value_name = "URL", default_value = rekor::DEFAULT_URL, requires = "rekor_key")]
+    pub rekor_url: String,
+    /// Command to run before resolving the pins, can be used multiple times
+    #[arg(long, value_name = "CMD")]
+    pub pre_resolve: Vec<String>,
+    /// Command to run after the PKGBUILD has been written, with the results in the environment, eg.
+    /// to commit the change
+    #[arg(long, value_name = "CMD")]
+    pub post_write: Vec<String>,
+    #[command(subcommand)]
+    pub subcommand: Option<SubCommand>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    /// A unified diff of the whole file
+    #[default]
+    Unified,
+    /// Only the changed values of variables and source entries
+    Compact,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Plain,
+    Json,
+}
+
+/// Conditions that make the run fail, each one includes the ones listed before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum FailOn {
+    /// Always exit successfully, eg. for informational reports
+    Never,
+    /// Errors that abort the run (default)
+    Errors,
+    /// Warnings that were logged
+    Warnings,
+    /// Pins that were updated or are outdated
+    Changes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Annotations {
+    Github,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SubCommand {
+    /// Find the release tags that correspond to sources pinned with #commit=
+    Lookup,
+    /// Resolve the pins and compare them with other known versions, without writing anything
+    Check(Check),
+    /// Manage the keyring used to verify signatures
+    #[command(subcommand)]
+    Keys(Keys),
+    /// Switch the sources between tag object and commit pinning, without changing the pinned version
+    Migrate(Migrate),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Migrate {
+    /// The pin style to switch to, `_tag`/`_commit` variables are renamed to match
+    #[arg(long, value_enum)]
+    pub to: PinStyle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PinStyle {
+    /// Pin the tag object with #tag=, so makepkg can verify the signature of the tag
+    Tag,
+    /// Pin the commit with #commit=
+    Commit,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Keys {
+    /// Import or refresh the keys listed in validpgpkeys= into the keyring of updvcspins
+    Import(KeysImport),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct KeysImport {
+    /// Only copy keys from the default gpg keyring, without contacting the keyserver
+    #[arg(long)]
+    pub local: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Check {
+    /// Compare with the version in the Arch Linux [core] and [extra] repositories
+    #[arg(long)]
+    pub arch: bool,
+    /// Compare with the version currently published in the AUR
+    #[arg(long)]
+    pub aur: bool,
+    /// Report if a newer version is known to repology
+    #[arg(long)]
+    pub repology: bool,
+    /// The name of the project on repology, by default it's looked up by pkgbase
+    #[arg(long, value_name = "NAME", requires = "repology")]
+    pub repology_project: Option<String>,
+    /// Also consider alpha, beta and rc tags as newer tags, unless the pin is one already
+    #[arg(long)]
+    pub prereleases: bool,
+    /// Ignore tags younger than this, eg. `2d`, to give upstream time to retract a release
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub min_age: Option<Duration>,
+    /// List every tag that was considered as newer tag and why it was rejected
+    #[arg(long)]
+    pub explain: bool,
+    /// Send a notification about new upstream tags to `desktop`, `matrix:HOMESERVER/ROOM` or `irc[s]://HOST/CHANNEL`
+    #[arg(long = "notify", value_name = "TARGET")]
+    pub notify: Vec<String>,
+    /// Read the access token for matrix notifications from this file
+    #[arg(long, value_name = "FILE")]
+    pub matrix_token: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_locked() {
+        // --locked only verifies, it can't be combined with updating the lockfile
+        assert!(Args::try_parse_from(["updvcspins", "--locked", "--write-lock"]).is_err());
+    }
+
+    #[test]
+    fn test_ls_remote() {
+        // the previous pin is looked up in the local clone for these
+        for flag in ["--osv", "--interactive", "--changelog"] {
+            assert!(Args::try_parse_from(["updvcspins", "--ls-remote", flag]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_partial_clone() {
+        assert!(Args::try_parse_from(["updvcspins", "--partial-clone"]).is_ok());
+        // makepkg can't use partial clones as SRCDEST
+        assert!(
+            Args::try_parse_from(["updvcspins", "--partial-clone", "--verify-sources"]).is_err()
+        );
+    }
 }
@@ -0,0 +1,278 @@
+use crate::errors::*;
+use crate::http;
+use crate::makepkg::{self, Backend};
+use crate::rewrite;
+use crate::source::{Input, Source};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tempfile::NamedTempFile;
+
+/// The checksum algorithms of makepkg, used for the `<alg>sums=` arrays
+pub const ALGORITHMS: &[&str] = &[
+    "ck", "md5", "sha1", "sha224", "sha256", "sha384", "sha512", "b2",
+];
+
+/// Checksum arrays by name, eg. `sha256sums`
+pub type Checksums = BTreeMap<String, Vec<String>>;
+
+/// The version in a tag name, without a leading `v`
+fn version(tag: &str) -> &str {
+    match tag.strip_prefix('v') {
+        Some(version) if version.starts_with(|c: char| c.is_ascii_digit()) => version,
+        _ => tag,
+    }
+}
+
+/// The url of a tarball the forge generates for a tag, or of an asset attached to its release,
+/// rewritten for a different tag
+///
+/// Returns `None` if the url doesn't belong to the repository or is not for a tag.
+pub fn archive_url(repo_url: &str, url: &str, new_tag: &str) -> Option<String> {
+    let repo_url = repo_url.strip_prefix("git+").unwrap_or(repo_url);
+    if !repo_url.starts_with("https://") && !repo_url.starts_with("http://") {
+        return None;
+    }
+    let repo_url = repo_url.trim_end_matches('/');
+    let repo_url = repo_url.strip_suffix(".git").unwrap_or(repo_url);
+    let path = url.strip_prefix(repo_url)?.strip_prefix('/')?;
+
+    // eg. `archive/refs/tags/v1.2.3.tar.gz` on GitHub
+    for prefix in ["archive/refs/tags/", "archive/"] {
+        let Some(file) = path.strip_prefix(prefix) else {
+            continue;
+        };
+        let (tag, extension) = [".tar.gz", ".tar.bz2", ".tar.xz", ".zip"]
+            .iter()
+            .find_map(|ext| Some((file.strip_suffix(ext)?, ext)))?;
+        if tag.contains('/') || tag == new_tag {
+            return None;
+        }
+        return Some(format!("{}/{}{}{}", repo_url, prefix, new_tag, extension));
+    }
+
+    // eg. `releases/download/v1.2.3/foo-1.2.3.tar.gz` on GitHub or
+    // `-/archive/v1.2.3/foo-v1.2.3.tar.gz` on GitLab
+    for prefix in ["releases/download/", "-/archive/"] {
+        let Some((tag, file)) = path.strip_prefix(prefix).and_then(|p| p.split_once('/')) else {
+            continue;
+        };
+        if tag == new_tag {
+            return None;
+        }
+        let file = if file.contains(tag) {
+            file.replace(tag, new_tag)
+        } else {
+            file.replace(version(tag), version(new_tag))
+        };
+        return Some(format!("{}/{}{}/{}", repo_url, prefix, new_tag, file));
+    }
+    None
+}
+
+/// Sources whose url changes with the pkgver, eg. `https://example.com/foo-$pkgver.tar.gz`
+///
+/// The PKGBUILD is evaluated again with the new pkgver, returned as (array, index, url).
+pub fn templated_urls(
+    pkgbuild: &[u8],
+    sources: &BTreeMap<String, Vec<Input>>,
+    pkgver: &str,
+) -> Result<Vec<(String, usize, String)>> {
+    let options = rewrite::Options {
+        pkgver: Some(pkgver.to_string()),
+        ..Default::default()
+    };
+    let mut bumped = Vec::new();
+    rewrite::rewrite(
+        pkgbuild,
+        &mut bumped,
+        &mut sources.clone(),
+        &BTreeMap::new(),
+        &options,
+    )?;
+
+    let variables = makepkg::Variables::dump(&bumped)?;
+    let mut urls = Vec::new();
+    for (array, inputs) in sources {
+        let values = variables.get(array).unwrap_or_default();
+        if values.len() != inputs.len() {
+            bail!(
+                "{}= has {} entries with pkgver {:?} instead of {}",
+                array,
+                values.len(),
+                pkgver,
+                inputs.len()
+            );
+        }
+        for (index, (input, value)) in inputs.iter().zip(values).enumerate() {
+            let Source::Url(old) = input.source() else {
+                continue;
+            };
+            let input = value.parse::<Input>()?;
+            let Source::Url(new) = input.source() else {
+                continue;
+            };
+            if old != new {
+                urls.push((array.clone(), index, new.clone()));
+            }
+        }
+    }
+    Ok(urls)
+}
+
+/// Check with a HEAD request that a url exists
+pub fn exists(url: &str) -> Result<()> {
+    let response = http::head(url)
+        .config()
+        .http_status_as_error(false)
+        .build()
+        .call()
+        .with_context(|| anyhow!("Failed to request {:?}", url))?;
+    let status = response.status();
+    // some servers don't support HEAD, the download is going to tell
+    if !status.is_success() && !matches!(status.as_u16(), 405 | 501) {
+        bail!("Source {:?} doesn't exist: {}", url, status);
+    }
+    Ok(())
+}
+
+/// The checksum arrays that belong to a source array, eg. `sha256sums_x86_64` for `source_x86_64`
+pub fn checksum_arrays(pkgbuild: &[u8], backend: &Backend, array: &str) -> Result<Checksums> {
+    let suffix = array.strip_prefix("source").unwrap_or_default();
+    let mut arrays = BTreeMap::new();
+    for alg in ALGORITHMS {
+        let name = format!("{}sums{}", alg, suffix);
+        let values = backend.list_variable(pkgbuild, &name)?;
+        if !values.is_empty() {
+            arrays.insert(name, values);
+        }
+    }
+    Ok(arrays)
+}
+
+/// Download a file into a temporary file
+pub fn download(url: &str) -> Result<NamedTempFile> {
+    info!("Downloading {:?}", url);
+    let mut response = http::get(url)
+        .call()
+        .with_context(|| anyhow!("Failed to download {:?}", url))?;
+    let mut file = NamedTempFile::new()?;
+    io::copy(&mut response.body_mut().as_reader(), &mut file)
+        .with_context(|| anyhow!("Failed to download {:?}", url))?;
+    Ok(file)
+}
+
+/// Calculate the checksum of a file the same way makepkg does, eg. `sha256` with sha256sum
+pub fn checksum(alg: &str, path: &Path) -> Result<String> {
+    let binary = match alg {
+        "ck" => "cksum".to_string(),
+        _ => format!("{}sum", alg),
+    };
+    let output = Command::new(&binary)
+        .stdin(File::open(path)?)
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| anyhow!("Failed to run {}", binary))?;
+    if !output.status.success() {
+        bail!("{} exited with error: {:?}", binary, output.status);
+    }
+    let output = String::from_utf8(output.stdout)?;
+    let sum = output
+        .split_whitespace()
+        .next()
+        .with_context(|| anyhow!("No checksum in output of {}", binary))?;
+    Ok(sum.to_string())
+}
+
+/// Update the checksums of the entry of a source array, entries that are `SKIP` stay unchanged
+pub fn update_checksums(arrays: &mut Checksums, index: usize, path: &Path) -> Result<()> {
+    for (name, values) in arrays {
+        let Some(value) = values.get_mut(index) else {
+            bail!("{}= has no entry for source number {}", name, index + 1);
+        };
+        if value == "SKIP" {
+            continue;
+        }
+        let alg = name.split_once("sums").map(|(alg, _)| alg).unwrap_or(name);
+        *value = checksum(alg, path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templated_urls() {
+        let pkgbuild = b"pkgver=1.2.3\nsource=(\"https://example.com/foo-$pkgver.tar.gz\"\n        \"https://example.com/static.txt\")\n";
+        let sources = BTreeMap::from([(
+            "source".to_string(),
+            [
+                "https://example.com/foo-1.2.3.tar.gz",
+                "https://example.com/static.txt",
+            ]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect(),
+        )]);
+        assert_eq!(
+            templated_urls(pkgbuild, &sources, "1.3.0").unwrap(),
+            vec![(
+                "source".to_string(),
+                0,
+                "https://example.com/foo-1.3.0.tar.gz".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_archive_url() {
+        let repo = "git+https://github.com/kpcyrd/updvcspins.git";
+        assert_eq!(
+            archive_url(
+                repo,
+                "https://github.com/kpcyrd/updvcspins/archive/refs/tags/v1.2.3.tar.gz",
+                "v1.3.0"
+            )
+            .as_deref(),
+            Some("https://github.com/kpcyrd/updvcspins/archive/refs/tags/v1.3.0.tar.gz")
+        );
+        assert_eq!(
+            archive_url(
+                repo,
+                "https://github.com/kpcyrd/updvcspins/releases/download/v1.2.3/updvcspins-1.2.3.tar.gz",
+                "v1.3.0"
+            )
+            .as_deref(),
+            Some("https://github.com/kpcyrd/updvcspins/releases/download/v1.3.0/updvcspins-1.3.0.tar.gz")
+        );
+        assert_eq!(
+            archive_url(
+                "git+https://gitlab.com/foo/bar.git",
+                "https://gitlab.com/foo/bar/-/archive/v2/bar-v2.tar.gz",
+                "v3"
+            )
+            .as_deref(),
+            Some("https://gitlab.com/foo/bar/-/archive/v3/bar-v3.tar.gz")
+        );
+        assert_eq!(
+            archive_url(
+                repo,
+                "https://github.com/kpcyrd/updvcspins/archive/refs/tags/v1.3.0.tar.gz",
+                "v1.3.0"
+            ),
+            None
+        );
+        assert_eq!(
+            archive_url(
+                repo,
+                "https://github.com/kpcyrd/other/archive/refs/tags/v1.2.3.tar.gz",
+                "v1.3.0"
+            ),
+            None
+        );
+    }
+}
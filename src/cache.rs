@@ -0,0 +1,176 @@
+use crate::errors::*;
+use crate::file;
+use crate::git::{self, GitSource};
+use crate::makepkg::{ResolvedPin, TagAnnotation};
+use crate::source::Source;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// Seconds since unix epoch
+    resolved_at: u64,
+    commit_hash: String,
+    tag_hash: Option<String>,
+    annotation: Option<TagAnnotation>,
+}
+
+/// Resolved pins from previous runs, keyed by repository url and tag
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: BTreeMap<String, Entry>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Only tags are cached, branches and HEAD are expected to move between runs
+fn key(source: &GitSource) -> Option<String> {
+    let tag = source.tag.as_ref()?;
+    let signed = if source.signed { "?signed" } else { "" };
+    Some(format!(
+        "{}#tag={}{}",
+        git::normalize_url(&source.url),
+        tag,
+        signed
+    ))
+}
+
+impl Cache {
+    /// `$XDG_CACHE_HOME/updvcspins/resolved.json`, or `~/.cache/updvcspins/resolved.json`
+    pub fn default_path() -> Result<PathBuf> {
+        let dir = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                let home = env::var_os("HOME").context("Failed to determine cache folder")?;
+                Path::new(&home).join(".cache")
+            }
+        };
+        Ok(dir.join("updvcspins").join("resolved.json"))
+    }
+
+    /// Load the cache, a missing or unreadable cache is treated as empty
+    pub fn load(path: PathBuf, ttl: Duration) -> Self {
+        let entries = match fs::read(&path) {
+            Ok(buf) => serde_json::from_slice(&buf).unwrap_or_else(|err| {
+                warn!("Ignoring corrupt cache {:?}: {:#}", path, err);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Cache { path, ttl, entries }
+    }
+
+    fn is_fresh(&self, entry: &Entry) -> bool {
+        now().saturating_sub(entry.resolved_at) < self.ttl.as_secs()
+    }
+
+    pub fn get(&self, source: &GitSource) -> Option<ResolvedPin> {
+        let key = key(source)?;
+        let entry = self.entries.get(&key).filter(|e| self.is_fresh(e))?;
+        debug!("Using cached resolution for {:?}: {:?}", key, entry);
+        Some(ResolvedPin {
+            commit_hash: entry.commit_hash.clone(),
+            tag_hash: entry.tag_hash.clone(),
+            annotation: entry.annotation.clone(),
+            source: Source::Git(source.clone()),
+        })
+    }
+
+    pub fn insert(&mut self, pin: &ResolvedPin) {
+        let Source::Git(source) = &pin.source else {
+            return;
+        };
+        let Some(key) = key(source) else {
+            return;
+        };
+        self.entries.insert(
+            key,
+            Entry {
+                resolved_at: now(),
+                commit_hash: pin.commit_hash.clone(),
+                tag_hash: pin.tag_hash.clone(),
+                annotation: pin.annotation.clone(),
+            },
+        );
+    }
+
+    /// Write the cache to disk, expired entries are dropped
+    pub fn save(mut self) -> Result<()> {
+        let ttl = self.ttl.as_secs();
+        let now = now();
+        self.entries
+            .retain(|_, e| now.saturating_sub(e.resolved_at) < ttl);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| anyhow!("Failed to create cache folder {:?}", parent))?;
+        }
+        let buf = serde_json::to_vec(&self.entries)?;
+        file::atomic_write(&self.path, &buf, false)
+            .with_context(|| anyhow!("Failed to write cache {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let source = "git+https://example.com/foo.git#tag=v1"
+            .parse::<GitSource>()
+            .unwrap();
+
+        let mut cache = Cache::load(path.clone(), Duration::from_secs(60));
+        assert_eq!(cache.get(&source), None);
+        cache.insert(&ResolvedPin {
+            commit_hash: "c0ffee".to_string(),
+            tag_hash: Some("7a9".to_string()),
+            annotation: None,
+            source: Source::Git(source.clone()),
+        });
+        cache.save().unwrap();
+
+        let cache = Cache::load(path.clone(), Duration::from_secs(60));
+        let other = "git+https://example.com/foo#tag=v1"
+            .parse::<GitSource>()
+            .unwrap();
+        let pin = cache.get(&other).unwrap();
+        assert_eq!(pin.commit_hash, "c0ffee");
+        assert_eq!(pin.source, Source::Git(other));
+        // the signature still needs to be verified
+        let signed = "git+https://example.com/foo.git#tag=v1?signed"
+            .parse::<GitSource>()
+            .unwrap();
+        assert_eq!(cache.get(&signed), None);
+
+        // branches are never cached
+        let mut cache = Cache::load(path.clone(), Duration::from_secs(60));
+        let branch = "git+https://example.com/foo.git#branch=v1"
+            .parse::<GitSource>()
+            .unwrap();
+        cache.insert(&ResolvedPin {
+            commit_hash: "c0ffee".to_string(),
+            tag_hash: None,
+            annotation: None,
+            source: Source::Git(branch.clone()),
+        });
+        assert_eq!(cache.get(&branch), None);
+
+        let cache = Cache::load(path, Duration::ZERO);
+        assert_eq!(cache.get(&source), None);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::errors::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+/// Commit summaries listed per pin, the rest is only counted
+const MAX_COMMITS: usize = 20;
+
+/// An update of one pin
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PinChange {
+    pub name: String,
+    /// The previous pin, eg. `v1.2.3 (0123456789)`, if it was pinned before
+    pub old: Option<String>,
+    pub new: String,
+    /// Summaries of the upstream commits between the previous and the new pin
+    pub commits: Vec<String>,
+}
+
+/// An entry of the changelog file of a package, written for every update
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Entry {
+    /// eg. `2024-01-31`
+    pub date: String,
+    pub old_version: String,
+    pub new_version: String,
+    pub pins: Vec<PinChange>,
+}
+
+impl Entry {
+    pub fn to_markdown(&self) -> String {
+        let mut out = if self.old_version == self.new_version {
+            format!("## {}: {}\n\n", self.date, self.new_version)
+        } else {
+            format!(
+                "## {}: {} → {}\n\n",
+                self.date, self.old_version, self.new_version
+            )
+        };
+        for pin in &self.pins {
+            match &pin.old {
+                Some(old) => out.push_str(&format!("- {}: {} → {}\n", pin.name, old, pin.new)),
+                None => out.push_str(&format!("- {}: {}\n", pin.name, pin.new)),
+            }
+            for summary in pin.commits.iter().take(MAX_COMMITS) {
+                out.push_str(&format!("  - {}\n", summary));
+            }
+            if pin.commits.len() > MAX_COMMITS {
+                out.push_str(&format!(
+                    "  - … and {} more\n",
+                    pin.commits.len() - MAX_COMMITS
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Append an entry to the changelog file, it's created if it doesn't exist yet
+pub fn append(path: &Path, entry: &Entry) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| anyhow!("Failed to open changelog {:?}", path))?;
+    // keep a blank line between entries
+    let separator = if file.metadata()?.len() > 0 { "\n" } else { "" };
+    write!(file, "{}{}", separator, entry.to_markdown())
+        .with_context(|| anyhow!("Failed to write changelog {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_markdown() {
+        let entry = Entry {
+            date: "2024-01-31".to_string(),
+            old_version: "1.2.3".to_string(),
+            new_version: "1.3.0".to_string(),
+            pins: vec![
+                PinChange {
+                    name: "foo".to_string(),
+                    old: Some("v1.2.3 (0123456789)".to_string()),
+                    new: "v1.3.0 (abcdef0123)".to_string(),
+                    commits: vec!["Release 1.3.0".to_string(), "Fix crash".to_string()],
+                },
+                PinChange {
+                    name: "bar".to_string(),
+                    old: None,
+                    new: "main (9876543210)".to_string(),
+                    commits: vec![],
+                },
+            ],
+        };
+        assert_eq!(
+            entry.to_markdown(),
+            "## 2024-01-31: 1.2.3 → 1.3.0
+
+- foo: v1.2.3 (0123456789) → v1.3.0 (abcdef0123)
+  - Release 1.3.0
+  - Fix crash
+- bar: main (9876543210)
+"
+        );
+    }
+}
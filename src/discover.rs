@@ -0,0 +1,112 @@
+use crate::errors::*;
+use git_repository::bstr::ByteSlice;
+use git_repository::glob::wildmatch::Mode;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings for finding PKGBUILDs in a directory tree
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// How many directories deep to search below the root, unlimited if `None`
+    pub max_depth: Option<usize>,
+    /// Directories to skip, patterns without a `/` match the directory name at any depth,
+    /// others match the path relative to the root, eg. `**/trunk`
+    pub excludes: Vec<String>,
+}
+
+impl Options {
+    fn is_excluded(&self, relative: &Path) -> bool {
+        let name = relative.file_name().unwrap_or_default();
+        self.excludes.iter().any(|pattern| {
+            let value = if pattern.contains('/') {
+                relative.as_os_str()
+            } else {
+                name
+            };
+            git_repository::glob::wildmatch(
+                pattern.as_bytes().as_bstr(),
+                value.as_encoded_bytes().as_bstr(),
+                Mode::NO_MATCH_SLASH_LITERAL,
+            )
+        })
+    }
+}
+
+/// Recursively find all PKGBUILDs below a folder, symlinked directories aren't followed
+pub fn find_pkgbuilds(root: &Path, options: &Options) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut queue = vec![(root.to_path_buf(), 0)];
+    while let Some((dir, depth)) = queue.pop() {
+        let entries =
+            fs::read_dir(&dir).with_context(|| anyhow!("Failed to read directory {:?}", dir))?;
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                if options.max_depth.is_some_and(|max| depth >= max) {
+                    continue;
+                }
+                if options.is_excluded(relative) {
+                    debug!("Skipping excluded directory: {:?}", path);
+                    continue;
+                }
+                queue.push((path, depth + 1));
+            } else if entry.file_name() == "PKGBUILD" && !file_type.is_symlink() {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_pkgbuilds() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in [
+            "PKGBUILD",
+            "foo/PKGBUILD",
+            "foo/src/foo/PKGBUILD",
+            "bar/trunk/PKGBUILD",
+            "bar/repos/extra/PKGBUILD",
+            ".git/PKGBUILD",
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let find = |max_depth, excludes: &[&str]| {
+            let options = Options {
+                max_depth,
+                excludes: excludes.iter().map(|s| s.to_string()).collect(),
+            };
+            find_pkgbuilds(dir.path(), &options)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.strip_prefix(dir.path()).unwrap().display().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(find(Some(0), &[]), ["PKGBUILD"]);
+        assert_eq!(find(Some(1), &[".git"]), ["PKGBUILD", "foo/PKGBUILD"]);
+        assert_eq!(
+            find(None, &[".git", "src", "**/trunk"]),
+            ["PKGBUILD", "bar/repos/extra/PKGBUILD", "foo/PKGBUILD"]
+        );
+        assert_eq!(
+            find(None, &["bar/*"]),
+            [
+                ".git/PKGBUILD",
+                "PKGBUILD",
+                "foo/PKGBUILD",
+                "foo/src/foo/PKGBUILD"
+            ]
+        );
+    }
+}
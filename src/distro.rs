@@ -0,0 +1,229 @@
+use crate::errors::*;
+use crate::http;
+use crate::vercmp::{pkg_vercmp, vercmp};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+const ARCH_SEARCH_URL: &str = "https://archlinux.org/packages/search/json/";
+const ARCH_REPOS: &[&str] = &["core", "extra"];
+const AUR_RPC_URL: &str = "https://aur.archlinux.org/rpc/v5/info";
+const REPOLOGY_PROJECT_URL: &str = "https://repology.org/api/v1/project/";
+const REPOLOGY_PROJECT_BY_URL: &str = "https://repology.org/tools/project-by";
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PackageVersion {
+    /// Where this version was found, eg. `extra`
+    pub repo: String,
+    pub epoch: u32,
+    pub pkgver: String,
+    pub pkgrel: String,
+}
+
+impl PackageVersion {
+    /// The full version without the repo, eg. `1:2.3-4`
+    pub fn version(&self) -> String {
+        if self.epoch > 0 {
+            format!("{}:{}-{}", self.epoch, self.pkgver, self.pkgrel)
+        } else {
+            format!("{}-{}", self.pkgver, self.pkgrel)
+        }
+    }
+
+    /// How a full version like `1:2.3-4` compares to this version
+    ///
+    /// The pkgrel is only compared if `version` has one.
+    pub fn compare(&self, version: &str) -> Ordering {
+        pkg_vercmp(version, &self.version())
+    }
+}
+
+impl FromStr for PackageVersion {
+    type Err = Error;
+
+    /// Parse a full version like `1:2.3-4`, the repo is left empty
+    fn from_str(s: &str) -> Result<Self> {
+        let (epoch, rest) = match s.split_once(':') {
+            Some((epoch, rest)) => {
+                let epoch = epoch
+                    .parse()
+                    .with_context(|| anyhow!("Invalid epoch in version: {:?}", s))?;
+                (epoch, rest)
+            }
+            None => (0, s),
+        };
+        let (pkgver, pkgrel) = rest
+            .rsplit_once('-')
+            .with_context(|| anyhow!("Version has no pkgrel: {:?}", s))?;
+        Ok(PackageVersion {
+            repo: String::new(),
+            epoch,
+            pkgver: pkgver.to_string(),
+            pkgrel: pkgrel.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for PackageVersion {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "[{}] {}", self.repo, self.version())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchSearch {
+    results: Vec<ArchPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchPackage {
+    pkgname: String,
+    pkgbase: String,
+    repo: String,
+    epoch: u32,
+    pkgver: String,
+    pkgrel: String,
+}
+
+/// Look up the versions of a pkgbase in the Arch Linux [core] and [extra] repositories
+pub fn arch_versions(pkgbase: &str) -> Result<Vec<PackageVersion>> {
+    debug!("Searching archlinux.org for {:?}", pkgbase);
+    let search: ArchSearch = http::get(ARCH_SEARCH_URL)
+        .query("name", pkgbase)
+        .call()
+        .context("Failed to query archlinux.org")?
+        .body_mut()
+        .read_json()
+        .context("Failed to decode response from archlinux.org")?;
+
+    let mut versions = Vec::<PackageVersion>::new();
+    for pkg in search.results {
+        if pkg.pkgname != pkgbase && pkg.pkgbase != pkgbase {
+            continue;
+        }
+        if !ARCH_REPOS.contains(&pkg.repo.as_str()) {
+            continue;
+        }
+        // the same package is listed once per architecture
+        if versions.iter().any(|v| v.repo == pkg.repo) {
+            continue;
+        }
+        versions.push(PackageVersion {
+            repo: pkg.repo,
+            epoch: pkg.epoch,
+            pkgver: pkg.pkgver,
+            pkgrel: pkg.pkgrel,
+        });
+    }
+    Ok(versions)
+}
+
+#[derive(Debug, Deserialize)]
+struct AurInfo {
+    results: Vec<AurPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AurPackage {
+    version: String,
+}
+
+/// Look up the version of a package in the AUR
+pub fn aur_version(pkgname: &str) -> Result<Option<PackageVersion>> {
+    debug!("Querying AUR RPC for {:?}", pkgname);
+    let info: AurInfo = http::get(AUR_RPC_URL)
+        .query("arg[]", pkgname)
+        .call()
+        .context("Failed to query the AUR")?
+        .body_mut()
+        .read_json()
+        .context("Failed to decode response from the AUR")?;
+
+    let Some(pkg) = info.results.into_iter().next() else {
+        return Ok(None);
+    };
+    let mut version = pkg.version.parse::<PackageVersion>()?;
+    version.repo = "aur".to_string();
+    Ok(Some(version))
+}
+
+#[derive(Debug, Deserialize)]
+struct RepologyPackage {
+    version: String,
+    status: Option<String>,
+}
+
+/// The newest version of a project according to repology
+///
+/// Repology project names often differ from the pkgbase, so unless a project is given it's looked
+/// up by the name of the source package in the Arch Linux repositories or the AUR.
+pub fn repology_newest(pkgbase: &str, project: Option<&str>) -> Result<Option<String>> {
+    let requests = match project {
+        Some(project) => {
+            debug!("Querying repology for project {:?}", project);
+            vec![http::get(&format!("{}{}", REPOLOGY_PROJECT_URL, project))]
+        }
+        None => ["arch", "aur"]
+            .into_iter()
+            .map(|repo| {
+                debug!("Querying repology for {:?} in {:?}", pkgbase, repo);
+                http::get(REPOLOGY_PROJECT_BY_URL)
+                    .query("repo", repo)
+                    .query("name_type", "srcname")
+                    .query("target_page", "api_v1_project")
+                    .query("name", pkgbase)
+            })
+            .collect(),
+    };
+
+    for request in requests {
+        let mut response = request
+            .config()
+            .http_status_as_error(false)
+            .build()
+            .call()
+            .context("Failed to query repology")?;
+        match response.status().as_u16() {
+            404 => continue,
+            status if status >= 400 => bail!("Failed to query repology: http status {}", status),
+            _ => (),
+        }
+        let packages: Vec<RepologyPackage> = response
+            .body_mut()
+            .read_json()
+            .context("Failed to decode response from repology")?;
+
+        let newest = packages
+            .into_iter()
+            .filter(|pkg| pkg.status.as_deref() == Some("newest"))
+            .map(|pkg| pkg.version)
+            .max_by(|a, b| vercmp(a, b));
+        return Ok(newest);
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let version = "1:2.3-4".parse::<PackageVersion>().unwrap();
+        assert_eq!(version.version(), "1:2.3-4");
+        assert_eq!(version.compare("1:2.3-4"), Ordering::Equal);
+        assert_eq!(version.compare("1:2.3-5"), Ordering::Greater);
+        assert_eq!(version.compare("1:2.3"), Ordering::Equal);
+        assert_eq!(version.compare("1:2.4-1"), Ordering::Greater);
+        // a newer pkgver doesn't make up for a lower epoch
+        assert_eq!(version.compare("2.4-1"), Ordering::Less);
+        assert_eq!(version.compare("2:1.0-1"), Ordering::Greater);
+
+        let version = "2.3-4".parse::<PackageVersion>().unwrap();
+        assert_eq!(version.version(), "2.3-4");
+        assert_eq!(version.compare("0:2.3-4"), Ordering::Equal);
+        assert_eq!(version.compare("2.3-3"), Ordering::Less);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::errors::*;
+use crate::makepkg::ResolvedPin;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// The phase reported by git, eg. `Receiving objects`
+    pub stage: String,
+    pub current: u64,
+    pub total: Option<u64>,
+}
+
+/// Receives events while pins are resolved and the PKGBUILD is updated.
+///
+/// All methods default to doing nothing, implementations only need to override the events they care about.
+/// Repositories may be resolved in parallel, so events can arrive from multiple threads.
+pub trait Observer: Sync {
+    fn clone_started(&self, _url: &str, _path: &Path) {}
+
+    fn fetch_started(&self, _path: &Path) {}
+
+    fn fetch_progress(&self, _path: &Path, _progress: &Progress) {}
+
+    fn fetch_finished(&self, _path: &Path) {}
+
+    /// The name is the tag or branch that was resolved, or `HEAD`
+    fn pin_resolved(&self, _name: &str, _pin: &ResolvedPin) {}
+
+    fn file_written(&self, _path: &Path) {}
+}
+
+#[derive(Debug, Default)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
+
+/// Reports events through the `log` crate
+#[derive(Debug, Default)]
+pub struct LogObserver;
+
+impl Observer for LogObserver {
+    fn clone_started(&self, url: &str, path: &Path) {
+        info!("Cloning {:?} into {:?}", url, path);
+    }
+
+    fn fetch_started(&self, path: &Path) {
+        info!("Fetching updates for {:?}", path);
+    }
+
+    fn fetch_progress(&self, path: &Path, progress: &Progress) {
+        if let Some(total) = progress.total {
+            trace!(
+                "{:?}: {}: {}/{}",
+                path,
+                progress.stage,
+                progress.current,
+                total
+            );
+        } else {
+            trace!("{:?}: {}: {}", path, progress.stage, progress.current);
+        }
+    }
+
+    fn fetch_finished(&self, path: &Path) {
+        debug!("Finished fetching {:?}", path);
+    }
+
+    fn pin_resolved(&self, name: &str, pin: &ResolvedPin) {
+        if let Some(tag_hash) = &pin.tag_hash {
+            info!(
+                "Resolved tag {:?} (tag hash: {:?}, commit hash: {:?})",
+                name, tag_hash, pin.commit_hash
+            );
+        } else {
+            info!("Resolved {:?} (commit hash: {:?})", name, pin.commit_hash);
+        }
+        if let Some(annotation) = &pin.annotation {
+            if let Some(tagger) = &annotation.tagger {
+                info!("Tagged by {}", tagger);
+            }
+            for line in annotation.message.trim_end().lines() {
+                info!("    {}", line);
+            }
+        }
+    }
+
+    fn file_written(&self, path: &Path) {
+        info!("Updated {:?}", path);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::errors::*;
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata, OpenOptions, Permissions, TryLockError};
+use std::io::Write;
+use std::os::unix;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_else(|| "PKGBUILD".as_ref()));
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+/// An exclusive advisory lock, the lock file is removed again when this is dropped
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+    file: File,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // remove the file while still holding the lock, anybody waiting for it notices and retries
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("Failed to remove lock file {:?}: {:#}", self.path, err);
+        }
+        debug!("Released lock {:?}", self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Take an exclusive advisory lock for a read-modify-write of the given file
+///
+/// A sidecar file is used because the file itself is replaced by `atomic_write`. The lock is
+/// released when the returned guard is dropped.
+pub fn lock(path: &Path) -> Result<Lock> {
+    let lock_path = lock_path(path);
+    loop {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| anyhow!("Failed to open lock file {:?}", lock_path))?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                info!("Waiting for other process to release lock {:?}", lock_path);
+                file.lock()
+                    .with_context(|| anyhow!("Failed to lock {:?}", lock_path))?;
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(Error::from(err).context(anyhow!("Failed to lock {:?}", lock_path)));
+            }
+        }
+
+        // the previous owner may have removed the file while we were waiting for it
+        let locked = file.metadata()?;
+        match fs::metadata(&lock_path) {
+            Ok(current) if (current.dev(), current.ino()) == (locked.dev(), locked.ino()) => {
+                debug!("Acquired lock {:?}", lock_path);
+                return Ok(Lock {
+                    path: lock_path,
+                    file,
+                });
+            }
+            Ok(_) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(Error::from(err).context(anyhow!("Failed to access {:?}", lock_path)));
+            }
+        }
+    }
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".orig");
+    PathBuf::from(backup)
+}
+
+fn restore_metadata(file: &File, original: &Metadata) -> Result<()> {
+    file.set_permissions(original.permissions())
+        .context("Failed to restore file permissions")?;
+
+    let current = file.metadata()?;
+    if (current.uid(), current.gid()) != (original.uid(), original.gid()) {
+        // this is only expected to succeed if we're running as root, eg. in a build chroot
+        if let Err(err) = unix::fs::fchown(file, Some(original.uid()), Some(original.gid())) {
+            warn!(
+                "Failed to restore file ownership ({}:{}): {:#}",
+                original.uid(),
+                original.gid(),
+                err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace the file at `path` without ever leaving a truncated file behind
+///
+/// The content is written to a temporary file in the same directory and renamed over the original.
+/// If `path` is a symlink, the file it points to is replaced and the symlink is kept.
+pub fn atomic_write(path: &Path, content: &[u8], backup: bool) -> Result<()> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(Error::from(err).context(anyhow!("Failed to access {:?}", path))),
+    };
+
+    if let Some(metadata) = &metadata {
+        if backup {
+            let backup = backup_path(path);
+            debug!("Creating backup at {:?}", backup);
+            fs::copy(path, &backup)
+                .with_context(|| anyhow!("Failed to create backup at {:?}", backup))?;
+        }
+
+        if !metadata.is_file() {
+            debug!("Target is not a regular file, writing directly: {:?}", path);
+            fs::write(path, content).with_context(|| anyhow!("Failed to write to {:?}", path))?;
+            return Ok(());
+        }
+    }
+
+    let target;
+    let path = if metadata.is_some() {
+        target =
+            fs::canonicalize(path).with_context(|| anyhow!("Failed to resolve path {:?}", path))?;
+        if target != path {
+            debug!("Writing to {:?} through {:?}", target, path);
+        }
+        target.as_path()
+    } else {
+        path
+    };
+    let folder = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // new files get the usual permissions after umask instead of the 0600 of temporary files
+    let mut tmp = tempfile::Builder::new()
+        .permissions(Permissions::from_mode(0o666))
+        .tempfile_in(folder)
+        .with_context(|| anyhow!("Failed to create temporary file in {:?}", folder))?;
+    if let Some(metadata) = &metadata {
+        restore_metadata(tmp.as_file(), metadata)?;
+    }
+    tmp.write_all(content)
+        .context("Failed to write to temporary file")?;
+    tmp.as_file()
+        .sync_all()
+        .context("Failed to sync temporary file")?;
+    tmp.persist(path)
+        .with_context(|| anyhow!("Failed to replace {:?}", path))?;
+
+    // make sure the rename itself is persisted
+    File::open(folder)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| anyhow!("Failed to sync directory {:?}", folder))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atomic_write_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PKGBUILD");
+        atomic_write(&path, b"new file\n", true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new file\n");
+        assert!(!backup_path(&path).exists());
+
+        atomic_write(&path, b"updated\n", true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"updated\n");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"new file\n");
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_atomic_write_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("pkgs")).unwrap();
+        let target = dir.path().join("pkgs/PKGBUILD");
+        fs::write(&target, b"old\n").unwrap();
+        let link = dir.path().join("PKGBUILD");
+        unix::fs::symlink("pkgs/PKGBUILD", &link).unwrap();
+
+        atomic_write(&link, b"new\n", false).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"new\n");
+        assert_eq!(fs::read_dir(dir.path().join("pkgs")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_atomic_write_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PKGBUILD");
+        fs::write(&path, b"old\n").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+
+        atomic_write(&path, b"new\n", false).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+    }
+
+    #[test]
+    fn test_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PKGBUILD");
+        let guard = lock(&path).unwrap();
+        assert!(lock_path(&path).exists());
+        assert!(matches!(
+            File::open(lock_path(&path)).unwrap().try_lock(),
+            Err(TryLockError::WouldBlock)
+        ));
+        drop(guard);
+        assert!(!lock_path(&path).exists());
+        drop(lock(&path).unwrap());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
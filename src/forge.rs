@@ -0,0 +1,542 @@
+use crate::errors::*;
+use crate::git::{self, GitSource};
+use crate::http;
+use crate::makepkg::{ResolvedPin, TagAnnotation};
+use crate::source::Source;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ureq::http::HeaderMap;
+use ureq::typestate::WithoutBody;
+use ureq::RequestBuilder;
+use url::Url;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Kind {
+    GitHub,
+    GitLab,
+}
+
+impl FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "github" => Ok(Kind::GitHub),
+            "gitlab" => Ok(Kind::GitLab),
+            _ => bail!("Unknown forge, expected github or gitlab: {:?}", s),
+        }
+    }
+}
+
+/// A self-hosted forge, eg. GitHub Enterprise or a GitLab instance
+#[derive(Debug, Default, Clone)]
+pub struct Host {
+    pub kind: Option<Kind>,
+    /// Base url of the API, defaults to `/api/v3` (GitHub) or `/api/v4` (GitLab) on the host
+    pub api_url: Option<String>,
+    pub token: Option<String>,
+}
+
+/// Forge settings by hostname
+pub type Hosts = BTreeMap<String, Host>;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Forge {
+    pub kind: Kind,
+    /// Base url of the API, eg. `https://api.github.com`
+    pub api_url: String,
+    /// Base url of the web interface, eg. `https://github.com`
+    pub web_url: String,
+    /// `owner/repo` on GitHub, the path of the project on GitLab
+    pub project: String,
+    pub token: Option<String>,
+}
+
+impl Forge {
+    /// Detect the forge from the url of a git remote
+    pub fn detect(remote_url: &str, hosts: &Hosts) -> Option<Forge> {
+        let url;
+        let (host, path, web_url) = if let Some((host, path)) = git::split_scp_url(remote_url) {
+            let host = host.rsplit('@').next()?;
+            (host, path, format!("https://{}", host))
+        } else {
+            url = Url::parse(remote_url).ok()?;
+            let host = url.host_str()?;
+            // the web interface is served with https for ssh:// and git:// remotes
+            let web_url = match (url.scheme(), url.port()) {
+                ("http", Some(port)) => format!("http://{}:{}", host, port),
+                ("http", None) => format!("http://{}", host),
+                ("https", Some(port)) => format!("https://{}:{}", host, port),
+                _ => format!("https://{}", host),
+            };
+            (host, url.path(), web_url)
+        };
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let configured = hosts.get(host);
+
+        let kind = if let Some(kind) = configured.and_then(|h| h.kind) {
+            kind
+        } else if host == "github.com" {
+            Kind::GitHub
+        } else if host == "gitlab.com" || host.starts_with("gitlab.") {
+            Kind::GitLab
+        } else {
+            return None;
+        };
+        if kind == Kind::GitHub && !path.contains('/') {
+            return None;
+        }
+
+        let api_url = match (configured.and_then(|h| h.api_url.as_ref()), kind) {
+            (Some(api_url), _) => api_url.trim_end_matches('/').to_string(),
+            (None, Kind::GitHub) if host == "github.com" => "https://api.github.com".to_string(),
+            (None, Kind::GitHub) => format!("https://{}/api/v3", host),
+            (None, Kind::GitLab) => format!("https://{}/api/v4", host),
+        };
+        Some(Forge {
+            kind,
+            api_url,
+            web_url,
+            project: path.to_string(),
+            token: configured
+                .and_then(|h| h.token.clone())
+                .or_else(|| (host == "github.com").then(|| env::var("GITHUB_TOKEN").ok())?),
+        })
+    }
+
+    /// The page of the forge that compares two revisions, eg. the commits of two pins
+    pub fn compare_url(&self, old: &str, new: &str) -> String {
+        match self.kind {
+            Kind::GitHub => format!(
+                "{}/{}/compare/{}...{}",
+                self.web_url, self.project, old, new
+            ),
+            Kind::GitLab => format!(
+                "{}/{}/-/compare/{}...{}",
+                self.web_url, self.project, old, new
+            ),
+        }
+    }
+
+    /// The page of the release of a tag
+    pub fn release_page(&self, tag: &str) -> String {
+        match self.kind {
+            Kind::GitHub => format!(
+                "{}/{}/releases/tag/{}",
+                self.web_url,
+                self.project,
+                self.encode_ref(tag)
+            ),
+            Kind::GitLab => format!(
+                "{}/{}/-/releases/{}",
+                self.web_url,
+                self.project,
+                self.encode_ref(tag)
+            ),
+        }
+    }
+
+    fn encode(value: &str) -> String {
+        url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+    }
+
+    /// Encode the name of a tag or branch for use in a url
+    ///
+    /// GitHub expects the slashes of a ref name as they are, GitLab wants them encoded as well.
+    fn encode_ref(&self, name: &str) -> String {
+        match self.kind {
+            Kind::GitHub => name
+                .split('/')
+                .map(Self::encode)
+                .collect::<Vec<_>>()
+                .join("/"),
+            Kind::GitLab => Self::encode(name),
+        }
+    }
+
+    fn release_url(&self, tag: &str) -> String {
+        match self.kind {
+            Kind::GitHub => format!(
+                "{}/repos/{}/releases/tags/{}",
+                self.api_url,
+                self.project,
+                self.encode_ref(tag)
+            ),
+            Kind::GitLab => format!(
+                "{}/projects/{}/releases/{}",
+                self.api_url,
+                Self::encode(&self.project),
+                self.encode_ref(tag)
+            ),
+        }
+    }
+
+    fn request(&self, url: &str) -> RequestBuilder<WithoutBody> {
+        let request = http::get(url).config().http_status_as_error(false).build();
+        match (&self.token, self.kind) {
+            (Some(token), Kind::GitHub) => {
+                request.header("Authorization", format!("Bearer {}", token))
+            }
+            (Some(token), Kind::GitLab) => request.header("PRIVATE-TOKEN", token),
+            (None, _) => request,
+        }
+    }
+
+    /// Request an API endpoint, waiting for the rate limit if needed, `None` if not found
+    fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<Option<T>> {
+        let mut attempt = 0;
+        let mut response = loop {
+            throttle(&self.api_url);
+            debug!("Sending request to {:?}", url);
+            let response = self
+                .request(url)
+                .call()
+                .with_context(|| anyhow!("Failed to request {:?}", url))?;
+            let limit = RateLimit::from_headers(response.headers());
+            if let Some(limit) = limit {
+                debug!("Rate limit of {:?}: {:?}", self.api_url, limit);
+                RATE_LIMITS
+                    .lock()
+                    .unwrap()
+                    .insert(self.api_url.clone(), limit);
+            }
+            let status = response.status().as_u16();
+            let limited = limit.is_some_and(|limit| limit.remaining == 0);
+            if matches!(status, 403 | 429) && limited && attempt < MAX_RETRIES {
+                attempt += 1;
+                continue;
+            }
+            break response;
+        };
+        match response.status().as_u16() {
+            404 => return Ok(None),
+            status if status >= 400 => bail!("Request to {:?} failed: http status {}", url, status),
+            _ => (),
+        }
+        let value = response
+            .body_mut()
+            .read_json()
+            .with_context(|| anyhow!("Failed to decode response of {:?}", url))?;
+        Ok(Some(value))
+    }
+
+    /// Fetch the release notes of the release for a tag, if there is one
+    pub fn release_notes(&self, tag: &str) -> Result<Option<String>> {
+        let release = self
+            .get_json::<Release>(&self.release_url(tag))
+            .context("Failed to fetch release notes")?;
+        Ok(release.and_then(|release| release.body.or(release.description)))
+    }
+
+    /// Resolve the tag or branch of a source with the API of the forge, without git
+    pub fn resolve(&self, source: GitSource) -> Result<ResolvedPin> {
+        let (tag_hash, annotation, commit_hash) = match (&source.tag, &source.branch) {
+            (Some(tag), _) => self.resolve_tag(tag)?,
+            (None, Some(branch)) => (None, None, self.resolve_branch(branch)?),
+            (None, None) => bail!("Only tags and branches can be resolved with the forge API"),
+        };
+        Ok(ResolvedPin {
+            commit_hash,
+            tag_hash,
+            annotation,
+            source: Source::Git(source),
+        })
+    }
+
+    fn resolve_tag(&self, tag: &str) -> Result<(Option<String>, Option<TagAnnotation>, String)> {
+        match self.kind {
+            Kind::GitHub => {
+                let url = format!(
+                    "{}/repos/{}/git/ref/tags/{}",
+                    self.api_url,
+                    self.project,
+                    self.encode_ref(tag)
+                );
+                let reference = self
+                    .get_json::<GitHubRef>(&url)?
+                    .with_context(|| anyhow!("Tag not found: {:?}", tag))?;
+                let tag_hash = reference.object.sha.clone();
+                let mut object = reference.object;
+                let mut annotation = None;
+                // tags of tags are peeled until the commit
+                for _ in 0..MAX_PEEL {
+                    if object.kind != "tag" {
+                        break;
+                    }
+                    let url = format!(
+                        "{}/repos/{}/git/tags/{}",
+                        self.api_url, self.project, object.sha
+                    );
+                    let tag = self
+                        .get_json::<GitHubTag>(&url)?
+                        .with_context(|| anyhow!("Tag object not found: {:?}", object.sha))?;
+                    annotation.get_or_insert(TagAnnotation {
+                        tagger: tag.tagger.map(|t| format!("{} <{}>", t.name, t.email)),
+                        message: tag.message,
+                        signed: tag.verification.is_some_and(|v| v.signature.is_some()),
+                    });
+                    object = tag.object;
+                }
+                if object.kind != "commit" {
+                    bail!("Tag {:?} doesn't point to a commit: {:?}", tag, object.kind);
+                }
+                Ok((Some(tag_hash), annotation, object.sha))
+            }
+            Kind::GitLab => {
+                let url = format!(
+                    "{}/projects/{}/repository/tags/{}",
+                    self.api_url,
+                    Self::encode(&self.project),
+                    self.encode_ref(tag)
+                );
+                let tag = self
+                    .get_json::<GitLabTag>(&url)?
+                    .with_context(|| anyhow!("Tag not found: {:?}", tag))?;
+                Ok((Some(tag.target), None, tag.commit.id))
+            }
+        }
+    }
+
+    fn resolve_branch(&self, branch: &str) -> Result<String> {
+        let url = match self.kind {
+            Kind::GitHub => format!(
+                "{}/repos/{}/git/ref/heads/{}",
+                self.api_url,
+                self.project,
+                self.encode_ref(branch)
+            ),
+            Kind::GitLab => format!(
+                "{}/projects/{}/repository/branches/{}",
+                self.api_url,
+                Self::encode(&self.project),
+                self.encode_ref(branch)
+            ),
+        };
+        let not_found = || anyhow!("Branch not found: {:?}", branch);
+        match self.kind {
+            Kind::GitHub => Ok(self
+                .get_json::<GitHubRef>(&url)?
+                .ok_or_else(not_found)?
+                .object
+                .sha),
+            Kind::GitLab => Ok(self
+                .get_json::<GitLabBranch>(&url)?
+                .ok_or_else(not_found)?
+                .commit
+                .id),
+        }
+    }
+}
+
+/// How many tag objects are followed to find the commit of a tag
+const MAX_PEEL: usize = 8;
+
+/// How often a request is retried after waiting for the rate limit to reset
+const MAX_RETRIES: usize = 3;
+
+/// The rate limits of the APIs in use, by base url, as reported by the last response
+static RATE_LIMITS: Mutex<BTreeMap<String, RateLimit>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct RateLimit {
+    remaining: u64,
+    /// Seconds since unix epoch when more requests are available
+    reset: u64,
+}
+
+impl RateLimit {
+    /// Read the `x-ratelimit-*` headers of GitHub (`ratelimit-*` on GitLab), or `retry-after`
+    /// for secondary rate limits
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<u64>().ok();
+        if let Some(retry_after) = header("retry-after") {
+            return Some(RateLimit {
+                remaining: 0,
+                reset: now() + retry_after,
+            });
+        }
+        Some(RateLimit {
+            remaining: header("x-ratelimit-remaining").or_else(|| header("ratelimit-remaining"))?,
+            reset: header("x-ratelimit-reset").or_else(|| header("ratelimit-reset"))?,
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Wait for the rate limit of an API to reset if no requests are remaining
+fn throttle(api_url: &str) {
+    let Some(limit) = RATE_LIMITS.lock().unwrap().get(api_url).copied() else {
+        return;
+    };
+    if limit.remaining > 0 {
+        return;
+    }
+    let wait = limit.reset.saturating_sub(now());
+    if wait > 0 {
+        warn!(
+            "Rate limit of {:?} exhausted, waiting {}s for it to reset",
+            api_url, wait
+        );
+        thread::sleep(Duration::from_secs(wait + 1));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubObject {
+    sha: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubRef {
+    object: GitHubObject,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubTagger {
+    name: String,
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubVerification {
+    signature: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubTag {
+    object: GitHubObject,
+    tagger: Option<GitHubTagger>,
+    message: String,
+    verification: Option<GitHubVerification>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabCommit {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabTag {
+    /// The tag object for annotated tags, the commit for lightweight tags
+    target: String,
+    commit: GitLabCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabBranch {
+    commit: GitLabCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    /// Used by GitHub
+    body: Option<String>,
+    /// Used by GitLab
+    description: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let hosts = Hosts::from([
+            (
+                "git.corp".to_string(),
+                Host {
+                    kind: Some(Kind::GitHub),
+                    api_url: None,
+                    token: Some("secret".to_string()),
+                },
+            ),
+            (
+                "code.corp".to_string(),
+                Host {
+                    kind: Some(Kind::GitLab),
+                    api_url: Some("https://code.corp/gitlab/api/v4/".to_string()),
+                    token: None,
+                },
+            ),
+        ]);
+
+        let forge = Forge::detect("https://github.com/kpcyrd/updvcspins.git", &hosts).unwrap();
+        assert_eq!(
+            forge.release_url("v1"),
+            "https://api.github.com/repos/kpcyrd/updvcspins/releases/tags/v1"
+        );
+        let forge = Forge::detect("https://git.corp/foo/bar", &hosts).unwrap();
+        assert_eq!(
+            forge.release_url("v1"),
+            "https://git.corp/api/v3/repos/foo/bar/releases/tags/v1"
+        );
+        assert_eq!(forge.token.as_deref(), Some("secret"));
+        assert_eq!(
+            forge.compare_url("v1", "v2"),
+            "https://git.corp/foo/bar/compare/v1...v2"
+        );
+        let forge = Forge::detect("git@code.corp:group/sub/bar.git", &hosts).unwrap();
+        assert_eq!(
+            forge.release_url("v1"),
+            "https://code.corp/gitlab/api/v4/projects/group%2Fsub%2Fbar/releases/v1"
+        );
+        assert_eq!(
+            forge.compare_url("v1", "v2"),
+            "https://code.corp/group/sub/bar/-/compare/v1...v2"
+        );
+        assert_eq!(
+            forge.release_page("v2"),
+            "https://code.corp/group/sub/bar/-/releases/v2"
+        );
+        assert_eq!(Forge::detect("https://example.com/foo/bar", &hosts), None);
+    }
+
+    #[test]
+    fn test_encode_ref() {
+        let hosts = Hosts::new();
+        let forge = Forge::detect("https://github.com/foo/bar", &hosts).unwrap();
+        assert_eq!(
+            forge.release_url("release/v1.0+build#2"),
+            "https://api.github.com/repos/foo/bar/releases/tags/release/v1.0%2Bbuild%232"
+        );
+        assert_eq!(
+            forge.release_page("v1.0?"),
+            "https://github.com/foo/bar/releases/tag/v1.0%3F"
+        );
+        let forge = Forge::detect("https://gitlab.com/foo/bar", &hosts).unwrap();
+        assert_eq!(
+            forge.release_url("release/v1.0+build#2"),
+            "https://gitlab.com/api/v4/projects/foo%2Fbar/releases/release%2Fv1.0%2Bbuild%232"
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(RateLimit::from_headers(&headers), None);
+        headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset", "1700000000".parse().unwrap());
+        assert_eq!(
+            RateLimit::from_headers(&headers),
+            Some(RateLimit {
+                remaining: 0,
+                reset: 1700000000,
+            })
+        );
+    }
+}
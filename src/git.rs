@@ -1,21 +1,151 @@
 use crate::errors::*;
-use crate::makepkg::{ResolvedPin, Source};
+use crate::events::{Observer, Progress};
+use crate::http;
+use crate::makepkg::{ResolvedPin, TagAnnotation};
+use crate::network;
+use crate::source::{Source, SourceString};
+use git_repository::commit::describe::SelectRef;
+use git_repository::{objs, Commit, ObjectId, Repository};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fmt;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct GitSource {
     pub url: String,
     pub commit: Option<String>,
     pub tag: Option<String>,
+    pub branch: Option<String>,
     pub signed: bool,
+    #[serde(skip)]
+    query_first: bool,
+}
+
+impl GitSource {
+    /// The url git should use, without the `git+` prefix used by makepkg
+    pub fn remote_url(&self) -> &str {
+        self.url.strip_prefix("git+").unwrap_or(&self.url)
+    }
+
+    pub fn builder<I: Into<String>>(url: I) -> GitSourceBuilder {
+        GitSourceBuilder {
+            url: url.into(),
+            commit: None,
+            tag: None,
+            branch: None,
+            signed: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GitSourceBuilder {
+    url: String,
+    commit: Option<String>,
+    tag: Option<String>,
+    branch: Option<String>,
+    signed: bool,
+}
+
+impl GitSourceBuilder {
+    pub fn commit<I: Into<String>>(mut self, commit: I) -> Self {
+        self.commit = Some(commit.into());
+        self
+    }
+
+    pub fn tag<I: Into<String>>(mut self, tag: I) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    pub fn branch<I: Into<String>>(mut self, branch: I) -> Self {
+        self.branch = Some(branch.into());
+        self
+    }
+
+    pub fn signed(mut self, signed: bool) -> Self {
+        self.signed = signed;
+        self
+    }
+
+    pub fn build(self) -> Result<GitSource> {
+        let refs = [&self.commit, &self.tag, &self.branch];
+        if refs.iter().filter(|x| x.is_some()).count() > 1 {
+            bail!("A git source can only reference one of commit, tag or branch");
+        }
+        for value in refs.into_iter().flatten() {
+            check_ref(value)?;
+        }
+
+        if self.url.contains(['#', '?']) {
+            bail!(
+                "Url must not contain fragments or query strings: {:?}",
+                self.url
+            );
+        }
+        let url = self
+            .url
+            .parse::<Url>()
+            .with_context(|| anyhow!("Failed to parse url: {:?}", self.url))?;
+        if !url.scheme().starts_with("git") {
+            bail!("Url is not a git url: {:?}", self.url);
+        }
+        let path = url.path();
+        let name = path.strip_suffix('/').unwrap_or(path).rsplit('/').next();
+        if name.is_none_or(str::is_empty) {
+            bail!("Url has no repository name: {:?}", self.url);
+        }
+
+        Ok(GitSource {
+            url: self.url,
+            commit: self.commit,
+            tag: self.tag,
+            branch: self.branch,
+            signed: self.signed,
+            query_first: false,
+        })
+    }
+}
+
+/// Check that a commit, tag or branch can be written as fragment of a makepkg source
+///
+/// makepkg takes the value after the last `=` and ends the fragment at the last `?`, so these
+/// can't be represented. A `#` is fine, the fragment starts at the first one.
+fn check_ref(value: &str) -> Result<()> {
+    if value.is_empty() {
+        bail!("Commits, tags and branches can't be empty");
+    }
+    if value.contains('=') {
+        bail!(
+            "Ref {:?} contains `=`, makepkg would only use the part after the last `=`",
+            value
+        );
+    }
+    // the same rules as git check-ref-format, `?` is also a query string for makepkg
+    if value.contains(|c: char| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+        || value.contains("..")
+        || value.contains("@{")
+        || value.contains("//")
+        || value.starts_with(['-', '/', '.'])
+        || value.ends_with(['/', '.'])
+        || value.ends_with(".lock")
+    {
+        bail!("Ref {:?} is not a valid git ref name", value);
+    }
+    Ok(())
 }
 
 impl fmt::Display for GitSource {
     fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
         write!(w, "{}", self.url)?;
-        if self.signed {
+        if self.signed && self.query_first {
             write!(w, "?signed")?;
         }
         if let Some(commit) = &self.commit {
@@ -24,79 +154,1589 @@ impl fmt::Display for GitSource {
         if let Some(tag) = &self.tag {
             write!(w, "#tag={}", tag)?;
         }
+        if let Some(branch) = &self.branch {
+            write!(w, "#branch={}", branch)?;
+        }
+        if self.signed && !self.query_first {
+            write!(w, "?signed")?;
+        }
         Ok(())
     }
 }
 
-impl FromStr for GitSource {
-    type Err = Error;
+impl TryFrom<SourceString> for GitSource {
+    type Error = Error;
 
-    fn from_str(mut s: &str) -> Result<Self> {
-        let mut signed = false;
+    fn try_from(s: SourceString) -> Result<Self> {
         let mut commit = None;
         let mut tag = None;
+        let mut branch = None;
 
-        if let Some(remaining) = s.strip_suffix("?signed") {
-            signed = true;
-            s = remaining;
+        if let Some(fragment) = &s.fragment {
+            let (key, value) = fragment
+                .split_once('=')
+                .with_context(|| anyhow!("Invalid fragment in git source: {:?}", fragment))?;
+            check_ref(value)
+                .with_context(|| anyhow!("Invalid fragment in git source: {:?}", fragment))?;
+            let value = Some(value.to_string());
+            match key {
+                "commit" => commit = value,
+                "tag" => tag = value,
+                "branch" => branch = value,
+                _ => bail!("Unsupported fragment in git source: {:?}", fragment),
+            }
         }
 
-        if let Some((remaining, value)) = s.rsplit_once("#commit=") {
-            commit = Some(value.to_string());
-            s = remaining;
-        }
-
-        if let Some((remaining, value)) = s.rsplit_once("#tag=") {
-            tag = Some(value.to_string());
-            s = remaining;
-        }
-
-        if let Some(remaining) = s.strip_suffix("?signed") {
-            signed = true;
-            s = remaining;
-        }
+        let signed = match s.query.as_deref() {
+            Some("signed") => true,
+            Some(query) => bail!("Unsupported query in git source: {:?}", query),
+            None => false,
+        };
 
         Ok(Self {
-            url: s.to_string(),
+            url: s.url,
             commit,
             tag,
+            branch,
             signed,
+            query_first: s.query_first,
         })
     }
 }
 
-pub fn run(source: GitSource, repo_path: &Path) -> Result<ResolvedPin> {
+impl FromStr for GitSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<SourceString>()?.try_into()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TagInfo {
+    pub name: String,
+    pub tag_hash: String,
+    pub commit_hash: Option<String>,
+    /// Seconds since unix epoch, from the tagger or the tagged commit
+    pub time: Option<u32>,
+    pub message: Option<String>,
+}
+
+impl TagInfo {
+    pub fn summary(&self) -> Option<&str> {
+        let message = self.message.as_ref()?;
+        message.lines().map(str::trim).find(|l| !l.is_empty())
+    }
+}
+
+/// List all tags of the repository, newest first
+pub fn list_tags(repo_path: &Path) -> Result<Vec<TagInfo>> {
+    let repo = git_repository::open(repo_path).context("Failed to open repository")?;
+    let references = repo.references().context("Failed to read references")?;
+
+    let mut tags = Vec::new();
+    for tag in references.tags().context("Failed to list tags")? {
+        let tag = tag.map_err(|err| anyhow!("Failed to read tag: {:#}", err))?;
+        let name = tag.name().as_bstr().to_string();
+        let name = name.strip_prefix("refs/tags/").unwrap_or(&name).to_string();
+        let Some(id) = tag.inner.target.try_id() else {
+            debug!("Skipping symbolic tag: {:?}", name);
+            continue;
+        };
+
+        let object = repo
+            .find_object(id)
+            .with_context(|| anyhow!("Failed to read object for tag {:?}", name))?;
+        let info = match object.kind {
+            objs::Kind::Tag => {
+                let tag = object
+                    .try_to_tag_ref()
+                    .with_context(|| anyhow!("Failed to decode tag {:?}", name))?;
+                TagInfo {
+                    name,
+                    tag_hash: id.to_string(),
+                    commit_hash: Some(tag.target().to_string()),
+                    time: tag.tagger.map(|t| t.time.seconds_since_unix_epoch),
+                    message: Some(tag.message.to_string()),
+                }
+            }
+            objs::Kind::Commit => {
+                let time = object.into_commit().time().ok();
+                TagInfo {
+                    name,
+                    tag_hash: id.to_string(),
+                    commit_hash: Some(id.to_string()),
+                    time: time.map(|t| t.seconds_since_unix_epoch),
+                    message: None,
+                }
+            }
+            _ => TagInfo {
+                name,
+                tag_hash: id.to_string(),
+                commit_hash: None,
+                time: None,
+                message: None,
+            },
+        };
+        tags.push(info);
+    }
+
+    tags.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.name.cmp(&b.name)));
+    Ok(tags)
+}
+
+/// Parse a progress line of git, eg. `Receiving objects:  45% (123/456), 1.2 MiB | 2 MiB/s`
+fn parse_progress(line: &str) -> Option<Progress> {
+    let line = line.strip_prefix("remote: ").unwrap_or(line);
+    let (stage, remaining) = line.split_once(": ")?;
+    let remaining = remaining.trim_start();
+
+    let (current, total) = if let Some((_, counter)) = remaining.split_once('(') {
+        let (counter, _) = counter.split_once(')')?;
+        let (current, total) = counter.split_once('/')?;
+        (current.parse().ok()?, Some(total.parse().ok()?))
+    } else {
+        let current = remaining.split([',', ' ']).next()?;
+        (current.parse().ok()?, None)
+    };
+
+    Some(Progress {
+        stage: stage.to_string(),
+        current,
+        total,
+    })
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Fetch updates for repositories that already exist
+    pub fetch: bool,
+    /// Never let git prompt for credentials or host keys, fail instead
+    pub non_interactive: bool,
+    /// Clone without blobs, only for mirrors that makepkg doesn't use as SRCDEST
+    pub partial_clone: bool,
+    /// Resolve refs with `git ls-remote` instead of cloning the repository
+    pub ls_remote: bool,
+}
+
+fn git_with_progress(
+    args: &[&OsStr],
+    path: &Path,
+    options: &Options,
+    observer: &dyn Observer,
+) -> Result<()> {
+    debug!("Executing git: {:?}", args);
+    let mut child = remote_command(options)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+
+    let handle_line = |line: &[u8]| {
+        let line = String::from_utf8_lossy(line);
+        if let Some(progress) = parse_progress(&line) {
+            observer.fetch_progress(path, &progress);
+        } else if !line.is_empty() {
+            debug!("git: {}", line);
+        }
+    };
+
+    // progress updates are terminated with \r instead of \n
+    let mut stderr = BufReader::new(child.stderr.take().context("Failed to get stderr of git")?);
+    let mut line = Vec::new();
+    loop {
+        let buf = stderr.fill_buf()?;
+        if buf.is_empty() {
+            handle_line(&line);
+            break;
+        }
+        if let Some(idx) = buf.iter().position(|b| *b == b'\r' || *b == b'\n') {
+            line.extend_from_slice(&buf[..idx]);
+            stderr.consume(idx + 1);
+            handle_line(&line);
+            line.clear();
+        } else {
+            let len = buf.len();
+            line.extend_from_slice(buf);
+            stderr.consume(len);
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("Git exited with error: {:?}", status);
+    }
+    observer.fetch_finished(path);
+    Ok(())
+}
+
+/// A git command, partial clones must not download missing blobs if network access is disabled
+fn command() -> Command {
+    let mut cmd = Command::new("git");
+    if network::is_disabled() {
+        cmd.env("GIT_NO_LAZY_FETCH", "1");
+    }
+    cmd
+}
+
+/// A git command that talks to a remote, never prompting if running non-interactively
+fn remote_command(options: &Options) -> Command {
+    let mut cmd = command();
+    if options.non_interactive {
+        cmd.env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes")
+            .env("SSH_ASKPASS_REQUIRE", "never");
+    }
+    cmd
+}
+
+fn git(args: &[&OsStr]) -> Result<()> {
+    debug!("Executing git: {:?}", args);
+    let status = command()
+        .args(args)
+        .stdin(Stdio::null())
+        .status()
+        .context("Failed to run git")?;
+    if !status.success() {
+        bail!("Git exited with error: {:?}", status);
+    }
+    Ok(())
+}
+
+fn git_output(args: &[&OsStr]) -> Result<String> {
+    debug!("Executing git: {:?}", args);
+    let output = command()
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("Git exited with error: {:?}", output.status);
+    }
+    String::from_utf8(output.stdout).context("Git output is not valid utf8")
+}
+
+/// Create a working copy of a commit, sharing the objects of the local repository
+pub fn checkout(repo_path: &Path, commit_hash: &str, dest: &Path) -> Result<()> {
+    git(&[
+        "clone".as_ref(),
+        "--quiet".as_ref(),
+        "--shared".as_ref(),
+        "--no-checkout".as_ref(),
+        "--".as_ref(),
+        repo_path.as_os_str(),
+        dest.as_os_str(),
+    ])
+    .with_context(|| anyhow!("Failed to clone {:?}", repo_path))?;
+    git(&[
+        "-C".as_ref(),
+        dest.as_os_str(),
+        "checkout".as_ref(),
+        "--quiet".as_ref(),
+        "--detach".as_ref(),
+        commit_hash.as_ref(),
+    ])
+    .with_context(|| anyhow!("Failed to checkout commit {:?}", commit_hash))
+}
+
+/// Create a mirror of the repository, the same way makepkg does
+pub fn clone(
+    source: &GitSource,
+    repo_path: &Path,
+    options: &Options,
+    observer: &dyn Observer,
+) -> Result<()> {
+    let url = source.remote_url();
+    network::guard(&format!("git clone {}", url));
+    observer.clone_started(url, repo_path);
+    let mut args: Vec<&OsStr> = vec!["clone".as_ref(), "--mirror".as_ref()];
+    if options.partial_clone {
+        // blobs are only downloaded once they are needed, eg. for a diffstat or checkout
+        args.push("--filter=blob:none".as_ref());
+    }
+    args.extend([
+        "--progress".as_ref(),
+        "--".as_ref(),
+        url.as_ref(),
+        repo_path.as_os_str(),
+    ]);
+    git_with_progress(&args, repo_path, options, observer)
+        .with_context(|| anyhow!("Failed to clone {:?}", url))
+}
+
+/// Update all refs of an existing mirror
+pub fn fetch(repo_path: &Path, options: &Options, observer: &dyn Observer) -> Result<()> {
+    network::guard(&format!("git fetch {:?}", repo_path));
+    observer.fetch_started(repo_path);
+    git_with_progress(
+        &[
+            "-C".as_ref(),
+            repo_path.as_os_str(),
+            "fetch".as_ref(),
+            "--all".as_ref(),
+            "-p".as_ref(),
+            "--progress".as_ref(),
+        ],
+        repo_path,
+        options,
+        observer,
+    )
+    .with_context(|| anyhow!("Failed to fetch {:?}", repo_path))
+}
+
+/// Point the remote of an existing mirror to a new url
+pub fn set_remote_url(repo_path: &Path, url: &str) -> Result<()> {
+    git(&[
+        "-C".as_ref(),
+        repo_path.as_os_str(),
+        "remote".as_ref(),
+        "set-url".as_ref(),
+        "origin".as_ref(),
+        url.as_ref(),
+    ])
+    .with_context(|| anyhow!("Failed to update remote of {:?}", repo_path))
+}
+
+/// Follow permanent redirects of an http(s) remote, returning the new url if the repository moved
+pub fn find_redirect(source: &GitSource) -> Result<Option<String>> {
+    const INFO_REFS: &str = "/info/refs?service=git-upload-pack";
+
+    let remote = source.remote_url();
+    if !remote.starts_with("https://") && !remote.starts_with("http://") {
+        return Ok(None);
+    }
+
+    let mut url = remote.trim_end_matches('/').to_string();
+    let mut moved = false;
+    for _ in 0..5 {
+        let probe = format!("{}{}", url, INFO_REFS);
+        let Some(location) = http::permanent_redirect(&probe)? else {
+            break;
+        };
+        let location = Url::parse(&probe)
+            .and_then(|probe| probe.join(&location))
+            .with_context(|| anyhow!("Invalid redirect location: {:?}", location))?
+            .to_string();
+        url = location
+            .strip_suffix(INFO_REFS)
+            .with_context(|| {
+                anyhow!(
+                    "Repository redirected to unexpected location: {:?}",
+                    location
+                )
+            })?
+            .to_string();
+        debug!("Repository {:?} redirected to {:?}", remote, url);
+        moved = true;
+    }
+
+    if !moved {
+        return Ok(None);
+    }
+    let prefix = if source.url.starts_with("git+") {
+        "git+"
+    } else {
+        ""
+    };
+    Ok(Some(format!("{}{}", prefix, url)))
+}
+
+/// Clone the repository if it's missing, or fetch updates if configured
+pub fn ensure_repo(
+    source: &GitSource,
+    repo_path: &Path,
+    options: &Options,
+    observer: &dyn Observer,
+) -> Result<()> {
     if !repo_path.exists() {
-        bail!(
-            "Repo does not exist yet, cloning is currently not supported {:?}",
-            repo_path
-        );
+        clone(source, repo_path, options, observer)?;
+    } else if options.fetch {
+        fetch(repo_path, options, observer)?;
+    }
+    Ok(())
+}
+
+/// A ref advertised by a remote
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+struct RemoteRef {
+    hash: String,
+    /// The commit an annotated tag points to, from the `^{}` entry
+    peeled: Option<String>,
+}
+
+impl RemoteRef {
+    /// The commit of the ref, lightweight tags and branches point at it directly
+    fn commit_hash(&self) -> &str {
+        self.peeled.as_deref().unwrap_or(&self.hash)
+    }
+}
+
+/// Parse the output of `git ls-remote`, pairing tags with their `^{}` entry
+fn parse_ls_remote(output: &str) -> Result<BTreeMap<String, RemoteRef>> {
+    let mut refs = BTreeMap::<String, RemoteRef>::new();
+    for line in output.lines() {
+        let Some((hash, name)) = line.split_once('\t') else {
+            bail!("Invalid line in ls-remote output: {:?}", line);
+        };
+        let (name, peeled) = match name.strip_suffix("^{}") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let entry = refs.entry(name.to_string()).or_default();
+        if peeled {
+            entry.peeled = Some(hash.to_string());
+        } else {
+            entry.hash = hash.to_string();
+        }
+    }
+    if let Some((name, _)) = refs.iter().find(|(_, r)| r.hash.is_empty()) {
+        bail!("Peeled ref without a ref in ls-remote output: {:?}", name);
+    }
+    Ok(refs)
+}
+
+/// Resolve the ref of a source with `git ls-remote`, without cloning the repository
+pub fn ls_remote(
+    source: GitSource,
+    options: &Options,
+    observer: &dyn Observer,
+) -> Result<ResolvedPin> {
+    let url = source.remote_url();
+    network::guard(&format!("git ls-remote {}", url));
+    let (name, pattern) = if let Some(tag) = &source.tag {
+        (tag.clone(), format!("refs/tags/{}", tag))
+    } else if let Some(branch) = &source.branch {
+        (branch.clone(), format!("refs/heads/{}", branch))
+    } else if source.commit.is_some() {
+        bail!("Source is pinned to a commit, configure a tag or branch to resolve instead");
+    } else {
+        ("HEAD".to_string(), "HEAD".to_string())
+    };
+
+    let mut cmd = remote_command(options);
+    // the peeled entry of annotated tags is only listed if it's requested too
+    cmd.args([
+        "ls-remote",
+        "--",
+        url,
+        &pattern,
+        &format!("{}^{{}}", pattern),
+    ]);
+    debug!("Executing git: {:?}", cmd);
+    let output = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("Failed to list refs of {:?}: {:?}", url, output.status);
+    }
+    let output = String::from_utf8(output.stdout).context("Git output is not valid utf8")?;
+    let refs = parse_ls_remote(&output)?;
+    let remote_ref = refs
+        .get(&pattern)
+        .with_context(|| anyhow!("Failed to find {:?} on remote", pattern))?;
+    debug!("Resolved {:?} with ls-remote: {:?}", pattern, remote_ref);
+
+    let pin = ResolvedPin {
+        tag_hash: source.tag.as_ref().map(|_| remote_ref.hash.clone()),
+        annotation: None,
+        commit_hash: remote_ref.commit_hash().to_string(),
+        source: Source::Git(source),
+    };
+    observer.pin_resolved(&name, &pin);
+    Ok(pin)
+}
+
+pub fn run(
+    source: GitSource,
+    repo_path: &Path,
+    options: &Options,
+    observer: &dyn Observer,
+) -> Result<ResolvedPin> {
+    if options.ls_remote {
+        return ls_remote(source, options, observer);
     }
+    ensure_repo(&source, repo_path, options, observer)?;
 
     let repo = git_repository::open(repo_path).context("Failed to open repository")?;
-    let tag_name = source.tag.as_ref().context("No tag configured")?;
-    let tag_ref = format!("refs/tags/{}", tag_name);
-    let tag = repo
-        .find_reference(&tag_ref)
-        .context("Failed to find tag")?;
-    debug!("Resolved tag from repository: {:?}", tag);
-
-    let tag_hash = tag
-        .inner
-        .target
-        .try_into_id()
-        .map_err(|r| anyhow!("Ref could not be turned into hash: {:?}", r))?
-        .to_string();
-    info!("Resolved tag {:?} to tag hash: {:?}", tag_name, tag_hash);
-    let peeled = tag.inner.peeled.context("Failed to resolve tag")?;
-    let commit_hash = peeled.to_string();
-    info!(
-        "Resolved tag {:?} to commit hash: {:?}",
-        tag_name, commit_hash
-    );
-    Ok(ResolvedPin {
+    let mut annotation = None;
+    let (name, tag_hash, commit_hash) = if let Some(tag_name) = &source.tag {
+        let tag_ref = format!("refs/tags/{}", tag_name);
+        let mut tag = repo
+            .find_reference(&tag_ref)
+            .context("Failed to find tag")?;
+        debug!("Resolved tag from repository: {:?}", tag);
+
+        let tag_hash = tag
+            .inner
+            .target
+            .clone()
+            .try_into_id()
+            .map_err(|r| anyhow!("Ref could not be turned into hash: {:?}", r))?
+            .to_string();
+        debug!("Resolved tag {:?} to tag hash: {:?}", tag_name, tag_hash);
+        annotation = read_annotation(&repo, &tag_hash)?;
+        // lightweight tags point at the commit directly, there's nothing to peel
+        let commit_hash = tag
+            .peel_to_id_in_place()
+            .context("Failed to resolve tag")?
+            .detach()
+            .to_string();
+        debug!(
+            "Resolved tag {:?} to commit hash: {:?}",
+            tag_name, commit_hash
+        );
+        (tag_name.clone(), Some(tag_hash), commit_hash)
+    } else if let Some(branch) = &source.branch {
+        let branch_ref = format!("refs/heads/{}", branch);
+        let mut reference = repo
+            .find_reference(&branch_ref)
+            .context("Failed to find branch")?;
+        let commit_hash = reference
+            .peel_to_id_in_place()
+            .context("Failed to resolve branch")?
+            .detach()
+            .to_string();
+        debug!(
+            "Resolved branch {:?} to commit hash: {:?}",
+            branch, commit_hash
+        );
+        (branch.clone(), None, commit_hash)
+    } else if source.commit.is_some() {
+        bail!("Source is pinned to a commit, configure a tag or branch to resolve instead");
+    } else {
+        let commit_hash = repo
+            .head_id()
+            .context("Failed to resolve HEAD")?
+            .detach()
+            .to_string();
+        debug!("Resolved HEAD to commit hash: {:?}", commit_hash);
+        ("HEAD".to_string(), None, commit_hash)
+    };
+
+    let pin = ResolvedPin {
         tag_hash,
+        annotation,
         commit_hash,
         source: Source::Git(source),
-    })
+    };
+    observer.pin_resolved(&name, &pin);
+    Ok(pin)
+}
+
+const SSH_SIGNATURE_BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+
+/// Read tagger and message of an annotated tag, lightweight tags have none
+fn read_annotation(repo: &Repository, tag_hash: &str) -> Result<Option<TagAnnotation>> {
+    let id = ObjectId::from_hex(tag_hash.as_bytes())
+        .with_context(|| anyhow!("Invalid tag hash: {:?}", tag_hash))?;
+    let object = repo.find_object(id).context("Failed to find tag")?;
+    if object.kind != objs::Kind::Tag {
+        return Ok(None);
+    }
+    let tag = object.try_to_tag_ref().context("Failed to decode tag")?;
+    // only OpenPGP signatures are split from the message by the decoder
+    let message = tag.message.to_string();
+    let (message, ssh_signature) = match message.split_once(SSH_SIGNATURE_BEGIN) {
+        Some((message, _)) => (message.to_string(), true),
+        None => (message, false),
+    };
+    Ok(Some(TagAnnotation {
+        tagger: tag.tagger.map(|t| format!("{} <{}>", t.name, t.email)),
+        message,
+        signed: tag.pgp_signature.is_some() || ssh_signature,
+    }))
+}
+
+fn find_commit<'a>(repo: &'a Repository, commit_hash: &str) -> Result<Commit<'a>> {
+    let id = ObjectId::from_hex(commit_hash.as_bytes())
+        .with_context(|| anyhow!("Invalid commit hash: {:?}", commit_hash))?;
+    let commit = repo
+        .find_object(id)
+        .context("Failed to find commit")?
+        .try_into_commit()
+        .context("Pinned object is not a commit")?;
+    Ok(commit)
+}
+
+/// The conventional pkgver of -git packages: `r<number of commits>.g<short hash>`
+pub fn vcs_pkgver(repo_path: &Path, commit_hash: &str) -> Result<String> {
+    let repo = git_repository::open(repo_path).context("Failed to open repository")?;
+    let commit = find_commit(&repo, commit_hash)?;
+
+    let mut count = 0;
+    for ancestor in commit.ancestors().all()? {
+        ancestor.context("Failed to traverse commit history")?;
+        count += 1;
+    }
+    let short = commit.id().shorten_or_id();
+    Ok(format!("r{}.g{}", count, short))
+}
+
+/// Turn a tag name like `v1.2.3` or `1.2-rc1` into a pkgver
+///
+/// If a prefix is given (eg. `release-`) it's stripped instead of a leading `v`.
+pub fn tag_to_pkgver(name: &str, prefix: Option<&str>) -> String {
+    let version = match prefix.and_then(|prefix| name.strip_prefix(prefix)) {
+        Some(version) => version,
+        // strip a leading v from tags like v1.2.3
+        None => match name.strip_prefix('v') {
+            Some(version) if version.starts_with(|c: char| c.is_ascii_digit()) => version,
+            _ => name,
+        },
+    };
+    version.replace('-', ".")
+}
+
+/// A pkgver based on `git describe --long --tags`, eg. `1.2.3.r4.g0123abc`
+///
+/// With a tag prefix only tags starting with it are considered.
+pub fn describe_pkgver(
+    repo_path: &Path,
+    commit_hash: &str,
+    tag_prefix: Option<&str>,
+) -> Result<String> {
+    if let Some(prefix) = tag_prefix {
+        let pattern = format!("{}*", prefix);
+        let out = git_output(&[
+            "-C".as_ref(),
+            repo_path.as_os_str(),
+            "describe".as_ref(),
+            "--long".as_ref(),
+            "--tags".as_ref(),
+            "--match".as_ref(),
+            pattern.as_ref(),
+            commit_hash.as_ref(),
+        ])
+        .with_context(|| {
+            anyhow!(
+                "No tag matching {:?} found that this commit is based on",
+                pattern
+            )
+        })?;
+        // <tag>-<depth>-g<hash>, the tag itself may contain dashes
+        let mut parts = out.trim().rsplitn(3, '-');
+        let (Some(hash), Some(depth), Some(name)) = (parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Unexpected output of git describe: {:?}", out);
+        };
+        debug!(
+            "Described commit {:?}: tag={:?}, depth={}",
+            commit_hash, name, depth
+        );
+        return Ok(format!(
+            "{}.r{}.{}",
+            tag_to_pkgver(name, tag_prefix),
+            depth,
+            hash
+        ));
+    }
+
+    let repo = git_repository::open(repo_path).context("Failed to open repository")?;
+    let commit = find_commit(&repo, commit_hash)?;
+
+    let format = commit
+        .describe()
+        .names(SelectRef::AllTags)
+        .try_resolve()
+        .context("Failed to describe commit")?
+        .context("No tag found that this commit is based on")?
+        .format()
+        .context("Failed to describe commit")?;
+    let name = format
+        .name
+        .context("No tag found that this commit is based on")?
+        .to_string();
+    debug!(
+        "Described commit {:?}: tag={:?}, depth={}",
+        commit_hash, name, format.depth
+    );
+
+    Ok(format!(
+        "{}.r{}.g{}",
+        tag_to_pkgver(&name, None),
+        format.depth,
+        format.id.to_hex_with_len(format.hex_len)
+    ))
+}
+
+/// The newest commit in the history of a commit that touches a path, eg. a component of a monorepo
+pub fn last_commit_touching(repo_path: &Path, commit_hash: &str, path: &str) -> Result<String> {
+    let out = git_output(&[
+        "-C".as_ref(),
+        repo_path.as_os_str(),
+        "log".as_ref(),
+        "-1".as_ref(),
+        "--format=%H".as_ref(),
+        commit_hash.as_ref(),
+        "--".as_ref(),
+        path.as_ref(),
+    ])?;
+    let commit = out.trim();
+    if commit.is_empty() {
+        bail!(
+            "No commit touches {:?} in the history of {}",
+            path,
+            commit_hash
+        );
+    }
+    Ok(commit.to_string())
+}
+
+/// The newest commit on the first-parent history of a commit that was committed before a time
+pub fn last_commit_before(repo_path: &Path, commit_hash: &str, time: SystemTime) -> Result<String> {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .context("Time is before the unix epoch")?
+        .as_secs();
+    let before = format!("--before=@{}", secs);
+    let out = git_output(&[
+        "-C".as_ref(),
+        repo_path.as_os_str(),
+        "rev-list".as_ref(),
+        "-1".as_ref(),
+        "--first-parent".as_ref(),
+        before.as_ref(),
+        commit_hash.as_ref(),
+    ])?;
+    let commit = out.trim();
+    if commit.is_empty() {
+        bail!(
+            "No commit in the history of {} is older than {}",
+            commit_hash,
+            humantime::format_rfc3339(time)
+        );
+    }
+    Ok(commit.to_string())
+}
+
+/// Resolve a commit hash, tag name or tag object hash to the commit it points to
+pub fn resolve_commit(repo_path: &Path, rev: &str) -> Result<String> {
+    let repo = git_repository::open(repo_path).context("Failed to open repository")?;
+    let spec = format!("{}^{{commit}}", rev);
+    let id = repo
+        .rev_parse_single(spec.as_str())
+        .with_context(|| anyhow!("Failed to resolve {:?} to a commit", rev))?;
+    Ok(id.detach().to_string())
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Reachability {
+    /// Part of the history of a branch or tag
+    Reachable,
+    /// The object is still around locally, but no branch or tag contains it anymore
+    Unreachable,
+    /// The object isn't known to the local repository at all
+    Unknown,
+}
+
+/// Check if a commit is still part of the history of any branch or tag
+pub fn reachability(repo_path: &Path, rev: &str) -> Result<Reachability> {
+    let Ok(commit) = resolve_commit(repo_path, rev) else {
+        debug!("Commit {:?} is missing from {:?}", rev, repo_path);
+        return Ok(Reachability::Unknown);
+    };
+    let contains = format!("--contains={}", commit);
+    let refs = git_output(&[
+        "-C".as_ref(),
+        repo_path.as_os_str(),
+        "for-each-ref".as_ref(),
+        "--count=1".as_ref(),
+        "--format=%(refname)".as_ref(),
+        contains.as_ref(),
+    ])?;
+    if refs.trim().is_empty() {
+        Ok(Reachability::Unreachable)
+    } else {
+        Ok(Reachability::Reachable)
+    }
+}
+
+/// Check if this is a full sha1 or sha256 object id
+pub fn is_full_hash(hash: &str) -> bool {
+    matches!(hash.len(), 40 | 64) && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Expand an abbreviated commit hash to the full object id, `None` if it's ambiguous
+pub fn expand_commit(repo_path: &Path, short: &str) -> Result<Option<String>> {
+    if is_full_hash(short) {
+        return Ok(Some(short.to_string()));
+    }
+    let disambiguate = format!("--disambiguate={}", short);
+    let candidates = git_output(&[
+        "-C".as_ref(),
+        repo_path.as_os_str(),
+        "rev-parse".as_ref(),
+        disambiguate.as_ref(),
+    ])?;
+    let repo = git_repository::open(repo_path).context("Failed to open repository")?;
+    // the abbreviation may also match trees, blobs or tags, only commits can be pinned
+    let candidates = candidates
+        .lines()
+        .filter(|id| find_commit(&repo, id).is_ok())
+        .collect::<Vec<_>>();
+    match candidates[..] {
+        [] => bail!("Failed to find commit {:?} in {:?}", short, repo_path),
+        [full] => {
+            debug!("Expanded abbreviated commit {:?} to {:?}", short, full);
+            Ok(Some(full.to_string()))
+        }
+        _ => {
+            warn!(
+                "Abbreviated commit {:?} is ambiguous in {:?}, matching: {}",
+                short,
+                repo_path,
+                candidates.join(", ")
+            );
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub commit_hash: String,
+    pub summary: String,
+}
+
+/// The commits reachable from `new` but not from `old`, like `git log old..new`
+pub fn changelog(repo_path: &Path, old: &str, new: &str) -> Result<Vec<LogEntry>> {
+    let repo = git_repository::open(repo_path).context("Failed to open repository")?;
+    let old = find_commit(&repo, old)?;
+    let new = find_commit(&repo, new)?;
+
+    let mut known = HashSet::new();
+    for ancestor in old.ancestors().all()? {
+        known.insert(
+            ancestor
+                .context("Failed to traverse commit history")?
+                .detach(),
+        );
+    }
+
+    let mut log = Vec::new();
+    for ancestor in new.ancestors().all()? {
+        let id = ancestor
+            .context("Failed to traverse commit history")?
+            .detach();
+        if known.contains(&id) {
+            continue;
+        }
+        let commit = repo
+            .find_object(id)
+            .context("Failed to read commit")?
+            .try_into_commit()
+            .context("Object is not a commit")?;
+        let summary = commit
+            .message()
+            .context("Failed to decode commit message")?
+            .summary()
+            .to_string();
+        log.push(LogEntry {
+            commit_hash: id.to_string(),
+            summary,
+        });
+    }
+    Ok(log)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    pub url: String,
+    /// The commit recorded in the superproject
+    pub commit_hash: String,
+}
+
+/// List the submodules of a commit, with the commits they're pinned to
+pub fn submodules(repo_path: &Path, commit_hash: &str) -> Result<Vec<Submodule>> {
+    let ls_tree = |path: &str| {
+        git_output(&[
+            "-C".as_ref(),
+            repo_path.as_os_str(),
+            "ls-tree".as_ref(),
+            commit_hash.as_ref(),
+            "--".as_ref(),
+            path.as_ref(),
+        ])
+    };
+    if ls_tree(".gitmodules")?.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let blob = format!("{}:.gitmodules", commit_hash);
+    let config = git_output(&[
+        "-C".as_ref(),
+        repo_path.as_os_str(),
+        "config".as_ref(),
+        "--blob".as_ref(),
+        blob.as_ref(),
+        "--list".as_ref(),
+    ])?;
+
+    let mut paths = Vec::<(String, String)>::new();
+    let mut urls = HashMap::new();
+    for line in config.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let Some((name, key)) = key
+            .strip_prefix("submodule.")
+            .and_then(|key| key.rsplit_once('.'))
+        else {
+            continue;
+        };
+        match key {
+            "path" => paths.push((name.to_string(), value.to_string())),
+            "url" => {
+                urls.insert(name.to_string(), value.to_string());
+            }
+            _ => (),
+        }
+    }
+
+    let mut submodules = Vec::new();
+    for (name, path) in paths {
+        let url = urls
+            .remove(&name)
+            .with_context(|| anyhow!("Submodule has no url: {:?}", name))?;
+        // <mode> SP <type> SP <hash> TAB <path>
+        let entry = ls_tree(&path)?;
+        let commit_hash = match entry.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "commit", hash, ..] => hash.to_string(),
+            _ => bail!("Submodule {:?} is not recorded at {:?}", name, path),
+        };
+        submodules.push(Submodule {
+            name,
+            path,
+            url,
+            commit_hash,
+        });
+    }
+    Ok(submodules)
+}
+
+/// Resolve a submodule url that is relative to the superproject
+pub fn submodule_url(superproject: &str, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_string();
+    }
+    let base = format!("{}/", superproject.trim_end_matches('/'));
+    match Url::parse(&base).and_then(|base| base.join(url)) {
+        Ok(url) => url.to_string(),
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Split a scp-like ssh remote (`git@host:org/repo.git`) into host and path
+///
+/// Without a `git+` prefix the host needs a user or a dot, so local files like `fix:typo.patch`
+/// aren't mistaken for remotes.
+pub fn split_scp_url(url: &str) -> Option<(&str, &str)> {
+    let (url, explicit) = match url.strip_prefix("git+") {
+        Some(url) => (url, true),
+        None => (url, false),
+    };
+    if url.contains("://") {
+        return None;
+    }
+    let (host, path) = url.split_once(':')?;
+    if host.is_empty() || host.contains('/') || path.is_empty() {
+        return None;
+    }
+    if !explicit && !host.contains('@') && !host.contains('.') {
+        return None;
+    }
+    Some((host, path))
+}
+
+/// The scheme and host of a remote url, scp-like remotes are reported as ssh
+pub fn remote_host(url: &str) -> Option<(String, String)> {
+    if let Some((host, _path)) = split_scp_url(url) {
+        let host = host.rsplit_once('@').map_or(host, |(_user, host)| host);
+        return Some(("ssh".to_string(), host.to_string()));
+    }
+    let url = Url::parse(url.strip_prefix("git+").unwrap_or(url)).ok()?;
+    Some((url.scheme().to_string(), url.host_str()?.to_string()))
+}
+
+/// Normalize a remote url for comparison, ignoring `git+`, trailing slashes and `.git`
+pub fn normalize_url(url: &str) -> &str {
+    let url = url.strip_prefix("git+").unwrap_or(url);
+    let url = url.trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url)
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct DiffStat {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+impl fmt::Display for DiffStat {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            w,
+            "{} file(s) changed, +{}/-{}",
+            self.files_changed, self.insertions, self.deletions
+        )
+    }
+}
+
+/// Count the changed files and lines between two commits, like `git diff --shortstat`
+pub fn diffstat(repo_path: &Path, old: &str, new: &str) -> Result<DiffStat> {
+    let out = git_output(&[
+        "-C".as_ref(),
+        repo_path.as_os_str(),
+        "diff".as_ref(),
+        "--numstat".as_ref(),
+        old.as_ref(),
+        new.as_ref(),
+        "--".as_ref(),
+    ])?;
+
+    let mut stat = DiffStat::default();
+    for line in out.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(insertions), Some(deletions), Some(_path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("Unexpected output from git diff: {:?}", line);
+        };
+        stat.files_changed += 1;
+        // binary files are listed as `-`
+        stat.insertions += insertions.parse::<usize>().unwrap_or(0);
+        stat.deletions += deletions.parse::<usize>().unwrap_or(0);
+    }
+    Ok(stat)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TagLookup {
+    /// Tags that point directly at the commit
+    Exact(Vec<TagInfo>),
+    /// The oldest tag that contains the commit in its history
+    ContainedIn(TagInfo),
+    NotFound,
+}
+
+/// Find the release tag a commit corresponds to
+pub fn find_tag_for_commit(repo_path: &Path, commit_hash: &str) -> Result<TagLookup> {
+    let tags = list_tags(repo_path)?;
+    let exact = tags
+        .iter()
+        .filter(|tag| tag.commit_hash.as_deref() == Some(commit_hash))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !exact.is_empty() {
+        return Ok(TagLookup::Exact(exact));
+    }
+
+    let repo = git_repository::open(repo_path).context("Failed to open repository")?;
+    let id = find_commit(&repo, commit_hash)?.id;
+
+    // tags are sorted newest first, we're looking for the first release containing the commit
+    for tag in tags.iter().rev() {
+        let Some(tag_commit) = &tag.commit_hash else {
+            continue;
+        };
+        let Ok(commit) = find_commit(&repo, tag_commit) else {
+            debug!(
+                "Skipping tag that doesn't point to a commit: {:?}",
+                tag.name
+            );
+            continue;
+        };
+        for ancestor in commit.ancestors().all()? {
+            if ancestor.context("Failed to traverse commit history")? == id {
+                return Ok(TagLookup::ContainedIn(tag.clone()));
+            }
+        }
+    }
+
+    Ok(TagLookup::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    /// Run git with a fixed identity and dates, ignoring the config of the user
+    fn git_in(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=updvcspins",
+                "-c",
+                "user.email=updvcspins@example.com",
+                "-c",
+                "commit.gpgsign=false",
+                "-c",
+                "tag.gpgsign=false",
+                "-c",
+                "init.defaultBranch=main",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_DATE", "2024-01-31T12:00:00Z")
+            .env("GIT_COMMITTER_DATE", "2024-01-31T12:00:00Z")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {:?}",
+            args,
+            output
+        );
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim_end()
+            .to_string()
+    }
+
+    /// An upstream repository with two commits, tagged `v1.0.0` (annotated) and `v1.1.0`
+    fn upstream(dir: &Path) -> PathBuf {
+        let path = dir.join("upstream");
+        fs::create_dir(&path).unwrap();
+        git_in(&path, &["init", "-q"]);
+        git_in(
+            &path,
+            &["commit", "-q", "--allow-empty", "-m", "Initial commit"],
+        );
+        git_in(&path, &["tag", "-a", "v1.0.0", "-m", "Release 1.0.0"]);
+        git_in(&path, &["commit", "-q", "--allow-empty", "-m", "Fix crash"]);
+        git_in(&path, &["tag", "v1.1.0"]);
+        path
+    }
+
+    fn file_source(upstream: &Path, fragment: &str) -> GitSource {
+        format!("git+file://{}{}", upstream.display(), fragment)
+            .parse()
+            .unwrap()
+    }
+
+    /// Records the events it receives
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Recorder {
+        fn events(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Observer for Recorder {
+        fn clone_started(&self, url: &str, _path: &Path) {
+            self.0.lock().unwrap().push(format!("clone {}", url));
+        }
+
+        fn fetch_started(&self, _path: &Path) {
+            self.0.lock().unwrap().push("fetch".to_string());
+        }
+
+        fn pin_resolved(&self, name: &str, pin: &ResolvedPin) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("resolved {} {}", name, pin.commit_hash));
+        }
+    }
+
+    #[test]
+    fn test_observer_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = upstream(dir.path());
+        let source = file_source(&upstream, "#tag=v1.0.0");
+        let url = source.remote_url().to_string();
+
+        let recorder = Recorder::default();
+        let pin = run(
+            source,
+            &dir.path().join("foo"),
+            &Options::default(),
+            &recorder,
+        )
+        .unwrap();
+        let commit = git_in(&upstream, &["rev-parse", "v1.0.0^{commit}"]);
+        assert_eq!(pin.commit_hash, commit);
+        assert_eq!(
+            recorder.events(),
+            [
+                format!("clone {}", url),
+                format!("resolved v1.0.0 {}", commit)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ls_remote() {
+        let output = "1111111111111111111111111111111111111111\trefs/tags/v1.0.0
+2222222222222222222222222222222222222222\trefs/tags/v1.0.0^{}
+3333333333333333333333333333333333333333\trefs/tags/v1.1.0
+";
+        let refs = parse_ls_remote(output).unwrap();
+        assert_eq!(
+            refs["refs/tags/v1.0.0"],
+            RemoteRef {
+                hash: "1111111111111111111111111111111111111111".to_string(),
+                peeled: Some("2222222222222222222222222222222222222222".to_string()),
+            }
+        );
+        assert_eq!(
+            refs["refs/tags/v1.0.0"].commit_hash(),
+            "2222222222222222222222222222222222222222"
+        );
+        // lightweight tags have no ^{} entry
+        assert_eq!(
+            refs["refs/tags/v1.1.0"].commit_hash(),
+            "3333333333333333333333333333333333333333"
+        );
+        assert!(parse_ls_remote("2222\trefs/tags/v2^{}\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_refs() {
+        let git = "git+https://example.com/foo#tag=v1.0.0"
+            .parse::<GitSource>()
+            .unwrap();
+        assert_eq!(git.url, "git+https://example.com/foo");
+        assert_eq!(git.tag.as_deref(), Some("v1.0.0"));
+        assert!(!git.signed);
+
+        let git = "git+https://example.com/foo?signed#commit=abc"
+            .parse::<GitSource>()
+            .unwrap();
+        assert_eq!(git.commit.as_deref(), Some("abc"));
+        assert!(git.signed);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("git+https://example.com/foo#tag"
+            .parse::<GitSource>()
+            .is_err());
+        assert!("git+https://example.com/foo#revision=1"
+            .parse::<GitSource>()
+            .is_err());
+        assert!("git+https://example.com/foo?unsigned"
+            .parse::<GitSource>()
+            .is_err());
+        // makepkg would check out `b`
+        assert!("git+https://example.com/foo#branch=a=b"
+            .parse::<GitSource>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for s in [
+            "git+https://example.com/foo",
+            "git+https://example.com/foo#tag=v1.0.0",
+            "git+https://example.com/foo#commit=abc?signed",
+            "git+https://example.com/foo?signed#branch=main",
+            "git+https://example.com/foo?signed",
+        ] {
+            assert_eq!(s.parse::<GitSource>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_special_characters() {
+        for s in [
+            "git+https://example.com/foo#tag=release/v1.0",
+            "git+https://example.com/foo#tag=v1#2?signed",
+            "git+https://example.com/foo#branch=feature/@home",
+        ] {
+            assert_eq!(s.parse::<GitSource>().unwrap().to_string(), s);
+        }
+        let git = GitSource::builder("git+https://example.com/foo")
+            .tag("v1#2")
+            .build()
+            .unwrap();
+        assert_eq!(git.tag.as_deref(), Some("v1#2"));
+        // like makepkg, the query ends at the first `#`
+        let mut git = "git+https://example.com/foo?signed#tag=v1"
+            .parse::<GitSource>()
+            .unwrap();
+        git.tag = Some("v1#2".to_string());
+        assert_eq!(
+            git.to_string(),
+            "git+https://example.com/foo?signed#tag=v1#2"
+        );
+        assert_eq!(git.to_string().parse::<GitSource>().unwrap(), git);
+
+        for tag in [
+            "a=b", "a?b", "a b", "a..b", "a~1", "-a", "a/", "a.lock", "a\tb",
+        ] {
+            let err = GitSource::builder("git+https://example.com/foo")
+                .tag(tag)
+                .build()
+                .unwrap_err();
+            assert!(err.to_string().contains(&format!("{:?}", tag)), "{}", err);
+        }
+        assert!("git+https://example.com/foo#tag=a=b"
+            .parse::<GitSource>()
+            .is_err());
+        assert!("git+https://example.com/foo#tag="
+            .parse::<GitSource>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        for url in [
+            "https://example.com/foo",
+            "https://example.com/foo/",
+            "https://example.com/foo.git",
+            "git+https://example.com/foo.git/",
+        ] {
+            assert_eq!(normalize_url(url), "https://example.com/foo");
+        }
+        assert_ne!(
+            normalize_url("https://example.com/foo-git"),
+            normalize_url("https://example.com/foo")
+        );
+    }
+
+    #[test]
+    fn test_normalize_overlapping() {
+        let git = "git+https://example.com/foo#commit=abc?signed#tag=v1"
+            .parse::<GitSource>()
+            .unwrap();
+        assert_eq!(git.commit.as_deref(), Some("abc"));
+        assert!(git.signed);
+        assert_eq!(
+            git.to_string(),
+            "git+https://example.com/foo#commit=abc?signed"
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let git = GitSource::builder("git+https://example.com/foo")
+            .tag("v1")
+            .signed(true)
+            .build()
+            .unwrap();
+        assert_eq!(git.to_string(), "git+https://example.com/foo#tag=v1?signed");
+        assert!(GitSource::builder("git+https://example.com/foo")
+            .tag("v1")
+            .commit("abc")
+            .build()
+            .is_err());
+        assert!(GitSource::builder("https://example.com/foo")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let build = |url: &str| GitSource::builder(url).tag("v1").build();
+        assert!(build("git+https://example.com/foo.git").is_ok());
+        assert!(build("git://example.com/foo.git").is_ok());
+        assert!(build("git+https://example.com/foo.git#tag=v2").is_err());
+        assert!(build("git+https://example.com/foo.git?signed").is_err());
+        assert!(build("git+https://example.com/").is_err());
+        assert!(build("not a url").is_err());
+        assert!(GitSource::builder("git+https://example.com/foo.git")
+            .commit("")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_branch_roundtrip() {
+        let s = "git+https://example.com/foo.git#branch=main";
+        let source = s.parse::<GitSource>().unwrap();
+        assert_eq!(source.branch.as_deref(), Some("main"));
+        assert_eq!(source.to_string(), s);
+
+        let built = GitSource::builder("git+https://example.com/foo.git")
+            .branch("main")
+            .build()
+            .unwrap();
+        assert_eq!(built, source);
+        assert!(GitSource::builder("git+https://example.com/foo.git")
+            .branch("main")
+            .tag("v1")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_tag_to_pkgver() {
+        assert_eq!(tag_to_pkgver("v1.2.3", None), "1.2.3");
+        assert_eq!(tag_to_pkgver("1.2-rc1", None), "1.2.rc1");
+        assert_eq!(tag_to_pkgver("version", None), "version");
+        assert_eq!(tag_to_pkgver("release-1.2", Some("release-")), "1.2");
+        assert_eq!(tag_to_pkgver("foo-v1.2", Some("foo-v")), "1.2");
+        assert_eq!(tag_to_pkgver("v1.2", Some("release-")), "1.2");
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        assert_eq!(
+            parse_progress("Receiving objects:  45% (123/456), 1.2 MiB | 2 MiB/s"),
+            Some(Progress {
+                stage: "Receiving objects".to_string(),
+                current: 123,
+                total: Some(456),
+            })
+        );
+        assert_eq!(
+            parse_progress("remote: Counting objects: 1234, done."),
+            Some(Progress {
+                stage: "Counting objects".to_string(),
+                current: 1234,
+                total: None,
+            })
+        );
+        assert_eq!(
+            parse_progress("Cloning into bare repository 'foo'..."),
+            None
+        );
+        assert_eq!(
+            parse_progress("warning: redirecting to https://example.com/"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_non_interactive() {
+        let envs = |options: &Options| {
+            remote_command(options)
+                .get_envs()
+                .map(|(key, value)| (key.to_owned(), value.map(|v| v.to_owned())))
+                .collect::<Vec<_>>()
+        };
+        let prompt = ("GIT_TERMINAL_PROMPT".into(), Some("0".into()));
+        assert!(!envs(&Options::default()).contains(&prompt));
+        let envs = envs(&Options {
+            non_interactive: true,
+            ..Default::default()
+        });
+        assert!(envs.contains(&prompt));
+        assert!(envs.contains(&(
+            "GIT_SSH_COMMAND".into(),
+            Some("ssh -o BatchMode=yes".into())
+        )));
+    }
+
+    #[test]
+    fn test_vcs_pkgver() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = upstream(dir.path());
+        let head = git_in(&upstream, &["rev-parse", "HEAD"]);
+        let short = git_in(&upstream, &["rev-parse", "--short", "HEAD"]);
+        assert_eq!(
+            vcs_pkgver(&upstream, &head).unwrap(),
+            format!("r2.g{}", short)
+        );
+        let first = git_in(&upstream, &["rev-parse", "HEAD^"]);
+        assert!(vcs_pkgver(&upstream, &first).unwrap().starts_with("r1.g"));
+    }
+
+    #[test]
+    fn test_describe_pkgver() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = upstream(dir.path());
+        git_in(
+            &upstream,
+            &["commit", "-q", "--allow-empty", "-m", "Unreleased"],
+        );
+        let head = git_in(&upstream, &["rev-parse", "HEAD"]);
+        let short = git_in(&upstream, &["rev-parse", "--short", "HEAD"]);
+        assert_eq!(
+            describe_pkgver(&upstream, &head, None).unwrap(),
+            format!("1.1.0.r1.g{}", short)
+        );
+        git_in(&upstream, &["tag", "release-2.0-rc1", "HEAD^"]);
+        assert_eq!(
+            describe_pkgver(&upstream, &head, Some("release-")).unwrap(),
+            format!("2.0.rc1.r1.g{}", short)
+        );
+        assert!(describe_pkgver(&upstream, &head, Some("stable-")).is_err());
+    }
+
+    #[test]
+    fn test_find_tag_for_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = upstream(dir.path());
+        let first = git_in(&upstream, &["rev-parse", "HEAD^"]);
+        git_in(&upstream, &["tag", "latest", "HEAD^"]);
+        match find_tag_for_commit(&upstream, &first).unwrap() {
+            TagLookup::Exact(tags) => assert_eq!(
+                tags.iter().map(|tag| tag.name.as_str()).collect::<Vec<_>>(),
+                ["latest", "v1.0.0"]
+            ),
+            other => panic!("Unexpected lookup result: {:?}", other),
+        }
+
+        git_in(&upstream, &["tag", "-d", "latest", "v1.0.0"]);
+        match find_tag_for_commit(&upstream, &first).unwrap() {
+            TagLookup::ContainedIn(tag) => assert_eq!(tag.name, "v1.1.0"),
+            other => panic!("Unexpected lookup result: {:?}", other),
+        }
+
+        git_in(
+            &upstream,
+            &["commit", "-q", "--allow-empty", "-m", "Unreleased"],
+        );
+        let head = git_in(&upstream, &["rev-parse", "HEAD"]);
+        assert!(matches!(
+            find_tag_for_commit(&upstream, &head).unwrap(),
+            TagLookup::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_expand_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = upstream(dir.path());
+        let head = git_in(&upstream, &["rev-parse", "HEAD"]);
+        assert_eq!(
+            expand_commit(&upstream, &head[..7]).unwrap(),
+            Some(head.clone())
+        );
+        // objects that aren't commits are never expanded
+        let tag = git_in(&upstream, &["rev-parse", "v1.0.0"]);
+        assert!(expand_commit(&upstream, &tag[..7]).is_err());
+        let tree = git_in(&upstream, &["rev-parse", "HEAD^{tree}"]);
+        assert!(expand_commit(&upstream, &tree[..7]).is_err());
+    }
+
+    #[test]
+    fn test_reachability() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = upstream(dir.path());
+        let head = git_in(&upstream, &["rev-parse", "HEAD"]);
+        assert_eq!(
+            reachability(&upstream, &head).unwrap(),
+            Reachability::Reachable
+        );
+
+        // force-push the last commit away
+        git_in(&upstream, &["tag", "-d", "v1.1.0"]);
+        git_in(&upstream, &["reset", "-q", "--hard", "HEAD^"]);
+        assert_eq!(
+            reachability(&upstream, &head).unwrap(),
+            Reachability::Unreachable
+        );
+        assert_eq!(
+            reachability(&upstream, &"1".repeat(40)).unwrap(),
+            Reachability::Unknown
+        );
+    }
 }
@@ -0,0 +1,44 @@
+use crate::errors::*;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// The points at which hook commands run
+///
+/// There is no post-commit hook because updvcspins never commits the PKGBUILD itself, a post-write
+/// hook can do that and run anything that should follow.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Hook {
+    /// Before any pin is resolved
+    PreResolve,
+    /// After the updated PKGBUILD has been written
+    PostWrite,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreResolve => "pre-resolve",
+            Hook::PostWrite => "post-write",
+        }
+    }
+}
+
+/// Run a hook command with `sh -c`, passing the environment variables in addition to `UPDVCSPINS_HOOK`
+pub fn run(hook: Hook, cmd: &str, dir: &Path, env: &[(&str, String)]) -> Result<()> {
+    info!("Running {} hook: {:?}", hook.name(), cmd);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .current_dir(dir)
+        // keep stdout clean for when the PKGBUILD is written there
+        .stdout(io::stderr())
+        .env("UPDVCSPINS_HOOK", hook.name())
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .status()
+        .with_context(|| anyhow!("Failed to run {} hook", hook.name()))?;
+    if !status.success() {
+        bail!("The {} hook {:?} failed: {:?}", hook.name(), cmd, status);
+    }
+    Ok(())
+}
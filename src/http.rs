@@ -0,0 +1,55 @@
+use crate::errors::*;
+use crate::network;
+use ureq::typestate::{WithBody, WithoutBody};
+use ureq::{Agent, RequestBuilder};
+
+const USER_AGENT: &str = concat!("updvcspins/", env!("CARGO_PKG_VERSION"));
+
+/// Start a GET request with our user agent set
+pub fn get(url: &str) -> RequestBuilder<WithoutBody> {
+    network::guard(&format!("GET {}", url));
+    ureq::get(url).header("User-Agent", USER_AGENT)
+}
+
+/// Start a HEAD request with our user agent set
+pub fn head(url: &str) -> RequestBuilder<WithoutBody> {
+    network::guard(&format!("HEAD {}", url));
+    ureq::head(url).header("User-Agent", USER_AGENT)
+}
+
+/// Start a POST request with our user agent set
+pub fn post(url: &str) -> RequestBuilder<WithBody> {
+    network::guard(&format!("POST {}", url));
+    ureq::post(url).header("User-Agent", USER_AGENT)
+}
+
+/// Start a PUT request with our user agent set
+pub fn put(url: &str) -> RequestBuilder<WithBody> {
+    network::guard(&format!("PUT {}", url));
+    ureq::put(url).header("User-Agent", USER_AGENT)
+}
+
+/// Request the url without following redirects, returning the location of a permanent redirect
+pub fn permanent_redirect(url: &str) -> Result<Option<String>> {
+    network::guard(&format!("GET {}", url));
+    let agent: Agent = Agent::config_builder()
+        .max_redirects(0)
+        .http_status_as_error(false)
+        .build()
+        .into();
+    let response = agent
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .call()
+        .with_context(|| anyhow!("Failed to request {:?}", url))?;
+    if !matches!(response.status().as_u16(), 301 | 308) {
+        return Ok(None);
+    }
+    let location = response
+        .headers()
+        .get("Location")
+        .context("Redirect has no location")?
+        .to_str()
+        .context("Redirect location is not valid utf8")?;
+    Ok(Some(location.to_string()))
+}
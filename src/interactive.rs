@@ -0,0 +1,174 @@
+use crate::errors::*;
+use crate::rewrite;
+use similar::TextDiff;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    Quit,
+}
+
+pub fn diff(path: &Path, old: &[u8], new: &[u8]) -> String {
+    let old = String::from_utf8_lossy(old);
+    let new = String::from_utf8_lossy(new);
+    let name = path.display().to_string();
+    TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(&name, &name)
+        .to_string()
+}
+
+/// Only the values of assignments that changed, one line each, eg. `_commit: 0123abc → 4567def`
+pub fn compact_diff(old: &[u8], new: &[u8]) -> String {
+    let old = rewrite::assignments(&String::from_utf8_lossy(old));
+    let new = rewrite::assignments(&String::from_utf8_lossy(new));
+    let find = |assignments: &[(String, Vec<String>)], name: &str| {
+        assignments
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, words)| words.clone())
+            .unwrap_or_default()
+    };
+
+    let mut seen = BTreeSet::new();
+    let mut lines = Vec::new();
+    for (name, _) in old.iter().chain(&new) {
+        if !seen.insert(name) {
+            continue;
+        }
+        let (old, new) = (find(&old, name), find(&new, name));
+        if old == new {
+            continue;
+        }
+        if old.len() == new.len() {
+            for (old, new) in old.iter().zip(&new).filter(|(old, new)| old != new) {
+                lines.push(format!("{}: {} → {}", name, old, new));
+            }
+        } else if old.len() <= 1 && new.len() <= 1 {
+            let value = |words: &[String]| words.first().cloned().unwrap_or("(unset)".to_string());
+            lines.push(format!("{}: {} → {}", name, value(&old), value(&new)));
+        } else {
+            for word in old.iter().filter(|word| !new.contains(word)) {
+                lines.push(format!("{}: -{}", name, word));
+            }
+            for word in new.iter().filter(|word| !old.contains(word)) {
+                lines.push(format!("{}: +{}", name, word));
+            }
+        }
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Ask a yes/no/quit question on the terminal
+///
+/// The terminal is used directly so this also works while stdin is used for input.
+pub fn confirm(question: &str) -> Result<Answer> {
+    let tty = File::open("/dev/tty").context("Failed to open terminal for confirmation")?;
+    let mut tty = BufReader::new(tty);
+    let mut stderr = io::stderr();
+    loop {
+        write!(stderr, "{} [y/n/q] ", question)?;
+        stderr.flush()?;
+
+        let mut line = String::new();
+        if tty.read_line(&mut line)? == 0 {
+            bail!("Reached end of input while waiting for confirmation");
+        }
+        match line.trim() {
+            "y" | "Y" | "yes" => return Ok(Answer::Yes),
+            "n" | "N" | "no" => return Ok(Answer::No),
+            "q" | "Q" | "quit" => return Ok(Answer::Quit),
+            _ => continue,
+        }
+    }
+}
+
+/// Check if all characters of the filter appear in the same order in the text
+fn fuzzy_match(filter: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    filter
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|c| text.any(|t| t == c))
+}
+
+/// Let the user pick one of the items, typing text narrows down the list
+///
+/// Returns `None` if the user quit without selecting anything.
+pub fn pick(question: &str, items: &[String]) -> Result<Option<usize>> {
+    let tty = File::open("/dev/tty").context("Failed to open terminal for selection")?;
+    let mut tty = BufReader::new(tty);
+    let mut stderr = io::stderr();
+    let mut filter = String::new();
+    loop {
+        let matches = items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| fuzzy_match(&filter, item))
+            .collect::<Vec<_>>();
+
+        writeln!(stderr)?;
+        for (num, (_, item)) in matches.iter().enumerate() {
+            writeln!(stderr, "{:>4}) {}", num + 1, item)?;
+        }
+        if matches.is_empty() {
+            writeln!(stderr, "No matches for {:?}", filter)?;
+        }
+        write!(
+            stderr,
+            "{} [number, text to filter, empty to reset, q to quit] ",
+            question
+        )?;
+        stderr.flush()?;
+
+        let mut line = String::new();
+        if tty.read_line(&mut line)? == 0 {
+            bail!("Reached end of input while waiting for selection");
+        }
+        let line = line.trim();
+
+        if line == "q" {
+            return Ok(None);
+        } else if let Ok(num) = line.parse::<usize>() {
+            if let Some((idx, _)) = num.checked_sub(1).and_then(|n| matches.get(n)) {
+                return Ok(Some(*idx));
+            }
+            writeln!(stderr, "Invalid selection: {}", num)?;
+        } else {
+            filter = line.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let out = diff(
+            Path::new("PKGBUILD"),
+            b"pkgver=1\n_commit=old\n",
+            b"pkgver=1\n_commit=new\n",
+        );
+        assert_eq!(
+            out,
+            "--- PKGBUILD\n+++ PKGBUILD\n@@ -1,2 +1,2 @@\n pkgver=1\n-_commit=old\n+_commit=new\n"
+        );
+        assert_eq!(diff(Path::new("PKGBUILD"), b"a\n", b"a\n"), "");
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("", "v1.0.0"));
+        assert!(fuzzy_match("v11", "v1.1.0"));
+        assert!(fuzzy_match("RC", "v2.0.0-rc1"));
+        assert!(!fuzzy_match("v11", "v1.0.0"));
+        assert!(!fuzzy_match("0v", "v1.0.0-1"));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::errors::*;
+use crate::network;
+use std::env;
+use std::fs::{self, Permissions};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+pub const DEFAULT_KEYSERVER: &str = "hkps://keyserver.ubuntu.com";
+/// The keyring pacman uses, maintained by archlinux-keyring
+pub const ARCHLINUX_KEYRING: &str = "/etc/pacman.d/gnupg";
+
+fn gpg(homedir: Option<&Path>) -> Command {
+    let mut cmd = Command::new("gpg");
+    if let Some(homedir) = homedir {
+        cmd.arg("--homedir").arg(homedir);
+    }
+    cmd.args(["--batch", "--quiet"]).stdin(Stdio::null());
+    cmd
+}
+
+/// Read from a keyring owned by somebody else, eg. the system keyring
+fn gpg_readonly(homedir: &Path) -> Command {
+    let mut cmd = gpg(Some(homedir));
+    cmd.args([
+        "--no-permission-warning",
+        "--lock-never",
+        "--no-auto-check-trustdb",
+    ]);
+    cmd
+}
+
+/// Check if a key is in the keyring with full or ultimate validity, eg. not expired or revoked
+pub fn is_valid(homedir: &Path, fingerprint: &str) -> Result<bool> {
+    let output = gpg_readonly(homedir)
+        .args(["--with-colons", "--list-keys", "--"])
+        .arg(fingerprint)
+        .stderr(Stdio::null())
+        .output()
+        .context("Failed to run gpg")?;
+    if !output.status.success() {
+        return Ok(false);
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let validity = output
+        .lines()
+        .find(|line| line.starts_with("pub:"))
+        .and_then(|line| line.split(':').nth(1));
+    debug!(
+        "Validity of {:?} in {:?}: {:?}",
+        fingerprint, homedir, validity
+    );
+    Ok(matches!(validity, Some("f" | "u")))
+}
+
+/// `$XDG_DATA_HOME/updvcspins/gnupg`, or `~/.local/share/updvcspins/gnupg`
+pub fn default_path() -> Result<PathBuf> {
+    let dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var_os("HOME").context("Failed to determine data folder")?;
+            Path::new(&home).join(".local/share")
+        }
+    };
+    Ok(dir.join("updvcspins").join("gnupg"))
+}
+
+/// A public key as listed by gpg
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Key {
+    pub fingerprint: String,
+    /// Validity of the primary key, eg. `e` for expired or `r` for revoked
+    pub validity: String,
+    /// Seconds since unix epoch
+    pub expires: Option<u64>,
+    pub uids: Vec<String>,
+}
+
+/// Parse the output of `gpg --with-colons --list-keys`
+fn parse_keys(output: &str) -> Vec<Key> {
+    let mut keys: Vec<Key> = Vec::new();
+    let mut in_primary = false;
+    for line in output.lines() {
+        let fields = line.split(':').collect::<Vec<_>>();
+        let field = |idx: usize| fields.get(idx).copied().unwrap_or_default();
+        match field(0) {
+            "pub" => {
+                in_primary = true;
+                keys.push(Key {
+                    fingerprint: String::new(),
+                    validity: field(1).to_string(),
+                    expires: field(6).parse().ok(),
+                    uids: Vec::new(),
+                });
+            }
+            "sub" => in_primary = false,
+            "fpr" if in_primary => {
+                if let Some(key) = keys.last_mut().filter(|k| k.fingerprint.is_empty()) {
+                    key.fingerprint = field(9).to_string();
+                }
+            }
+            "uid" => {
+                if let Some(key) = keys.last_mut() {
+                    // the uid is escaped with C-style `\x3a` sequences for colons
+                    key.uids.push(field(9).replace("\\x3a", ":"));
+                }
+            }
+            _ => (),
+        }
+    }
+    keys
+}
+
+/// A gpg keyring used for verification, keys are added to it without touching the keyring of the user
+#[derive(Debug)]
+pub struct Keyring {
+    path: PathBuf,
+    /// Removed once the keyring is dropped, if temporary
+    _tmp: Option<TempDir>,
+}
+
+impl Keyring {
+    /// A temporary keyring that's deleted when dropped
+    pub fn new() -> Result<Self> {
+        let dir = tempfile::tempdir().context("Failed to create temporary keyring")?;
+        fs::set_permissions(dir.path(), Permissions::from_mode(0o700))?;
+        Ok(Keyring {
+            path: dir.path().to_owned(),
+            _tmp: Some(dir),
+        })
+    }
+
+    /// Open a persistent keyring, it's created if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path).with_context(|| anyhow!("Failed to create keyring {:?}", path))?;
+        fs::set_permissions(path, Permissions::from_mode(0o700))?;
+        Ok(Keyring {
+            path: path.to_owned(),
+            _tmp: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// List the keys with these fingerprints, keys that aren't in the keyring are skipped
+    pub fn list(&self, fingerprints: &[String]) -> Result<Vec<Key>> {
+        if fingerprints.is_empty() {
+            return Ok(Vec::new());
+        }
+        let output = gpg(Some(self.path()))
+            .args(["--with-colons", "--list-keys", "--"])
+            .args(fingerprints)
+            .stderr(Stdio::null())
+            .output()
+            .context("Failed to run gpg")?;
+        Ok(parse_keys(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Copy keys from another keyring, the default keyring of the user if `None`
+    pub fn import_from(&self, homedir: Option<&Path>, keys: &[String]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut export = match homedir {
+            Some(homedir) => gpg_readonly(homedir),
+            None => gpg(None),
+        };
+        let export = export
+            .arg("--export")
+            .args(keys)
+            .stderr(Stdio::null())
+            .output()
+            .context("Failed to run gpg")?;
+        if export.stdout.is_empty() {
+            return Ok(());
+        }
+        let mut import = gpg(Some(self.path()))
+            .arg("--import")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to run gpg")?;
+        let mut stdin = import.stdin.take().context("Failed to get stdin of gpg")?;
+        stdin.write_all(&export.stdout)?;
+        drop(stdin);
+        if !import.wait()?.success() {
+            bail!("Failed to import keys into temporary keyring");
+        }
+        Ok(())
+    }
+
+    /// Look up keys with WKD by email and download fingerprints from a keyserver
+    pub fn retrieve(
+        &self,
+        fingerprints: &[String],
+        emails: &[String],
+        keyserver: &str,
+    ) -> Result<()> {
+        for email in emails {
+            network::guard(&format!("WKD lookup of {}", email));
+            info!("Looking up key of {:?} with WKD", email);
+            let status = gpg(Some(self.path()))
+                .args(["--auto-key-locate", "clear,nodefault,wkd"])
+                .arg("--locate-external-keys")
+                .arg(email)
+                .stderr(Stdio::null())
+                .status()
+                .context("Failed to run gpg")?;
+            if !status.success() {
+                debug!("No key found with WKD for {:?}", email);
+            }
+        }
+
+        if fingerprints.is_empty() {
+            return Ok(());
+        }
+        network::guard(&format!("keyserver lookup on {}", keyserver));
+        info!("Receiving keys from {:?}: {:?}", keyserver, fingerprints);
+        let status = gpg(Some(self.path()))
+            .arg("--keyserver")
+            .arg(keyserver)
+            .arg("--recv-keys")
+            .args(fingerprints)
+            .status()
+            .context("Failed to run gpg")?;
+        if !status.success() {
+            warn!("Failed to receive some keys from {:?}", keyserver);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        let output = "tru::1:1760000000:1800000000:3:1:5
+pub:u:255:22:458D53A9E2970F44:1760000000:1855000000::u:::scESC:::::ed25519:::0:
+fpr:::::::::E653E0595456E28F37DC5999458D53A9E2970F44:
+grp:::::::::0123:
+uid:u::::1760000000::ABCD::T <t@e>::::::::::0:
+uid:u::::1760000000::ABCD::Work\\x3a T <t@w>::::::::::0:
+sub:u:255:18:1111111111111111:1760000000::::::e:::::cv25519::
+fpr:::::::::1111111111111111111111111111111111111111:
+pub:e:255:22:0000000000000000:1500000000:1600000000::-:::sc:::::ed25519:::0:
+fpr:::::::::0000000000000000000000000000000000000000:
+";
+        assert_eq!(
+            parse_keys(output),
+            vec![
+                Key {
+                    fingerprint: "E653E0595456E28F37DC5999458D53A9E2970F44".to_string(),
+                    validity: "u".to_string(),
+                    expires: Some(1855000000),
+                    uids: vec!["T <t@e>".to_string(), "Work: T <t@w>".to_string()],
+                },
+                Key {
+                    fingerprint: "0000000000000000000000000000000000000000".to_string(),
+                    validity: "e".to_string(),
+                    expires: Some(1600000000),
+                    uids: vec![],
+                },
+            ]
+        );
+    }
+}
@@ -1,4 +1,32 @@
+pub mod annotations;
 pub mod args;
+pub mod assets;
+pub mod cache;
+pub mod changelog;
+pub mod discover;
+pub mod distro;
 pub mod errors;
+pub mod events;
+pub mod file;
+pub mod forge;
 pub mod git;
+pub mod hooks;
+pub mod http;
+pub mod interactive;
+pub mod keyring;
+pub mod lock;
 pub mod makepkg;
+pub mod namcap;
+pub mod network;
+pub mod notify;
+pub mod osv;
+pub mod policy;
+pub mod progress;
+pub mod provenance;
+pub mod rekor;
+pub mod rewrite;
+pub mod signature;
+pub mod source;
+pub mod strategy;
+pub mod timings;
+pub mod vercmp;
@@ -0,0 +1,174 @@
+use crate::errors::*;
+use crate::file;
+use crate::git;
+use crate::makepkg::ResolvedPin;
+use crate::source::{Input, Source};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const FILENAME: &str = "vcspins.lock";
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct LockedPin {
+    pub url: String,
+    pub tag: Option<String>,
+    pub branch: Option<String>,
+    pub tag_hash: Option<String>,
+    pub commit_hash: String,
+}
+
+impl LockedPin {
+    fn from_pin(pin: &ResolvedPin) -> Result<Self> {
+        let Source::Git(git) = &pin.source else {
+            bail!("Only git sources can be locked: {}", pin.source);
+        };
+        Ok(LockedPin {
+            url: git.url.clone(),
+            tag: git.tag.clone(),
+            branch: git.branch.clone(),
+            tag_hash: pin.tag_hash.clone(),
+            commit_hash: pin.commit_hash.clone(),
+        })
+    }
+}
+
+/// The resolved pins of a PKGBUILD, by source filename
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub pins: BTreeMap<String, LockedPin>,
+}
+
+impl Lockfile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let buf = fs::read(path).with_context(|| anyhow!("Failed to read lockfile {:?}", path))?;
+        serde_json::from_slice(&buf).with_context(|| anyhow!("Failed to parse lockfile {:?}", path))
+    }
+
+    /// Load the lockfile if it exists, or start with an empty one
+    pub fn load_or_default(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut buf = serde_json::to_vec_pretty(self)?;
+        buf.push(b'\n');
+        file::atomic_write(path, &buf, false)
+            .with_context(|| anyhow!("Failed to write lockfile {:?}", path))
+    }
+
+    pub fn update(&mut self, resolved_pins: &BTreeMap<String, ResolvedPin>) -> Result<()> {
+        for (filename, pin) in resolved_pins {
+            self.pins
+                .insert(filename.clone(), LockedPin::from_pin(pin)?);
+        }
+        Ok(())
+    }
+
+    /// Make sure the resolved pins are the same as the locked ones
+    pub fn verify_pins(&self, resolved_pins: &BTreeMap<String, ResolvedPin>) -> Result<()> {
+        for (filename, pin) in resolved_pins {
+            let Some(locked) = self.pins.get(filename) else {
+                bail!("{:?} is missing from {}", filename, FILENAME);
+            };
+            if *locked != LockedPin::from_pin(pin)? {
+                bail!(
+                    "{:?} resolved to {:?} but {} has {:?}",
+                    filename,
+                    pin.commit_hash,
+                    FILENAME,
+                    locked.commit_hash
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure the sources of the PKGBUILD are pinned the way the lockfile says
+    pub fn verify_sources(&self, sources: &BTreeMap<String, Vec<Input>>) -> Result<()> {
+        for (filename, locked) in &self.pins {
+            let mut found = false;
+            for input in sources.values().flatten() {
+                if input.filename()? != filename.as_str() {
+                    continue;
+                }
+                let Source::Git(git) = input.source() else {
+                    bail!("Source of locked {:?} is not a git source", filename);
+                };
+                if git::normalize_url(&git.url) != git::normalize_url(&locked.url) {
+                    bail!(
+                        "Url of {:?} differs from {}: {:?}",
+                        filename,
+                        FILENAME,
+                        git.url
+                    );
+                }
+                let matches = match (&git.tag, &git.commit) {
+                    (Some(tag), _) => locked.tag_hash.as_ref() == Some(tag),
+                    (None, Some(commit)) => *commit == locked.commit_hash,
+                    (None, None) => false,
+                };
+                if !matches {
+                    bail!(
+                        "Source of {:?} isn't pinned to the commit in {}: {}",
+                        filename,
+                        FILENAME,
+                        input
+                    );
+                }
+                found = true;
+            }
+            if !found {
+                bail!("Locked {:?} is not used by any source", filename);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let pins = BTreeMap::from([(
+            "foo".to_string(),
+            ResolvedPin {
+                commit_hash: "c0ffee".to_string(),
+                tag_hash: Some("7a9".to_string()),
+                annotation: None,
+                source: "git+https://example.com/foo.git#tag=v1".parse().unwrap(),
+            },
+        )]);
+        let mut lockfile = Lockfile::default();
+        lockfile.update(&pins).unwrap();
+        lockfile.verify_pins(&pins).unwrap();
+
+        let sources =
+            |source: &str| BTreeMap::from([("source".to_string(), vec![source.parse().unwrap()])]);
+        for source in [
+            "git+https://example.com/foo.git#tag=7a9",
+            "git+https://example.com/foo#commit=c0ffee?signed",
+        ] {
+            lockfile.verify_sources(&sources(source)).unwrap();
+        }
+        for source in [
+            "git+https://example.com/foo.git#tag=v1",
+            "git+https://example.com/foo.git#commit=7a9",
+            "git+https://example.org/foo.git#commit=c0ffee",
+            "git+https://example.com/bar.git#commit=c0ffee",
+        ] {
+            assert!(lockfile.verify_sources(&sources(source)).is_err());
+        }
+
+        let mut moved = lockfile.clone();
+        moved.pins.get_mut("foo").unwrap().commit_hash = "f00".to_string();
+        assert!(moved.verify_pins(&pins).is_err());
+    }
+}
@@ -1,125 +1,2381 @@
 use clap::Parser;
 use env_logger::Env;
-use std::collections::HashMap;
-use std::fmt::Write;
+use log::LevelFilter;
+use std::cmp::Ordering;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
 use std::fs;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use updvcspins::args::Args;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use updvcspins::annotations;
+use updvcspins::args::{
+    Annotations, Args, Check, DiffFormat, FailOn, Keys, KeysImport, LogFormat, Migrate, PinStyle,
+    SubCommand,
+};
+use updvcspins::assets;
+use updvcspins::cache::Cache;
+use updvcspins::changelog;
+use updvcspins::discover;
+use updvcspins::distro;
 use updvcspins::errors::*;
-use updvcspins::git;
-use updvcspins::makepkg;
-use updvcspins::makepkg::Source;
+use updvcspins::events::{LogObserver, Observer};
+use updvcspins::file;
+use updvcspins::forge::{self, Forge};
+use updvcspins::git::{self, GitSource};
+use updvcspins::hooks::{self, Hook};
+use updvcspins::interactive::{self, Answer};
+use updvcspins::keyring::{self, Keyring};
+use updvcspins::lock::{self, Lockfile};
+use updvcspins::makepkg::{self, Backend, ResolvedPin};
+use updvcspins::namcap;
+use updvcspins::network;
+use updvcspins::notify;
+use updvcspins::osv;
+use updvcspins::policy::Policy;
+use updvcspins::progress::ProgressObserver;
+use updvcspins::provenance::{self, Resolver};
+use updvcspins::rekor;
+use updvcspins::rewrite;
+use updvcspins::signature;
+use updvcspins::source::{Input, Source};
+use updvcspins::strategy;
+use updvcspins::timings::{TimingObserver, Timings};
+use updvcspins::vercmp;
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn pick_tag(name: &str, repo_path: &Path, prefix: Option<&str>) -> Result<Option<String>> {
+    let tags = git::list_tags(repo_path)?
+        .into_iter()
+        .filter(|tag| prefix.is_none_or(|prefix| tag.name.starts_with(prefix)))
+        .collect::<Vec<_>>();
+    if tags.is_empty() {
+        match prefix {
+            Some(prefix) => bail!(
+                "Repository has no tags starting with {:?}: {:?}",
+                prefix,
+                repo_path
+            ),
+            None => bail!("Repository has no tags: {:?}", repo_path),
+        }
+    }
 
-    let log_level = match args.verbose {
-        0 => "warn",
-        1 => "info",
-        2 => "debug",
-        _ => "trace",
+    let items = tags
+        .iter()
+        .map(|tag| {
+            let mut item = tag.name.clone();
+            if let Some(time) = tag.time {
+                let time = UNIX_EPOCH + Duration::from_secs(time.into());
+                let date = humantime::format_rfc3339(time).to_string();
+                write!(item, "  ({})", &date[..10])?;
+            }
+            if let Some(summary) = tag.summary() {
+                write!(item, "  {}", summary)?;
+            }
+            Ok(item)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let idx = interactive::pick(&format!("Select tag for {:?}", name), &items)?;
+    Ok(idx.map(|idx| tags[idx].name.clone()))
+}
+
+fn read_pkgbuild(args: &Args) -> Result<Vec<u8>> {
+    if args.pkgbuild == Path::new("-") {
+        if args.no_confirm && io::stdin().is_terminal() {
+            bail!("Refusing to read PKGBUILD from a terminal with --no-confirm");
+        }
+        let mut buf = Vec::new();
+        io::stdin()
+            .read_to_end(&mut buf)
+            .context("Failed to read PKGBUILD from stdin")?;
+        Ok(buf)
+    } else {
+        fs::read(&args.pkgbuild)
+            .with_context(|| anyhow!("Failed to read PKGBUILD at {:?}", args.pkgbuild))
+    }
+}
+
+fn repos_folder(args: &Args) -> Result<&Path> {
+    if let Some(directory) = &args.directory {
+        Ok(directory.as_path())
+    } else if args.pkgbuild == Path::new("-") {
+        bail!("Reading the PKGBUILD from stdin requires --directory to locate the repositories");
+    } else {
+        args.pkgbuild
+            .parent()
+            .map(|parent| {
+                if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                }
+            })
+            .context("Failed to determine parent folder")
+    }
+}
+
+/// The first option that reads the local clone, these conflict with `--ls-remote` as well
+fn needs_clone(args: &Args) -> Option<&'static str> {
+    [
+        (args.pick_tag, "--pick-tag"),
+        (args.vcs_pkgver, "--vcs-pkgver"),
+        (args.describe_pkgver, "--describe-pkgver"),
+        (args.run_pkgver, "--run-pkgver"),
+        (args.changelog, "--changelog"),
+        (args.sync_submodules, "--sync-submodules"),
+        (args.verify_signatures, "--verify-signatures"),
+        (!args.require_tags.is_empty(), "--require-tag"),
+        (!args.commit_overrides.is_empty(), "--commit"),
+        (!args.paths.is_empty(), "--path"),
+        (args.as_of.is_some(), "--as-of"),
+        (args.osv, "--osv"),
+        (args.interactive, "--interactive"),
+    ]
+    .into_iter()
+    .find(|(set, _)| *set)
+    .map(|(_, flag)| flag)
+}
+
+/// The folder of the PKGBUILD, used as startdir for makepkg
+fn pkgbuild_folder(args: &Args) -> &Path {
+    match args.pkgbuild.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && args.pkgbuild != Path::new("-") => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// The value of a `[NAME=]VALUE` option for a repository, per-repository settings take precedence
+fn per_repo<'a>(values: &'a [String], filename: &str) -> Option<&'a str> {
+    let specific = values.iter().find_map(|v| {
+        let (name, value) = v.split_once('=')?;
+        (name == filename).then_some(value)
+    });
+    specific.or_else(|| values.iter().find(|v| !v.contains('=')).map(String::as_str))
+}
+
+/// The tag prefix configured for a repository
+fn tag_prefix<'a>(args: &'a Args, filename: &str) -> Option<&'a str> {
+    per_repo(&args.tag_prefixes, filename)
+}
+
+/// The pins to add or remove `?signed` for, from --require-signed and --unsigned
+fn signed_toggles(args: &Args) -> Result<BTreeMap<String, bool>> {
+    let mut signed = BTreeMap::new();
+    for name in &args.require_signed {
+        signed.insert(name.clone(), true);
+    }
+    for name in &args.unsigned {
+        if signed.insert(name.clone(), false).is_some() {
+            bail!(
+                "Can't use both --require-signed and --unsigned for {:?}",
+                name
+            );
+        }
+    }
+    Ok(signed)
+}
+
+/// Tags are selected by name, moving their pin to a different commit isn't possible
+fn is_tag(pin: &ResolvedPin) -> bool {
+    pin.tag_hash.is_some() || matches!(&pin.source, Source::Git(git) if git.tag.is_some())
+}
+
+/// Move the pin of a branch back to its newest commit that's not later than a time, for --as-of
+fn pin_as_of(
+    filename: &str,
+    pin: &mut ResolvedPin,
+    repo_path: &Path,
+    time: SystemTime,
+) -> Result<()> {
+    if is_tag(pin) {
+        bail!(
+            "{:?} is pinned to a tag, --as-of only applies to branches",
+            filename
+        );
+    }
+    let commit = git::last_commit_before(repo_path, &pin.commit_hash, time).with_context(|| {
+        anyhow!(
+            "Failed to find the commit of {:?} as of the given time",
+            filename
+        )
+    })?;
+    if commit != pin.commit_hash {
+        info!(
+            "Pinning {:?} to {} instead of {}, the newest commit as of {}",
+            filename,
+            commit,
+            pin.commit_hash,
+            humantime::format_rfc3339(time)
+        );
+        pin.commit_hash = commit;
+    }
+    Ok(())
+}
+
+/// Move the pin of a branch back to the newest commit that touches a path, for --path
+fn scope_to_path(
+    filename: &str,
+    pin: &mut ResolvedPin,
+    repo_path: &Path,
+    path: &str,
+) -> Result<()> {
+    if is_tag(pin) {
+        bail!(
+            "{:?} is pinned to a tag, use --tag-prefix to select the tags of a component instead of --path",
+            filename
+        );
+    }
+    let commit = git::last_commit_touching(repo_path, &pin.commit_hash, path)
+        .with_context(|| anyhow!("Failed to scope {:?} to {:?}", filename, path))?;
+    if commit != pin.commit_hash {
+        info!(
+            "Pinning {:?} to {} instead of {}, the newest commit touching {:?}",
+            filename, commit, pin.commit_hash, path
+        );
+        pin.commit_hash = commit;
+    }
+    Ok(())
+}
+
+/// Enforce --require-tag, and reject lightweight tags for `?signed` sources since makepkg
+/// runs `git verify-tag` on them
+fn check_tags(args: &Args, resolved_pins: &BTreeMap<String, ResolvedPin>) -> Result<()> {
+    for (filename, pin) in resolved_pins {
+        if let Some(required) = per_repo(&args.require_tags, filename) {
+            let required = required.parse::<signature::RequiredTag>()?;
+            required
+                .check(pin)
+                .with_context(|| anyhow!("Refusing to pin {:?}", filename))?;
+        }
+        let Source::Git(git) = &pin.source else {
+            continue;
+        };
+        if git.signed && pin.is_lightweight_tag() && !args.pin_commit {
+            bail!(
+                "Source of {:?} is ?signed but {:?} is a lightweight tag without a signature, use --pin-commit to verify the commit instead",
+                filename,
+                git.tag.as_deref().unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The commit a repository is pinned to with --commit, if any
+fn commit_override<'a>(args: &'a Args, filename: &str) -> Option<&'a str> {
+    args.commit_overrides.iter().find_map(|o| {
+        let (name, hash) = o.split_once('=')?;
+        (name == filename).then_some(hash)
+    })
+}
+
+/// Collect the settings of self-hosted forges from --forge and --forge-token
+fn forge_hosts(args: &Args) -> Result<forge::Hosts> {
+    let mut hosts = forge::Hosts::new();
+    for f in &args.forges {
+        let Some((host, kind)) = f.split_once('=') else {
+            bail!("Expected --forge to be HOST=KIND[:URL]: {:?}", f);
+        };
+        let (kind, api_url) = match kind.split_once(':') {
+            Some((kind, url)) => (kind, Some(url.to_string())),
+            None => (kind, None),
+        };
+        let entry = hosts.entry(host.to_string()).or_default();
+        entry.kind = Some(kind.parse()?);
+        entry.api_url = api_url;
+    }
+    for t in &args.forge_tokens {
+        let Some((host, path)) = t.split_once('=') else {
+            bail!("Expected --forge-token to be HOST=FILE: {:?}", t);
+        };
+        let token = fs::read_to_string(path)
+            .with_context(|| anyhow!("Failed to read token file {:?}", path))?;
+        hosts.entry(host.to_string()).or_default().token = Some(token.trim().to_string());
+    }
+    Ok(hosts)
+}
+
+fn observer(args: &Args) -> Box<dyn Observer> {
+    if ProgressObserver::is_supported() && args.log_format == LogFormat::Plain {
+        Box::new(ProgressObserver::new())
+    } else {
+        Box::new(LogObserver)
+    }
+}
+
+fn backend(args: &Args, pkgbuild: &[u8]) -> Result<Backend> {
+    if args.srcinfo {
+        Backend::srcinfo(pkgbuild).context("Failed to generate .SRCINFO")
+    } else {
+        Backend::bash(pkgbuild).context("Failed to read variables from PKGBUILD")
+    }
+}
+
+fn git_options(args: &Args) -> git::Options {
+    git::Options {
+        fetch: args.fetch,
+        non_interactive: args.no_confirm,
+        partial_clone: args.partial_clone,
+        ls_remote: args.ls_remote,
+    }
+}
+
+/// Returns the new epoch if the pkgver went backwards and --bump-epoch is set
+fn check_epoch(
+    args: &Args,
+    pkgbuild: &[u8],
+    backend: &Backend,
+    new_pkgver: &str,
+) -> Result<Option<u32>> {
+    let Some(old_pkgver) = backend.list_variable(pkgbuild, "pkgver")?.pop() else {
+        return Ok(None);
+    };
+    if vercmp::vercmp(new_pkgver, &old_pkgver) != Ordering::Less {
+        return Ok(None);
+    }
+    if !args.bump_epoch {
+        bail!(
+            "New pkgver {:?} is lower than the current pkgver {:?}, use --bump-epoch to increment epoch=",
+            new_pkgver,
+            old_pkgver
+        );
+    }
+
+    let epoch = match backend.list_variable(pkgbuild, "epoch")?.pop() {
+        Some(epoch) => epoch
+            .parse::<u32>()
+            .with_context(|| anyhow!("Invalid epoch in PKGBUILD: {:?}", epoch))?,
+        None => 0,
+    };
+    let epoch = epoch + 1;
+    info!(
+        "New pkgver {:?} is lower than {:?}, bumping epoch to {}",
+        new_pkgver, old_pkgver, epoch
+    );
+    Ok(Some(epoch))
+}
+
+/// The commit or tag a source was pinned to before the update
+fn previous_pin(sources: &BTreeMap<String, Vec<Input>>, filename: &str) -> Option<String> {
+    sources.values().flatten().find_map(|input| {
+        if input.filename().ok()? != filename {
+            return None;
+        }
+        let Source::Git(git) = input.source() else {
+            return None;
+        };
+        git.commit.clone().or_else(|| git.tag.clone())
+    })
+}
+
+/// Verify the signatures makepkg is going to check for `?signed` sources, for the ref that is written
+fn verify_signatures(
+    args: &Args,
+    folder: &Path,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    policy: &signature::Policy,
+) -> Result<(BTreeMap<String, signature::Object>, Vec<String>)> {
+    let mut verified = BTreeMap::new();
+    let mut signer_keys = Vec::<String>::new();
+    let mut retrieved: Option<(Keyring, signature::Policy)> = None;
+    for (filename, pin) in resolved_pins {
+        let Source::Git(git) = &pin.source else {
+            continue;
+        };
+        if !git.signed {
+            debug!("Not verifying signature of unsigned source {:?}", filename);
+            continue;
+        }
+        let (object, hash) = match &pin.tag_hash {
+            Some(tag_hash) if !args.pin_commit => (signature::Object::Tag, tag_hash),
+            _ => (signature::Object::Commit, &pin.commit_hash),
+        };
+        let repo_path = folder.join(filename);
+        let policy = retrieved.as_ref().map_or(policy, |(_, policy)| policy);
+        let signer = match signature::verify(&repo_path, object, hash, policy) {
+            Err(err)
+                if err.is::<signature::MissingKey>()
+                    && (args.auto_key_retrieve || !policy.trusted_keyrings.is_empty()) =>
+            {
+                info!("{}, looking for it in other keyrings", err);
+                let keyring = Keyring::new()?;
+                keyring.import_from(None, &policy.validpgpkeys)?;
+                if let Some(signature::MissingKey(key)) = err.downcast_ref() {
+                    for trusted in &policy.trusted_keyrings {
+                        keyring.import_from(Some(trusted), std::slice::from_ref(key))?;
+                    }
+                }
+                if args.auto_key_retrieve {
+                    let emails = pin
+                        .annotation
+                        .iter()
+                        .flat_map(|a| a.tagger.as_deref())
+                        .filter_map(|tagger| tagger.rsplit_once('<'))
+                        .map(|(_, email)| email.trim_end_matches('>').to_string())
+                        .collect::<Vec<_>>();
+                    keyring.retrieve(&policy.validpgpkeys, &emails, &args.keyserver)?;
+                }
+                let policy = signature::Policy {
+                    gnupghome: Some(keyring.path().to_owned()),
+                    ..policy.clone()
+                };
+                let signer = signature::verify(&repo_path, object, hash, &policy);
+                retrieved = Some((keyring, policy));
+                signer
+            }
+            signer => signer,
+        };
+        let signer = match signer {
+            Err(err) => match err.downcast_ref::<signature::UntrustedKey>() {
+                Some(signature::UntrustedKey(key)) if signer_keys.contains(key) => {
+                    Ok(signature::Signer::OpenPgp(key.clone()))
+                }
+                Some(signature::UntrustedKey(key)) if accept_signer_key(args, filename, key)? => {
+                    info!("Adding {} to validpgpkeys=", key);
+                    signer_keys.push(key.clone());
+                    Ok(signature::Signer::OpenPgp(key.clone()))
+                }
+                _ => Err(err),
+            },
+            signer => signer,
+        }
+        .with_context(|| anyhow!("Failed to verify signature of {:?}", filename))?;
+        info!("Verified signature of {:?} by {}", filename, signer);
+        verified.insert(filename.clone(), object);
+    }
+    Ok((verified, signer_keys))
+}
+
+/// Decide if the key of a valid signature should be added to validpgpkeys=
+fn accept_signer_key(args: &Args, filename: &str, key: &str) -> Result<bool> {
+    if args.add_signer_key {
+        return Ok(true);
+    }
+    if !args.interactive || args.yes {
+        return Ok(false);
+    }
+    let question = format!(
+        "{:?} is signed by {} which is not listed in validpgpkeys=, add it?",
+        filename, key
+    );
+    match interactive::confirm(&question)? {
+        Answer::Yes => Ok(true),
+        Answer::No => Ok(false),
+        Answer::Quit => bail!("Aborting"),
+    }
+}
+
+/// Find the previously pinned commits that can't be reached from any branch or tag anymore
+///
+/// Only repositories that were fetched or cloned during this run are checked.
+fn rewritten_history(
+    folder: &Path,
+    sources: &BTreeMap<String, Vec<Input>>,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    fetched: &BTreeSet<String>,
+) -> Result<Vec<(String, String, git::Reachability)>> {
+    let mut rewritten = Vec::new();
+    for filename in resolved_pins.keys() {
+        let Some(old) = previous_pin(sources, filename) else {
+            continue;
+        };
+        if !fetched.contains(filename) {
+            debug!(
+                "Skipping history check of {:?}, the repository wasn't fetched",
+                filename
+            );
+            continue;
+        }
+        // tag names can't be checked, a tag that doesn't resolve may have never existed
+        if !old.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        let repo_path = folder.join(filename);
+        match git::reachability(&repo_path, &old)? {
+            git::Reachability::Reachable => (),
+            reachability => rewritten.push((filename.clone(), old, reachability)),
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Shorten a commit hash for display, names are kept as-is
+fn short_ref(rev: &str) -> &str {
+    if rev.len() > 10 && rev.bytes().all(|b| b.is_ascii_hexdigit()) {
+        &rev[..10]
+    } else {
+        rev
+    }
+}
+
+/// The previous pin as `v1.2.3 (0123456789)` if the tag is known, or the shortened hash
+fn describe_previous(repo_path: &Path, old: &str) -> String {
+    let commit = repo_path
+        .exists()
+        .then(|| git::resolve_commit(repo_path, old).ok())
+        .flatten();
+    let Some(commit) = commit else {
+        return short_ref(old).to_string();
+    };
+    let name = if old.bytes().all(|b| b.is_ascii_hexdigit()) {
+        git::list_tags(repo_path)
+            .ok()
+            .and_then(|tags| tags.into_iter().find(|tag| tag.tag_hash == old))
+            .map(|tag| tag.name)
+    } else {
+        Some(old.to_string())
     };
-    env_logger::init_from_env(Env::default().default_filter_or(log_level));
+    match name {
+        Some(name) => format!("{} ({})", name, short_ref(&commit)),
+        None => short_ref(&commit).to_string(),
+    }
+}
 
-    fs::metadata(&args.pkgbuild)
-        .with_context(|| anyhow!("Failed to access PKGBUILD at {:?}", args.pkgbuild))?;
+/// The new pin as `v1.2.3 (0123456789)`, or `main (0123456789)` for branches
+fn describe_pin(pin: &ResolvedPin) -> String {
+    let name = match &pin.source {
+        Source::Git(git) => git.tag.as_deref().or(git.branch.as_deref()),
+        _ => None,
+    };
+    match name {
+        Some(name) => format!("{} ({})", name, short_ref(&pin.commit_hash)),
+        None => short_ref(&pin.commit_hash).to_string(),
+    }
+}
 
-    let vcspins = makepkg::list_pins(&args.pkgbuild).context("Failed to get pins from PKGBUILD")?;
+/// The hash that's written to the source of a pin
+fn written_ref(pin: &ResolvedPin, pin_commit: bool) -> &str {
+    match &pin.tag_hash {
+        Some(tag_hash) if !pin_commit => tag_hash,
+        _ => &pin.commit_hash,
+    }
+}
+
+/// The entry for --changelog-file, pins that didn't change are left out
+fn changelog_entry(
+    folder: &Path,
+    previous: &BTreeMap<String, String>,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    pin_commit: bool,
+    old_version: String,
+    new_version: String,
+) -> Result<changelog::Entry> {
+    let mut pins = Vec::new();
+    for (filename, pin) in resolved_pins {
+        let old = previous.get(filename);
+        if old.is_some_and(|old| old == written_ref(pin, pin_commit)) {
+            continue;
+        }
+        let repo_path = folder.join(filename);
+        let mut commits = Vec::new();
+        if let Some(old) = old.filter(|_| repo_path.exists()) {
+            if let Ok(old_commit) = git::resolve_commit(&repo_path, old) {
+                commits = git::changelog(&repo_path, &old_commit, &pin.commit_hash)?
+                    .into_iter()
+                    .map(|entry| entry.summary)
+                    .collect();
+            }
+        }
+        pins.push(changelog::PinChange {
+            name: filename.clone(),
+            old: old.map(|old| describe_previous(&repo_path, old)),
+            new: describe_pin(pin),
+            commits,
+        });
+    }
+    let now = humantime::format_rfc3339(SystemTime::now()).to_string();
+    Ok(changelog::Entry {
+        date: now[..10].to_string(),
+        old_version,
+        new_version,
+        pins,
+    })
+}
+
+/// The page of the forge that compares the previous and the new commit of a pin, if they differ
+fn compare_url(hosts: &forge::Hosts, pin: &ResolvedPin, old_commit: &str) -> Option<String> {
+    let Source::Git(git) = &pin.source else {
+        return None;
+    };
+    if old_commit == pin.commit_hash {
+        return None;
+    }
+    let forge = Forge::detect(git.remote_url(), hosts)?;
+    Some(forge.compare_url(old_commit, &pin.commit_hash))
+}
+
+/// A link that can be clicked in terminals that support OSC 8, others only show the text
+fn hyperlink(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// Open the pages of the forge for changed pins with xdg-open, for --open
+fn open_pages(
+    folder: &Path,
+    previous: &BTreeMap<String, String>,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    pin_commit: bool,
+    hosts: &forge::Hosts,
+) {
+    for (filename, pin) in resolved_pins {
+        let url = match previous.get(filename) {
+            Some(old) if old == written_ref(pin, pin_commit) => continue,
+            Some(old) => {
+                let old_commit = git::resolve_commit(&folder.join(filename), old);
+                compare_url(hosts, pin, old_commit.as_deref().unwrap_or(old))
+            }
+            None => match &pin.source {
+                Source::Git(git) => git.tag.as_ref().and_then(|tag| {
+                    Forge::detect(git.remote_url(), hosts).map(|forge| forge.release_page(tag))
+                }),
+                _ => None,
+            },
+        };
+        let Some(url) = url else {
+            debug!("No page to open for {:?}", filename);
+            continue;
+        };
+        info!("Opening {:?}", url);
+        let status = Command::new("xdg-open")
+            .arg(&url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => (),
+            Ok(status) => warn!("Failed to open {:?}, xdg-open exited with {}", url, status),
+            Err(err) => warn!("Failed to open {:?}, could not run xdg-open: {}", url, err),
+        }
+    }
+}
+
+/// Print what happened to each pin, eg. `foo: v1.2.3 (0123456789) → v1.3.0 (abcdef0123)`
+fn print_summary(
+    folder: &Path,
+    previous: &BTreeMap<String, String>,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    verified: &BTreeMap<String, signature::Object>,
+    pin_commit: bool,
+    hosts: &forge::Hosts,
+) {
+    for (filename, pin) in resolved_pins {
+        let new = describe_pin(pin);
+        let mut line = match previous.get(filename) {
+            Some(old) => {
+                let repo_path = folder.join(filename);
+                if old == written_ref(pin, pin_commit) {
+                    format!("{}: {} (unchanged)", filename, new)
+                } else {
+                    let mut line = format!(
+                        "{}: {} → {}",
+                        filename,
+                        describe_previous(&repo_path, old),
+                        new
+                    );
+                    let old_commit = git::resolve_commit(&repo_path, old);
+                    let old_commit = old_commit.as_deref().unwrap_or(old);
+                    if let Some(url) = compare_url(hosts, pin, old_commit) {
+                        if io::stderr().is_terminal() {
+                            write!(line, " [{}]", hyperlink(&url, "compare")).ok();
+                        } else {
+                            write!(line, " {}", url).ok();
+                        }
+                    }
+                    line
+                }
+            }
+            None => format!("{}: {}", filename, new),
+        };
+        match verified.get(filename) {
+            Some(signature::Object::Tag) => line.push_str(" [tag signature: ok]"),
+            Some(signature::Object::Commit) => line.push_str(" [commit signature: ok]"),
+            None => (),
+        }
+        eprintln!("{}", line);
+    }
+}
+
+fn print_changelog(
+    filename: &str,
+    old: &str,
+    log: &[git::LogEntry],
+    stat: &git::DiffStat,
+    compare_url: Option<&str>,
+) {
+    eprintln!(
+        "{}: {} new commit(s), {} since {}",
+        filename,
+        log.len(),
+        stat,
+        old
+    );
+    if let Some(url) = compare_url {
+        eprintln!("    {}", url);
+    }
+    for entry in log {
+        let short = &entry.commit_hash[..entry.commit_hash.len().min(10)];
+        eprintln!("    {} {}", short, entry.summary);
+    }
+}
+
+fn print_vulns(filename: &str, label: &str, rev: &str, vulns: &[osv::Vulnerability]) {
+    eprintln!(
+        "{}: {} known vulnerabilities {} {}",
+        filename,
+        vulns.len(),
+        label,
+        rev
+    );
+    for vuln in vulns {
+        let mut line = vuln.id.clone();
+        if !vuln.aliases.is_empty() {
+            line.push_str(&format!(" ({})", vuln.aliases.join(", ")));
+        }
+        if let Some(summary) = &vuln.summary {
+            line.push_str(&format!(": {}", summary));
+        }
+        eprintln!("    {}", line);
+    }
+}
+
+/// Resolve the repositories with a small pool of threads, results are returned in the original order
+fn resolve_parallel<F>(
+    jobs: Vec<(String, GitSource)>,
+    folder: &Path,
+    resolve: F,
+    timings: &Timings,
+    threads: usize,
+) -> Vec<(String, Result<(ResolvedPin, Resolver)>)>
+where
+    F: Fn(GitSource, &Path) -> Result<(ResolvedPin, Resolver)> + Sync,
+{
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let Some((idx, (filename, git))) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let repo_path = folder.join(&filename);
+                let resolved =
+                    timings.measure(format!("resolve {}", filename), || resolve(git, &repo_path));
+                results.lock().unwrap().push((idx, filename, resolved));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(idx, _, _)| *idx);
+    results
+        .into_iter()
+        .map(|(_, filename, resolved)| (filename, resolved))
+        .collect()
+}
+
+/// Find abbreviated commit hashes of sources that aren't pinned and expand them to full hashes
+fn expand_commits(
+    folder: &Path,
+    sources: &BTreeMap<String, Vec<Input>>,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    git_options: &git::Options,
+    observer: &dyn Observer,
+) -> Result<BTreeMap<(String, String), String>> {
+    let mut commits = BTreeMap::new();
+    for input in sources.values().flatten() {
+        let Source::Git(git) = input.source() else {
+            continue;
+        };
+        let Some(commit) = &git.commit else {
+            continue;
+        };
+        let filename = input.filename()?;
+        if git::is_full_hash(commit) || resolved_pins.contains_key(&*filename) {
+            continue;
+        }
+        let repo_path = folder.join(&*filename);
+        git::ensure_repo(git, &repo_path, git_options, observer)?;
+        if let Some(full) = git::expand_commit(&repo_path, commit)? {
+            info!("Expanding abbreviated commit {:?} to {:?}", commit, full);
+            commits.insert((filename.into_owned(), commit.clone()), full);
+        }
+    }
+    Ok(commits)
+}
+
+/// Pin the sources of submodules to the commits recorded in the pinned superprojects
+fn sync_submodules(
+    folder: &Path,
+    sources: &BTreeMap<String, Vec<Input>>,
+    resolved_pins: &mut BTreeMap<String, ResolvedPin>,
+) -> Result<()> {
+    let mut superprojects = resolved_pins
+        .iter()
+        .filter_map(|(filename, pin)| match &pin.source {
+            Source::Git(git) => Some((
+                filename.clone(),
+                git.remote_url().to_string(),
+                pin.commit_hash.clone(),
+            )),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    superprojects.sort();
+
+    for (filename, remote_url, commit_hash) in superprojects {
+        for submodule in git::submodules(&folder.join(&filename), &commit_hash)? {
+            let url = git::submodule_url(&remote_url, &submodule.url);
+            let input = sources
+                .values()
+                .flatten()
+                .find(|input| match input.source() {
+                    Source::Git(git) => git::normalize_url(&git.url) == git::normalize_url(&url),
+                    _ => false,
+                });
+            let Some(input) = input else {
+                debug!(
+                    "No source for submodule {:?} of {:?}",
+                    submodule.name, filename
+                );
+                continue;
+            };
+            let sub_filename = input.filename()?.into_owned();
+            if resolved_pins.contains_key(&sub_filename) {
+                warn!(
+                    "Submodule {:?} is also listed in vcspins, not overriding",
+                    sub_filename
+                );
+                continue;
+            }
+            info!(
+                "Pinning submodule {:?} of {:?} to commit {:?}",
+                sub_filename, filename, submodule.commit_hash
+            );
+
+            resolved_pins.insert(
+                sub_filename,
+                ResolvedPin {
+                    commit_hash: submodule.commit_hash,
+                    tag_hash: None,
+                    annotation: None,
+                    source: input.source().clone(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+fn lookup(args: &Args) -> Result<()> {
+    let pkgbuild = read_pkgbuild(args)?;
+    let backend = backend(args, &pkgbuild)?;
+    let sources = makepkg::list_source_arrays(&pkgbuild, &backend)
+        .context("Failed to get sources from PKGBUILD")?;
+    let folder = repos_folder(args)?;
+    let observer = observer(args);
+    let git_options = git_options(args);
+
+    let mut stdout = io::stdout();
+    for input in sources.into_values().flatten() {
+        let filename = input.filename()?.to_string();
+        let Source::Git(git) = input.take_source() else {
+            continue;
+        };
+        let Some(commit) = &git.commit else {
+            debug!(
+                "Skipping source that isn't pinned to a commit: {:?}",
+                filename
+            );
+            continue;
+        };
+
+        let repo_path = folder.join(&filename);
+        git::ensure_repo(&git, &repo_path, &git_options, observer.as_ref())?;
+        match git::find_tag_for_commit(&repo_path, commit)? {
+            git::TagLookup::Exact(tags) => {
+                let names = tags.iter().map(|t| t.name.as_str()).collect::<Vec<_>>();
+                writeln!(
+                    stdout,
+                    "{}: {} is tagged as {}",
+                    filename,
+                    commit,
+                    names.join(", ")
+                )?;
+            }
+            git::TagLookup::ContainedIn(tag) => {
+                writeln!(
+                    stdout,
+                    "{}: {} is first released in {}",
+                    filename, commit, tag.name
+                )?;
+            }
+            git::TagLookup::NotFound => {
+                writeln!(stdout, "{}: {} is not part of any tag", filename, commit)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The pin of a source as it's currently written, with the tag that points at it if known
+fn current_pin(
+    args: &Args,
+    filename: &str,
+    mut git: GitSource,
+    current: &GitSource,
+    repo_path: &Path,
+    to: PinStyle,
+) -> Result<ResolvedPin> {
+    let tags = git::list_tags(repo_path)?;
+    let (commit_hash, tag) = if let Some(tag) = &current.tag {
+        let tag = tags
+            .iter()
+            .find(|t| t.tag_hash == *tag || t.name == *tag)
+            .with_context(|| anyhow!("Tag of {:?} doesn't exist: {:?}", filename, tag))?;
+        (git::resolve_commit(repo_path, &tag.tag_hash)?, Some(tag))
+    } else if let Some(commit) = &current.commit {
+        let commit_hash = git::resolve_commit(repo_path, commit)?;
+        let prefix = tag_prefix(args, filename);
+        let mut candidates = tags
+            .iter()
+            .filter(|t| t.commit_hash.as_ref() == Some(&commit_hash))
+            .filter(|t| prefix.is_none_or(|prefix| t.name.starts_with(prefix)))
+            .collect::<Vec<_>>();
+        // prefer the tag that's selected in vcspins=
+        if let Some(tag) = candidates
+            .iter()
+            .find(|t| git.tag.as_ref() == Some(&t.name))
+        {
+            candidates = vec![*tag];
+        }
+        let tag = match (candidates.as_slice(), to) {
+            ([tag], _) => Some(*tag),
+            (_, PinStyle::Commit) => None,
+            ([], PinStyle::Tag) => bail!(
+                "{:?} is pinned to {} which isn't tagged, can't pin it to a tag",
+                filename,
+                commit_hash
+            ),
+            (tags, PinStyle::Tag) => bail!(
+                "{:?} is pinned to {} which is tagged multiple times ({}), use --tag-prefix to select one",
+                filename,
+                commit_hash,
+                tags.iter().map(|t| t.name.as_str()).collect::<Vec<_>>().join(", ")
+            ),
+        };
+        (commit_hash, tag)
+    } else {
+        bail!(
+            "Source of {:?} isn't pinned to a tag or commit, nothing to migrate",
+            filename
+        );
+    };
+
+    git.branch = None;
+    git.commit = None;
+    git.tag = tag.map(|t| t.name.clone());
+    git.signed = current.signed;
+    Ok(ResolvedPin {
+        commit_hash,
+        tag_hash: tag.map(|t| t.tag_hash.clone()),
+        annotation: None,
+        source: Source::Git(git),
+    })
+}
+
+fn migrate(args: &Args, migrate: &Migrate) -> Result<()> {
+    let pkgbuild = read_pkgbuild(args)?;
+    let backend = backend(args, &pkgbuild)?;
+    let vcspins =
+        makepkg::list_pins(&pkgbuild, &backend).context("Failed to get pins from PKGBUILD")?;
+    if vcspins.is_empty() {
+        bail!("No vcs pins are configured (vcspins= is empty)");
+    }
+    let mut sources = makepkg::list_source_arrays(&pkgbuild, &backend)
+        .context("Failed to get sources from PKGBUILD")?;
+    let folder = repos_folder(args)?;
+    let observer = observer(args);
+    let git_options = git_options(args);
+
+    let main_pin = vcspins[0].filename()?.to_string();
+    let mut resolved_pins = BTreeMap::new();
+    for pin in vcspins {
+        let filename = pin.filename()?.to_string();
+        let Source::Git(git) = pin.take_source() else {
+            bail!("Only git sources are allowed in vcspins");
+        };
+        let current = sources
+            .values()
+            .flatten()
+            .filter(|input| input.filename().is_ok_and(|f| f == filename))
+            .find_map(|input| match input.source() {
+                Source::Git(git) => Some(git.clone()),
+                _ => None,
+            })
+            .with_context(|| anyhow!("vcspins= references unknown source: {:?}", filename))?;
+        let repo_path = folder.join(&filename);
+        git::ensure_repo(&git, &repo_path, &git_options, observer.as_ref())?;
+        let pin = current_pin(args, &filename, git, &current, &repo_path, migrate.to)?;
+        if migrate.to == PinStyle::Tag && current.signed && pin.is_lightweight_tag() {
+            bail!(
+                "Source of {:?} is ?signed but {:?} is a lightweight tag without a signature, it can only be pinned by commit",
+                filename,
+                current.tag.as_deref().or(current.commit.as_deref()).unwrap_or_default()
+            );
+        }
+        resolved_pins.insert(filename, pin);
+    }
+
+    let (from, to) = match migrate.to {
+        PinStyle::Tag => ("commit", "tag"),
+        PinStyle::Commit => ("tag", "commit"),
+    };
+    let renamed = rewrite::rename_pin_variables(&pkgbuild, &resolved_pins, from, to);
+    let options = rewrite::Options {
+        pin_commit: migrate.to == PinStyle::Commit,
+        main_pin: Some(main_pin),
+        array_layout: args.array_layout,
+        array_indent: args.array_indent,
+        quote: args.array_quotes,
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    rewrite::rewrite(
+        &renamed[..],
+        &mut out,
+        &mut sources,
+        &resolved_pins,
+        &options,
+    )?;
+    rewrite::verify(&out, &resolved_pins, &options)?;
+
+    if args.diff.is_some() || args.dry_run {
+        let diff = match args.diff.unwrap_or_default() {
+            DiffFormat::Unified => interactive::diff(&args.pkgbuild, &pkgbuild, &out),
+            DiffFormat::Compact => interactive::compact_diff(&pkgbuild, &out),
+        };
+        eprint!("{}", diff);
+    }
+    if out == pkgbuild {
+        info!(
+            "Sources of {:?} are already pinned by {}",
+            args.pkgbuild, to
+        );
+        return Ok(());
+    }
+    CHANGES.store(true, atomic::Ordering::Relaxed);
+
+    let to_stdout = match &args.output {
+        Some(output) => output == Path::new("-"),
+        None => args.pkgbuild == Path::new("-"),
+    };
+    if args.dry_run {
+        debug!("Skipping write back because of dry run");
+    } else if to_stdout {
+        let mut stdout = io::stdout();
+        stdout.write_all(&out)?;
+        stdout.flush()?;
+    } else {
+        let path = args.output.as_ref().unwrap_or(&args.pkgbuild);
+        file::atomic_write(path, &out, args.backup).context("Failed to write to PKGBUILD")?;
+    }
+    Ok(())
+}
+
+/// Report an outdated pin for --fail-on and as warning for --annotations
+fn report_outdated(args: &Args, message: &str) {
+    CHANGES.store(true, atomic::Ordering::Relaxed);
+    if args.annotations == Some(Annotations::Github) {
+        let file = Some(args.pkgbuild.as_path());
+        eprintln!(
+            "{}",
+            annotations::github(annotations::Level::Warning, file, message)
+        );
+    }
+}
+
+fn check(args: &Args, check: &Check) -> Result<()> {
+    let pkgbuild = read_pkgbuild(args)?;
+    let backend = backend(args, &pkgbuild)?;
+    let vcspins =
+        makepkg::list_pins(&pkgbuild, &backend).context("Failed to get pins from PKGBUILD")?;
+    if vcspins.is_empty() {
+        bail!("No vcs pins are configured (vcspins= is empty)");
+    }
+    let folder = repos_folder(args)?;
+    let observer = observer(args);
+    let git_options = git_options(args);
+    let notify = check
+        .notify
+        .iter()
+        .map(|target| target.parse())
+        .collect::<Result<Vec<notify::Target>>>()?;
+    let notify_options = notify::Options {
+        matrix_token: match &check.matrix_token {
+            Some(path) => Some(
+                fs::read_to_string(path)
+                    .with_context(|| anyhow!("Failed to read token file {:?}", path))?
+                    .trim()
+                    .to_string(),
+            ),
+            None => None,
+        },
+    };
+
+    let mut stdout = io::stdout();
+    let mut pinned_version = None;
+    let mut new_tags = Vec::new();
+    for pin in vcspins {
+        let filename = pin.filename()?.to_string();
+        let Source::Git(git) = pin.take_source() else {
+            bail!("Only git sources are allowed in vcspins");
+        };
+        let repo_path = folder.join(&filename);
+        let resolved = git::run(git, &repo_path, &git_options, observer.as_ref())?;
+
+        let Source::Git(git) = &resolved.source else {
+            continue;
+        };
+        let Some(tag) = &git.tag else {
+            writeln!(stdout, "{}: pinned to {}", filename, resolved.commit_hash)?;
+            continue;
+        };
+        let prefix = tag_prefix(args, &filename);
+        let version = git::tag_to_pkgver(tag, prefix);
+        writeln!(stdout, "{}: pinned to {} ({})", filename, tag, version)?;
+
+        let tags = git::list_tags(&repo_path)?;
+        if check.explain {
+            writeln!(stdout, "{}: candidate tags, newest first:", filename)?;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut newest = None::<&git::TagInfo>;
+        for candidate in &tags {
+            let candidate_version = git::tag_to_pkgver(&candidate.name, prefix);
+            let too_new = check
+                .min_age
+                .zip(candidate.time)
+                .is_some_and(|(age, time)| now.saturating_sub(time.into()) < age.as_secs());
+            let rejected = match (prefix, newest) {
+                (Some(prefix), _) if !candidate.name.starts_with(prefix) => {
+                    Some(format!("doesn't start with tag prefix {:?}", prefix))
+                }
+                _ if !check.prereleases
+                    && vercmp::is_prerelease(&candidate_version)
+                    && !vercmp::is_prerelease(&version) =>
+                {
+                    Some("prerelease".to_string())
+                }
+                _ if too_new => Some(format!(
+                    "too new, younger than {}",
+                    humantime::format_duration(check.min_age.unwrap_or_default())
+                )),
+                _ if vercmp::vercmp(&candidate_version, &version) == Ordering::Less => {
+                    Some(format!("lower version than the pinned {}", version))
+                }
+                (_, Some(newest)) => Some(format!("older than {}", newest.name)),
+                (_, None) => {
+                    newest = Some(candidate);
+                    None
+                }
+            };
+            if check.explain {
+                match rejected {
+                    Some(reason) => {
+                        writeln!(stdout, "    {}: rejected, {}", candidate.name, reason)?
+                    }
+                    None => writeln!(stdout, "    {}: selected", candidate.name)?,
+                }
+            }
+        }
+        if let Some(newest) = newest {
+            if newest.name != *tag {
+                let message = format!("{}: newer tag available: {}", filename, newest.name);
+                writeln!(stdout, "{}", message)?;
+                report_outdated(args, &message);
+                new_tags.push(message);
+            }
+        }
+        pinned_version.get_or_insert(version);
+    }
+
+    let pinned_version = match pinned_version {
+        Some(version) => version,
+        None => backend
+            .list_variable(&pkgbuild, "pkgver")?
+            .pop()
+            .context("PKGBUILD has no pkgver")?,
+    };
+    let pkgbase = makepkg::pkgbase(&pkgbuild, &backend)?;
+
+    if !new_tags.is_empty() {
+        let title = format!("updvcspins: {}", pkgbase);
+        let message = new_tags.join("\n");
+        for target in &notify {
+            // a notification not getting through shouldn't fail the check
+            if let Err(err) = notify::send(target, &notify_options, &title, &message) {
+                warn!("Failed to send notification: {:#}", err);
+            }
+        }
+    }
+
+    let mut versions = Vec::new();
+    if check.arch {
+        let arch = distro::arch_versions(&pkgbase)?;
+        if arch.is_empty() {
+            writeln!(
+                stdout,
+                "{}: not found in the Arch Linux repositories",
+                pkgbase
+            )?;
+        }
+        versions.extend(arch);
+    }
+    if check.aur {
+        match distro::aur_version(&pkgbase)? {
+            Some(version) => versions.push(version),
+            None => writeln!(stdout, "{}: not found in the AUR", pkgbase)?,
+        }
+    }
+
+    if check.repology {
+        match distro::repology_newest(&pkgbase, check.repology_project.as_deref())? {
+            Some(newest) if vercmp::vercmp(&newest, &pinned_version) == Ordering::Greater => {
+                let message = format!(
+                    "{}: newer version exists according to repology: {}",
+                    pkgbase, newest
+                );
+                writeln!(stdout, "{}", message)?;
+                report_outdated(args, &message);
+            }
+            Some(newest) => writeln!(
+                stdout,
+                "{}: {} is up to date according to repology (newest: {})",
+                pkgbase, pinned_version, newest
+            )?,
+            None => writeln!(stdout, "{}: not found on repology", pkgbase)?,
+        }
+    }
+
+    // the pkgrel of the PKGBUILD only applies if the pinned version is the one it's built from
+    let mut full_version = pinned_version.clone();
+    if let Some(epoch) = backend.list_variable(&pkgbuild, "epoch")?.pop() {
+        if !epoch.is_empty() && epoch != "0" {
+            full_version = format!("{}:{}", epoch, full_version);
+        }
+    }
+    if backend.list_variable(&pkgbuild, "pkgver")?.last() == Some(&pinned_version) {
+        if let Some(pkgrel) = backend.list_variable(&pkgbuild, "pkgrel")?.pop() {
+            full_version = format!("{}-{}", full_version, pkgrel);
+        }
+    }
+
+    for version in versions {
+        let ordering = version.compare(&full_version);
+        let status = match ordering {
+            Ordering::Greater => "ahead of",
+            Ordering::Equal => "equal to",
+            Ordering::Less => "behind",
+        };
+        let message = format!("{}: {} is {} {}", pkgbase, full_version, status, version);
+        writeln!(stdout, "{}", message)?;
+        if ordering == Ordering::Less {
+            report_outdated(args, &message);
+        }
+    }
+
+    Ok(())
+}
+
+fn keyring_path(args: &Args) -> Result<PathBuf> {
+    match &args.keyring {
+        Some(path) => Ok(path.clone()),
+        None => keyring::default_path(),
+    }
+}
+
+fn keys_import(args: &Args, import: &KeysImport) -> Result<()> {
+    let pkgbuild = read_pkgbuild(args)?;
+    let backend = backend(args, &pkgbuild)?;
+    let validpgpkeys = backend.list_variable(&pkgbuild, "validpgpkeys")?;
+    if validpgpkeys.is_empty() {
+        bail!("No keys are configured (validpgpkeys= is empty)");
+    }
+
+    let path = keyring_path(args)?;
+    let keyring = Keyring::open(&path)?;
+    info!("Importing keys into {:?}", path);
+    keyring.import_from(None, &validpgpkeys)?;
+    if !import.local {
+        keyring.retrieve(&validpgpkeys, &[], &args.keyserver)?;
+    }
+
+    let keys = keyring.list(&validpgpkeys)?;
+    let mut stdout = io::stdout();
+    for key in &keys {
+        let expires = key.expires.map_or_else(
+            || "never".to_string(),
+            |secs| {
+                humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs))
+                    .to_string()
+            },
+        );
+        let status = match key.validity.as_str() {
+            "e" => " [expired]",
+            "r" => " [revoked]",
+            _ => "",
+        };
+        writeln!(
+            stdout,
+            "{} (expires: {}){}",
+            key.fingerprint, expires, status
+        )?;
+        for uid in &key.uids {
+            writeln!(stdout, "    {}", uid)?;
+        }
+    }
+
+    let missing = validpgpkeys
+        .iter()
+        .filter(|fpr| {
+            !keys
+                .iter()
+                .any(|key| key.fingerprint.eq_ignore_ascii_case(fpr))
+        })
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        bail!("Failed to import keys: {:?}", missing);
+    }
+    Ok(())
+}
+
+fn write_srcinfo(args: &Args) -> Result<()> {
+    let pkgbuild = read_pkgbuild(args)?;
+    let srcinfo = makepkg::print_srcinfo(&pkgbuild).context("Failed to generate .SRCINFO")?;
+
+    let path = match &args.output {
+        Some(output) => output.clone(),
+        None if args.pkgbuild == Path::new("-") => PathBuf::from("-"),
+        None => pkgbuild_folder(args).join(".SRCINFO"),
+    };
+    if args.dry_run {
+        debug!("Skipping write of {:?} because of dry run", path);
+    } else if path == Path::new("-") {
+        let mut stdout = io::stdout();
+        stdout.write_all(srcinfo.as_bytes())?;
+        stdout.flush()?;
+    } else if fs::read(&path).is_ok_and(|old| old == srcinfo.as_bytes()) {
+        info!("No changes for {:?}", path);
+    } else {
+        file::atomic_write(&path, srcinfo.as_bytes(), args.backup)
+            .with_context(|| anyhow!("Failed to write to {:?}", path))?;
+        observer(args).file_written(&path);
+    }
+    Ok(())
+}
+
+/// Point tarballs generated by the forge at the pinned tags and recalculate their checksums
+fn update_assets(
+    pkgbuild: &[u8],
+    backend: &Backend,
+    sources: &BTreeMap<String, Vec<Input>>,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+) -> Result<(Vec<(String, String)>, assets::Checksums)> {
+    let text = String::from_utf8_lossy(pkgbuild);
+    let mut urls = Vec::new();
+    let mut checksums = BTreeMap::new();
+    for (array, inputs) in sources {
+        let mut sums = None;
+        for (index, input) in inputs.iter().enumerate() {
+            let Source::Url(url) = input.source() else {
+                continue;
+            };
+            let new = resolved_pins.values().find_map(|pin| {
+                let Source::Git(git) = &pin.source else {
+                    return None;
+                };
+                assets::archive_url(&git.url, url, git.tag.as_deref()?)
+            });
+            let Some(new) = new else {
+                continue;
+            };
+            if !text.contains(url.as_str()) {
+                warn!(
+                    "Not updating {:?} to {:?}, the url is computed from variables",
+                    url, new
+                );
+                continue;
+            }
+            info!("Updating {:?} to {:?}", url, new);
+            let file = assets::download(&new)?;
+            let sums = match &mut sums {
+                Some(sums) => sums,
+                None => sums.insert(assets::checksum_arrays(pkgbuild, backend, array)?),
+            };
+            assets::update_checksums(sums, index, file.path())?;
+            urls.push((url.clone(), new));
+        }
+        checksums.extend(sums.unwrap_or_default());
+    }
+    Ok((urls, checksums))
+}
+
+/// Refresh the checksums of sources whose url is computed from the pkgver, if the pkgver changes
+fn refresh_templated(
+    pkgbuild: &[u8],
+    backend: &Backend,
+    sources: &BTreeMap<String, Vec<Input>>,
+    pkgver: &str,
+    checksums: &mut assets::Checksums,
+) -> Result<()> {
+    if backend
+        .list_variable(pkgbuild, "pkgver")?
+        .last()
+        .map(String::as_str)
+        == Some(pkgver)
+    {
+        return Ok(());
+    }
+    let mut arrays = BTreeMap::<String, assets::Checksums>::new();
+    for (array, index, url) in assets::templated_urls(pkgbuild, sources, pkgver)? {
+        info!(
+            "Source {} of {}= is {:?} for pkgver {:?}",
+            index + 1,
+            array,
+            url,
+            pkgver
+        );
+        assets::exists(&url)?;
+        let file = assets::download(&url)?;
+        let sums = match arrays.entry(array) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut sums = assets::checksum_arrays(pkgbuild, backend, entry.key())?;
+                // keep checksums that were already updated by --update-assets
+                for (name, values) in &mut sums {
+                    if let Some(updated) = checksums.get(name) {
+                        values.clone_from(updated);
+                    }
+                }
+                entry.insert(sums)
+            }
+        };
+        assets::update_checksums(sums, index, file.path())?;
+    }
+    checksums.extend(arrays.into_values().flatten());
+    Ok(())
+}
+
+/// Submit the written pins to Rekor and append them to the --provenance-log, if enabled
+fn log_provenance(
+    args: &Args,
+    pkgbuild: &Path,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    resolvers: &BTreeMap<String, Resolver>,
+    canonical_urls: &BTreeMap<String, (String, String)>,
+) -> Result<()> {
+    if args.provenance_log.is_none() && args.rekor_key.is_none() {
+        return Ok(());
+    }
+    let mut records = resolved_pins
+        .iter()
+        .map(|(filename, pin)| {
+            let resolver = resolvers.get(filename).copied().unwrap_or(Resolver::Git);
+            let mut record = provenance::Record::new(pkgbuild, filename, pin, resolver);
+            record.canonical_url = canonical_urls.get(filename).map(|(_, url)| url.as_str());
+            record
+        })
+        .collect::<Vec<_>>();
+    if let Some(key) = &args.rekor_key {
+        // the PKGBUILD is already written at this point, failures are recorded instead of aborting
+        let key = rekor::SigningKey::load(key);
+        for record in &mut records {
+            let result = key
+                .as_ref()
+                .map_err(|err| anyhow!("{:#}", err))
+                .and_then(|key| {
+                    let statement = serde_json::to_vec(record)?;
+                    rekor::submit(&args.rekor_url, key, &statement)
+                });
+            match result {
+                Ok(index) => record.rekor_log_index = Some(index),
+                Err(err) => {
+                    warn!("Failed to log pin of {:?} to Rekor: {:#}", record.repo, err);
+                    record.rekor_error = Some(format!("{:#}", err));
+                }
+            }
+        }
+    }
+    if let Some(log) = &args.provenance_log {
+        provenance::append(log, &records)?;
+    }
+    Ok(())
+}
+
+fn update(args: &Args) -> Result<()> {
+    let timings = Timings::new(args.timings);
+    let from_stdin = args.pkgbuild == Path::new("-");
+    let _lock = if from_stdin {
+        None
+    } else {
+        Some(file::lock(&args.pkgbuild)?)
+    };
+    let start = Instant::now();
+    let pkgbuild = read_pkgbuild(args)?;
+    let backend = backend(args, &pkgbuild)?;
+
+    let vcspins =
+        makepkg::list_pins(&pkgbuild, &backend).context("Failed to get pins from PKGBUILD")?;
     debug!("Found vcs pins: {:?}", vcspins);
 
     if vcspins.is_empty() {
         bail!("No vcs pins are configured (vcspins= is empty)");
     }
 
-    let mut sources =
-        makepkg::list_sources(&args.pkgbuild).context("Failed to get sources from PKGBUILD")?;
+    let mut sources = makepkg::list_source_arrays(&pkgbuild, &backend)
+        .context("Failed to get sources from PKGBUILD")?;
+    let policy = args.policy.as_deref().map(Policy::load).transpose()?;
+    let chains = strategy::Chains::parse(&args.resolve_orders)?;
+    if let Some(flag) = needs_clone(args).filter(|_| !chains.only_clone()) {
+        bail!("{} needs a local clone of the repositories, it can't be combined with the api or ls-remote strategies of --resolve-order", flag);
+    }
+    let hosts = forge_hosts(args)?;
+    timings.record("parse", start.elapsed());
+
+    let lock_path = pkgbuild_folder(args).join(lock::FILENAME);
+    let lockfile = if args.locked {
+        let lockfile = Lockfile::load(&lock_path)?;
+        lockfile
+            .verify_sources(&sources)
+            .context("PKGBUILD doesn't match the lockfile")?;
+        Some(lockfile)
+    } else {
+        None
+    };
+
+    for repo in &args.repos {
+        if !vcspins
+            .iter()
+            .any(|pin| pin.filename().is_ok_and(|f| f == **repo))
+        {
+            bail!(
+                "Repository selected with --repo is not in vcspins=: {:?}",
+                repo
+            );
+        }
+    }
+    for o in &args.commit_overrides {
+        let Some((repo, hash)) = o.split_once('=') else {
+            bail!("Expected --commit to be NAME=HASH: {:?}", o);
+        };
+        if hash.is_empty() || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Commit given with --commit is not a hash: {:?}", hash);
+        }
+        if !vcspins
+            .iter()
+            .any(|pin| pin.filename().is_ok_and(|f| f == *repo))
+        {
+            bail!(
+                "Repository selected with --commit is not in vcspins=: {:?}",
+                repo
+            );
+        }
+    }
+
+    for r in &args.require_tags {
+        let (_, kind) = r.split_once('=').unwrap_or(("", r));
+        kind.parse::<signature::RequiredTag>()?;
+    }
 
-    let folder = args
-        .pkgbuild
-        .parent()
-        .context("Failed to determine parent folder")?;
+    let folder = repos_folder(args)?;
+    let observer = observer(args);
+    let observer = TimingObserver::new(observer.as_ref(), &timings);
+    let git_options = git_options(args);
+    let hook_env = [(
+        "UPDVCSPINS_PKGBUILD",
+        args.pkgbuild.to_string_lossy().into_owned(),
+    )];
+    for cmd in &args.pre_resolve {
+        timings.measure("pre-resolve hook", || {
+            hooks::run(Hook::PreResolve, cmd, folder, &hook_env)
+        })?;
+    }
 
-    let mut resolved_pins = HashMap::new();
+    // `_commit=` and `_tag=` without a name belong to the first pin
+    let main_pin = vcspins[0].filename()?.to_string();
+    let mut jobs = Vec::new();
+    let mut pin_order = Vec::new();
+    let mut overrides = Vec::new();
+    let mut redirects = Vec::new();
+    let mut canonical_urls = BTreeMap::new();
     for pin in vcspins {
         debug!("Processing pin: {:?}", pin);
         let filename = pin.filename()?.to_string();
+        if !args.repos.is_empty() && !args.repos.contains(&filename) {
+            debug!("Skipping pin that wasn't selected: {:?}", filename);
+            continue;
+        }
 
         match pin.take_source() {
             Source::File(_f) => bail!("File sources are not allowed in vcspins"),
             Source::Url(_f) => bail!("Url sources are not allowed in vcspins"),
-            Source::Git(git) => {
+            Source::Git(mut git) => {
                 let repo_path = folder.join(&*filename);
-                let resolved = git::run(git, &repo_path)?;
-                resolved_pins.insert(filename, resolved);
+                if let Some(policy) = &policy {
+                    policy
+                        .check_source(&git)
+                        .with_context(|| anyhow!("Refusing to pin {:?}", filename))?;
+                }
+                pin_order.push(filename.clone());
+                if let Some(hash) = commit_override(args, &filename) {
+                    git::ensure_repo(&git, &repo_path, &git_options, &observer)?;
+                    let commit_hash = git::resolve_commit(&repo_path, hash).with_context(|| {
+                        anyhow!("Commit given with --commit is not in {:?}", filename)
+                    })?;
+                    git.tag = None;
+                    git.branch = None;
+                    git.commit = Some(commit_hash.clone());
+                    let pin = ResolvedPin {
+                        commit_hash,
+                        tag_hash: None,
+                        annotation: None,
+                        source: Source::Git(git),
+                    };
+                    observer.pin_resolved(&filename, &pin);
+                    overrides.push((filename, pin));
+                    continue;
+                }
+                if args.pick_tag {
+                    match pick_tag(&filename, &repo_path, tag_prefix(args, &filename))? {
+                        Some(tag) => {
+                            git.commit = None;
+                            git.branch = None;
+                            git.tag = Some(tag);
+                        }
+                        None => {
+                            info!("Aborting");
+                            return Ok(());
+                        }
+                    }
+                }
+                // moved repositories are resolved at their new location even if the source isn't updated
+                if args.fetch || args.fix_redirects || !repo_path.exists() {
+                    if let Some(url) = git::find_redirect(&git)? {
+                        let original = std::mem::replace(&mut git.url, url);
+                        if let Some(policy) = &policy {
+                            policy.check_source(&git).with_context(|| {
+                                anyhow!("Refusing to follow redirect of {:?}", filename)
+                            })?;
+                        }
+                        if args.fix_redirects {
+                            info!("Repository moved, updating {:?} to {:?}", original, git.url);
+                            redirects.push((original, git.url.clone()));
+                        } else {
+                            warn!(
+                                "Repository {:?} moved to {:?}, use --fix-redirects to update the source",
+                                original, git.url
+                            );
+                            canonical_urls.insert(filename.clone(), (original, git.url.clone()));
+                        }
+                        if repo_path.exists() {
+                            git::set_remote_url(&repo_path, git.remote_url())?;
+                        }
+                    }
+                }
+                jobs.push((filename, git));
             }
         }
     }
 
-    let f = File::open(&args.pkgbuild)?;
-    let r = BufReader::new(f);
+    let mut resolvers = overrides
+        .iter()
+        .map(|(filename, _)| (filename.clone(), Resolver::CommitOverride))
+        .collect::<BTreeMap<_, _>>();
+    let mut resolved_pins = BTreeMap::from_iter(overrides);
+    let mut cache = args
+        .cache_ttl
+        .map(|ttl| Ok::<_, Error>(Cache::load(Cache::default_path()?, ttl)))
+        .transpose()?;
+    if let Some(cache) = &cache {
+        // the local clone is still needed for everything after resolving
+        jobs.retain(|(filename, git)| {
+            match cache.get(git).filter(|_| folder.join(filename).exists()) {
+                Some(pin) => {
+                    info!("Using cached resolution for {:?}", filename);
+                    resolvers.insert(filename.clone(), Resolver::Cache);
+                    resolved_pins.insert(filename.clone(), pin);
+                    false
+                }
+                None => true,
+            }
+        });
+    }
+
+    // fresh clones are just as up-to-date as fetched repositories
+    let cloned = jobs
+        .iter()
+        .filter(|(filename, _)| !folder.join(filename).exists())
+        .map(|(filename, _)| filename.clone())
+        .collect::<BTreeSet<_>>();
+    let resolve = |git, repo_path: &Path| {
+        strategy::resolve(&chains, git, repo_path, &git_options, &hosts, &observer)
+    };
+    let results = resolve_parallel(jobs, folder, resolve, &timings, args.jobs.into());
+    for (filename, resolved) in results {
+        let (mut resolved, resolver) =
+            resolved.with_context(|| anyhow!("Failed to resolve pin for {:?}", filename))?;
+        if let (Some((original, _)), Source::Git(git)) =
+            (canonical_urls.get(&filename), &mut resolved.source)
+        {
+            git.url = original.clone();
+        }
+        if let Some(cache) = &mut cache {
+            cache.insert(&resolved);
+        }
+        resolvers.insert(filename.clone(), resolver);
+        resolved_pins.insert(filename, resolved);
+    }
+    if let Some(cache) = cache {
+        if let Err(err) = cache.save() {
+            warn!("Failed to update resolution cache: {:#}", err);
+        }
+    }
+    let signed = signed_toggles(args)?;
+    for (filename, signed) in &signed {
+        let Some(pin) = resolved_pins.get_mut(filename) else {
+            bail!(
+                "Can't change ?signed of {:?}, it's not in vcspins=",
+                filename
+            );
+        };
+        if let Source::Git(git) = &mut pin.source {
+            git.signed = *signed;
+        }
+    }
+
+    for (filename, pin) in &mut resolved_pins {
+        // commits given with --commit are pinned as-is
+        if resolvers.get(filename) == Some(&Resolver::CommitOverride) {
+            continue;
+        }
+        let repo_path = folder.join(filename);
+        if let Some(time) = args.as_of {
+            pin_as_of(filename, pin, &repo_path, time)?;
+        }
+        if let Some(path) = per_repo(&args.paths, filename) {
+            scope_to_path(filename, pin, &repo_path, path)?;
+        }
+    }
 
-    let mut out = String::new();
-    let mut iter = r.lines();
-    while let Some(line) = iter.next() {
-        let line = line.context("Failed to decode line")?;
-        trace!("Read line from PKGBUILD: {:?}", line);
+    if args.sync_submodules {
+        timings.measure("submodules", || {
+            sync_submodules(folder, &sources, &mut resolved_pins)
+        })?;
+        for filename in resolved_pins.keys() {
+            resolvers
+                .entry(filename.clone())
+                .or_insert(Resolver::Submodule);
+        }
+    }
 
-        if line.starts_with("_commit") {
-            let (key, first) = resolved_pins
-                .iter()
-                .next()
-                .context("Can't use _commit if no vcspins= is set")?;
-            debug!("Using repo for _commit: {:?}", key);
-            writeln!(out, "_commit={}", first.commit_hash)?;
-        } else if line.starts_with("_tag") {
-            let (key, first) = resolved_pins
-                .iter()
-                .next()
-                .context("Can't use _tag= if no vcspins= is set")?;
-            debug!("Using repo for _tag=: {:?}", key);
-            writeln!(out, "_tag={}", first.tag_hash)?;
-        } else if line.starts_with("source=") {
-            // skip original source array
-            for line in iter.by_ref() {
-                let line = line.context("Failed to decode line")?;
-                if line.ends_with(')') {
-                    break;
+    check_tags(args, &resolved_pins)?;
+
+    let fetched = resolvers
+        .iter()
+        .filter(|(filename, resolver)| {
+            **resolver == Resolver::Git && (args.fetch || cloned.contains(*filename))
+        })
+        .map(|(filename, _)| filename.clone())
+        .collect();
+    let rewritten = timings.measure("history check", || {
+        rewritten_history(folder, &sources, &resolved_pins, &fetched)
+    })?;
+    for (filename, old, reachability) in &rewritten {
+        if *reachability == git::Reachability::Unknown {
+            warn!(
+                "SECURITY: the previously pinned {:?} of {:?} is unknown to the upstream repository, it may have been force-pushed away and pruned",
+                old, filename
+            );
+        } else {
+            warn!(
+                "SECURITY: the previously pinned {:?} of {:?} is no longer part of the upstream history, it may have been force-pushed away",
+                old, filename
+            );
+        }
+    }
+    if !rewritten.is_empty() && !args.allow_rewritten_history {
+        bail!("Refusing to update pins after upstream history was rewritten, use --allow-rewritten-history to continue");
+    }
+
+    let mut names = backend.list_variable(&pkgbuild, "pkgbase")?;
+    names.extend(backend.list_variable(&pkgbuild, "pkgname")?);
+    let require_signatures = policy
+        .as_ref()
+        .is_some_and(|policy| policy.requires_signatures(&names));
+    if require_signatures {
+        for (filename, pin) in &resolved_pins {
+            if !matches!(&pin.source, Source::Git(git) if git.signed) {
+                bail!(
+                    "Policy requires signatures, but the source of {:?} is not ?signed",
+                    filename
+                );
+            }
+        }
+    }
+
+    let mut verified = BTreeMap::new();
+    let mut signer_keys = Vec::new();
+    if args.verify_signatures || require_signatures {
+        let policy = signature::Policy {
+            validpgpkeys: backend.list_variable(&pkgbuild, "validpgpkeys")?,
+            // git runs in the repository, relative paths would be resolved from there
+            allowed_signers: args
+                .allowed_signers
+                .as_ref()
+                .map(|path| {
+                    path.canonicalize()
+                        .with_context(|| anyhow!("Failed to resolve path: {:?}", path))
+                })
+                .transpose()?,
+            gnupghome: Some(keyring_path(args)?).filter(|path| path.exists()),
+            trusted_keyrings: args
+                .archlinux_keyring
+                .then(|| PathBuf::from(keyring::ARCHLINUX_KEYRING))
+                .into_iter()
+                .chain(args.trusted_keyrings.iter().cloned())
+                .collect(),
+        };
+        (verified, signer_keys) = timings.measure("signatures", || {
+            verify_signatures(args, folder, &resolved_pins, &policy)
+        })?;
+    }
+
+    if let Some(lockfile) = &lockfile {
+        lockfile
+            .verify_pins(&resolved_pins)
+            .context("Resolved pins don't match the lockfile")?;
+    }
+
+    let commits = timings.measure("expand commits", || {
+        expand_commits(folder, &sources, &resolved_pins, &git_options, &observer)
+    })?;
+
+    let start = Instant::now();
+    let pkgver = if args.vcs_pkgver || args.describe_pkgver {
+        // the first entry of vcspins= is considered the main repository
+        let filename = pin_order
+            .first()
+            .context("Can't derive pkgver if no vcspins= is set")?;
+        let pin = &resolved_pins[filename];
+        let repo_path = folder.join(filename);
+        let pkgver = if args.describe_pkgver {
+            git::describe_pkgver(&repo_path, &pin.commit_hash, tag_prefix(args, filename))?
+        } else {
+            git::vcs_pkgver(&repo_path, &pin.commit_hash)?
+        };
+        info!("Derived pkgver from {:?}: {:?}", filename, pkgver);
+        Some(pkgver)
+    } else if args.run_pkgver {
+        let srcdir = tempfile::tempdir().context("Failed to create temporary srcdir")?;
+        for (filename, pin) in &resolved_pins {
+            git::checkout(
+                &folder.join(filename),
+                &pin.commit_hash,
+                &srcdir.path().join(filename),
+            )?;
+        }
+        let startdir = pkgbuild_folder(args);
+        let startdir = startdir
+            .canonicalize()
+            .with_context(|| anyhow!("Failed to resolve path: {:?}", startdir))?;
+        let pkgver = makepkg::run_pkgver(&pkgbuild, srcdir.path(), &startdir)
+            .context("Failed to run pkgver()")?;
+        info!("Determined pkgver with pkgver(): {:?}", pkgver);
+        Some(pkgver)
+    } else {
+        None
+    };
+    if pkgver.is_some() {
+        timings.record("pkgver", start.elapsed());
+    }
+
+    let start = Instant::now();
+    if args.changelog || (args.interactive && !args.yes) {
+        for filename in resolved_pins.keys() {
+            let Some(old) = previous_pin(&sources, filename) else {
+                debug!("No previous pin for {:?}, skipping changelog", filename);
+                continue;
+            };
+            let repo_path = folder.join(filename);
+            // the previous pin may be gone after a force-push that was explicitly allowed
+            let old_commit = match git::resolve_commit(&repo_path, &old) {
+                Ok(commit) => commit,
+                Err(err) => {
+                    warn!(
+                        "Skipping changelog of {:?}, failed to resolve previous pin {:?}: {:#}",
+                        filename, old, err
+                    );
+                    continue;
+                }
+            };
+            let new_commit = &resolved_pins[filename].commit_hash;
+            let log = git::changelog(&repo_path, &old_commit, new_commit)?;
+            let stat = git::diffstat(&repo_path, &old_commit, new_commit)?;
+            let url = compare_url(&hosts, &resolved_pins[filename], &old_commit);
+            print_changelog(filename, &old, &log, &stat, url.as_deref());
+        }
+    }
+
+    if args.osv {
+        for filename in resolved_pins.keys() {
+            let new_commit = &resolved_pins[filename].commit_hash;
+            let present = osv::query_commit(new_commit)?;
+            if let Some(old) = previous_pin(&sources, filename) {
+                match git::resolve_commit(&folder.join(filename), &old) {
+                    Ok(old_commit) => {
+                        let fixed = osv::query_commit(&old_commit)?
+                            .into_iter()
+                            .filter(|vuln| !present.iter().any(|v| v.id == vuln.id))
+                            .collect::<Vec<_>>();
+                        print_vulns(filename, "fixed since", &old, &fixed);
+                    }
+                    Err(err) => warn!(
+                        "Can't report vulnerabilities fixed since previous pin {:?} of {:?}: {:#}",
+                        old, filename, err
+                    ),
                 }
             }
-            // write new source array
-            writeln!(out, "source=(")?;
-            for input in &mut sources {
-                // check if this is one of the repo's we updated our pin for
-                let filename = input.filename()?;
-                if let Some(pin) = resolved_pins.get(&*filename) {
-                    let src = input.source_mut();
-                    *src = pin.source.clone();
-                    if let Source::Git(git) = src {
-                        if args.pin_commit {
-                            git.tag = None;
-                            git.commit = Some(pin.commit_hash.clone());
-                        } else {
-                            git.tag = Some(pin.tag_hash.clone());
-                        }
+            print_vulns(filename, "present in", new_commit, &present);
+        }
+    }
+
+    if args.release_notes {
+        for filename in resolved_pins.keys() {
+            let Source::Git(git) = &resolved_pins[filename].source else {
+                continue;
+            };
+            let Some(tag) = &git.tag else {
+                continue;
+            };
+            let Some(forge) = Forge::detect(git.remote_url(), &hosts) else {
+                debug!("No supported forge for {:?}", git.remote_url());
+                continue;
+            };
+            match forge.release_notes(tag)? {
+                Some(notes) => {
+                    eprintln!("{}: release notes for {}", filename, tag);
+                    for line in notes.trim_end().lines() {
+                        eprintln!("    {}", line);
                     }
                 }
+                None => info!("{}: no release found for {:?}", filename, tag),
+            }
+        }
+    }
+
+    if args.changelog || (args.interactive && !args.yes) || args.osv || args.release_notes {
+        timings.record("reports", start.elapsed());
+    }
+
+    let epoch = match &pkgver {
+        Some(pkgver) => check_epoch(args, &pkgbuild, &backend, pkgver)?,
+        None => None,
+    };
+
+    // downloading release assets and templated sources to recalculate their checksums
+    let (urls, checksums) = timings.measure("checksums", || -> Result<_> {
+        let (urls, mut checksums) = if args.update_assets {
+            update_assets(&pkgbuild, &backend, &sources, &resolved_pins)?
+        } else {
+            Default::default()
+        };
+        if let Some(pkgver) = &pkgver {
+            refresh_templated(&pkgbuild, &backend, &sources, pkgver, &mut checksums)?;
+        }
+        Ok((urls, checksums))
+    })?;
 
-                writeln!(out, "    \"{}\"", input)?;
+    let options = rewrite::Options {
+        pin_commit: args.pin_commit,
+        main_pin: Some(main_pin),
+        pin_both: args.pin_both,
+        pkgver,
+        epoch,
+        redirects,
+        commits,
+        urls,
+        checksums,
+        validpgpkeys: signer_keys,
+        signed,
+        array_layout: args.array_layout,
+        array_indent: args.array_indent,
+        quote: args.array_quotes,
+        source_comments: args.source_comments.then(|| {
+            let now = humantime::format_rfc3339(SystemTime::now()).to_string();
+            now[..10].to_string()
+        }),
+    };
+    // the sources are updated by the rewrite
+    let previous = resolved_pins
+        .keys()
+        .filter_map(|filename| Some((filename.clone(), previous_pin(&sources, filename)?)))
+        .collect::<BTreeMap<_, _>>();
+    let mut out = Vec::new();
+    timings.measure("rewrite", || {
+        rewrite::rewrite(
+            &pkgbuild[..],
+            &mut out,
+            &mut sources,
+            &resolved_pins,
+            &options,
+        )
+    })?;
+    timings
+        .measure("self-check", || {
+            rewrite::verify(&out, &resolved_pins, &options)
+        })
+        .context("Self-check of rewrite failed")?;
+    if out != pkgbuild {
+        CHANGES.store(true, atomic::Ordering::Relaxed);
+    }
+
+    if args.verify_sources {
+        timings.measure("verify sources", || {
+            makepkg::verify_sources(&out, pkgbuild_folder(args), folder)
+        })?;
+    }
+
+    let confirm = args.interactive && !args.yes && !args.dry_run;
+    if confirm || args.diff.is_some() {
+        let diff = match args.diff.unwrap_or_default() {
+            DiffFormat::Unified => interactive::diff(&args.pkgbuild, &pkgbuild, &out),
+            DiffFormat::Compact => interactive::compact_diff(&pkgbuild, &out),
+        };
+        if diff.is_empty() {
+            info!("No changes for {:?}", args.pkgbuild);
+        } else if args.diff == Some(DiffFormat::Compact)
+            && (args.paths_from.is_some() || args.recursive.is_some())
+        {
+            // tell apart the changes of each PKGBUILD
+            eprintln!("{}:", args.pkgbuild.display());
+            for line in diff.lines() {
+                eprintln!("    {}", line);
             }
-            writeln!(out, ")")?;
         } else {
-            writeln!(out, "{}", line)?;
+            eprint!("{}", diff);
+        }
+        if confirm && diff.is_empty() {
+            return Ok(());
+        }
+    }
+    if confirm {
+        match interactive::confirm(&format!("Write changes to {:?}?", args.pkgbuild))? {
+            Answer::Yes => (),
+            Answer::No => {
+                info!("Skipping {:?}", args.pkgbuild);
+                return Ok(());
+            }
+            Answer::Quit => {
+                info!("Aborting");
+                return Ok(());
+            }
         }
     }
 
+    let to_stdout = match &args.output {
+        Some(output) => output == Path::new("-"),
+        None => from_stdin,
+    };
+    let mut written = None;
     if args.dry_run {
         debug!("Skipping write back because of dry run");
+    } else if args.locked {
+        info!("Pins match the lockfile, nothing to update with --locked");
+    } else if to_stdout {
+        debug!("Writing PKGBUILD to stdout...");
+        let mut stdout = io::stdout();
+        stdout.write_all(&out)?;
+        stdout.flush()?;
+        log_provenance(
+            args,
+            &args.pkgbuild,
+            &resolved_pins,
+            &resolvers,
+            &canonical_urls,
+        )?;
     } else {
-        let path = args.output.unwrap_or(args.pkgbuild);
+        let path = args.output.as_ref().unwrap_or(&args.pkgbuild);
         debug!("Updating PKGBUILD...");
-        fs::write(&path, &out).context("Failed to write to PKGBUILD")?;
+        timings
+            .measure("write", || file::atomic_write(path, &out, args.backup))
+            .context("Failed to write to PKGBUILD")?;
+        observer.file_written(path);
+        written = Some(path);
+        if args.write_lock {
+            let mut lockfile = Lockfile::load_or_default(&lock_path)?;
+            lockfile.update(&resolved_pins)?;
+            lockfile.save(&lock_path)?;
+            observer.file_written(&lock_path);
+        }
+        log_provenance(args, path, &resolved_pins, &resolvers, &canonical_urls)?;
+
+        if let Some(changelog_file) = args.changelog_file.as_ref().filter(|_| out != pkgbuild) {
+            let old_version = backend
+                .list_variable(&pkgbuild, "pkgver")?
+                .pop()
+                .unwrap_or_default();
+            let new_version = options.pkgver.clone().unwrap_or(old_version.clone());
+            let entry = changelog_entry(
+                folder,
+                &previous,
+                &resolved_pins,
+                args.pin_commit,
+                old_version,
+                new_version,
+            )?;
+            let changelog_path = pkgbuild_folder(args).join(changelog_file);
+            changelog::append(&changelog_path, &entry)?;
+            observer.file_written(&changelog_path);
+        }
+
+        if !args.post_write.is_empty() {
+            let mut hook_env = vec![
+                ("UPDVCSPINS_PKGBUILD", path.to_string_lossy().into_owned()),
+                (
+                    "UPDVCSPINS_RESOLVED",
+                    serde_json::to_string(&resolved_pins)?,
+                ),
+            ];
+            if let Some(pkgver) = &options.pkgver {
+                hook_env.push(("UPDVCSPINS_PKGVER", pkgver.clone()));
+            }
+            for cmd in &args.post_write {
+                timings.measure("post-write hook", || {
+                    hooks::run(Hook::PostWrite, cmd, folder, &hook_env)
+                })?;
+            }
+        }
+    }
+
+    let mut lints = Vec::new();
+    if args.lint {
+        lints = timings.measure("lint", || match written {
+            Some(path) => namcap::lint(path),
+            None => namcap::lint_content(pkgbuild_folder(args), &out),
+        })?;
+        if args.no_summary {
+            for message in &lints {
+                warn!("namcap: {}", message);
+            }
+        } else if !lints.is_empty() {
+            warn!("namcap reported {} issue(s), see the summary", lints.len());
+        }
     }
 
+    if args.open {
+        open_pages(folder, &previous, &resolved_pins, args.pin_commit, &hosts);
+    }
+
+    if !args.no_summary {
+        print_summary(
+            folder,
+            &previous,
+            &resolved_pins,
+            &verified,
+            args.pin_commit,
+            &hosts,
+        );
+        for message in &lints {
+            eprintln!("namcap: {}", message);
+        }
+    }
+
+    Ok(())
+}
+
+/// Setup the logger, -v takes precedence over RUST_LOG and --log-filter over both
+fn init_logging(args: &Args) -> Result<()> {
+    let log_level = match args.verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let mut builder = if args.verbose > 0 {
+        let mut builder = env_logger::Builder::new();
+        builder.parse_filters(log_level);
+        builder
+    } else {
+        env_logger::Builder::from_env(Env::default().default_filter_or(log_level))
+    };
+
+    for filter in &args.log_filters {
+        let (module, level) = filter
+            .split_once('=')
+            .with_context(|| anyhow!("Expected --log-filter to be MODULE=LEVEL: {:?}", filter))?;
+        let level = level
+            .parse::<LevelFilter>()
+            .with_context(|| anyhow!("Invalid log level: {:?}", level))?;
+        // modules of this crate can be selected without the crate name, eg. `git`
+        let module = if module == "updvcspins" || module.contains("::") {
+            module.to_string()
+        } else {
+            format!("updvcspins::{}", module)
+        };
+        builder.filter_module(&module, level);
+    }
+
+    if args.log_format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    if args.annotations == Some(Annotations::Github) {
+        builder.format(|buf, record| {
+            let level = match record.level() {
+                log::Level::Error => annotations::Level::Error,
+                log::Level::Warn => annotations::Level::Warning,
+                level => return writeln!(buf, "[{} {}] {}", level, record.target(), record.args()),
+            };
+            let message = record.args().to_string();
+            writeln!(buf, "{}", annotations::github(level, None, &message))
+        });
+    }
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(WarningLogger(logger)))?;
+    Ok(())
+}
+
+/// Warnings that were logged, for --fail-on warnings
+static WARNINGS: AtomicBool = AtomicBool::new(false);
+/// Pins that were updated or are outdated, for --fail-on changes
+static CHANGES: AtomicBool = AtomicBool::new(false);
+
+/// Records if warnings are logged
+struct WarningLogger(env_logger::Logger);
+
+impl log::Log for WarningLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.level() == log::Level::Warn && self.0.matches(record) {
+            WARNINGS.store(true, atomic::Ordering::Relaxed);
+        }
+        self.0.log(record);
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Read the PKGBUILD paths for --paths-from, empty lines are ignored
+fn read_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    let buf = if path == Path::new("-") {
+        let mut buf = String::new();
+        io::stdin()
+            .read_to_string(&mut buf)
+            .context("Failed to read paths from stdin")?;
+        buf
+    } else {
+        fs::read_to_string(path).with_context(|| anyhow!("Failed to read paths from {:?}", path))?
+    };
+    Ok(buf
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn run(args: &Args) -> Result<()> {
+    match args.subcommand {
+        Some(SubCommand::Lookup) => lookup(args),
+        Some(SubCommand::Check(ref check_args)) => check(args, check_args),
+        Some(SubCommand::Keys(Keys::Import(ref import))) => keys_import(args, import),
+        Some(SubCommand::Migrate(ref migrate_args)) => migrate(args, migrate_args),
+        None if args.write_srcinfo_only => write_srcinfo(args),
+        None => update(args),
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    init_logging(&args)?;
+
+    let (annotations, fail_on) = (args.annotations, args.fail_on);
+    let result = run_all(args);
+    if let (Err(err), Some(Annotations::Github)) = (&result, annotations) {
+        let message = format!("{:#}", err);
+        eprintln!(
+            "{}",
+            annotations::github(annotations::Level::Error, None, &message)
+        );
+    }
+    match result {
+        Err(err) if fail_on == FailOn::Never => {
+            eprintln!("Error: {:?}", err);
+            Ok(())
+        }
+        Err(err) => Err(err),
+        Ok(()) if fail_on >= FailOn::Warnings && WARNINGS.load(atomic::Ordering::Relaxed) => {
+            bail!("Warnings occurred, failing because of --fail-on")
+        }
+        Ok(()) if fail_on >= FailOn::Changes && CHANGES.load(atomic::Ordering::Relaxed) => {
+            bail!("Pins changed or are outdated, failing because of --fail-on")
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Run for the PKGBUILD, or each one of --paths-from and --recursive
+fn run_all(mut args: Args) -> Result<()> {
+    if args.no_network {
+        network::disable();
+    }
+
+    let paths = if let Some(paths_from) = &args.paths_from {
+        read_paths(paths_from)?
+    } else if let Some(root) = &args.recursive {
+        let options = discover::Options {
+            max_depth: args.max_depth,
+            excludes: args.excludes.clone(),
+        };
+        let paths = discover::find_pkgbuilds(root, &options)?;
+        if paths.is_empty() {
+            bail!("No PKGBUILDs found in {:?}", root);
+        }
+        paths
+    } else {
+        return run(&args);
+    };
+    let mut failed = 0;
+    for path in &paths {
+        args.pkgbuild = path.clone();
+        info!("Processing {:?}", path);
+        if let Err(err) = run(&args) {
+            error!("{:?}: {:#}", path, err);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("Failed to process {} of {} PKGBUILDs", failed, paths.len());
+    }
     Ok(())
 }
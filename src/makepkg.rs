@@ -1,187 +1,646 @@
+use crate::assets;
 use crate::errors::*;
-use crate::git::GitSource;
-use std::borrow::Cow;
-use std::fmt;
-use std::path::Path;
+use crate::git;
+use crate::network;
+use crate::source::{Input, Source, SourceString};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
-use url::Url;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Input {
-    Url(Source),
-    UrlWithFilename((Source, String)),
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedPin {
+    pub commit_hash: String,
+    /// Only available if a tag was resolved
+    pub tag_hash: Option<String>,
+    /// Only available if an annotated tag was resolved
+    pub annotation: Option<TagAnnotation>,
+    pub source: Source,
 }
 
-impl Input {
-    pub fn filename(&self) -> Result<Cow<str>> {
-        match self {
-            Input::Url(source) => source.filename(),
-            Input::UrlWithFilename((_, filename)) => Ok(Cow::Borrowed(filename)),
-        }
+impl ResolvedPin {
+    /// The pin was resolved from a tag that points at the commit directly, without a tag object
+    pub fn is_lightweight_tag(&self) -> bool {
+        self.tag_hash.as_ref() == Some(&self.commit_hash)
     }
+}
 
-    pub fn source(&self) -> &Source {
-        match self {
-            Input::Url(url) => url,
-            Input::UrlWithFilename((url, _file)) => url,
-        }
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TagAnnotation {
+    /// `Name <email>` of the tagger
+    pub tagger: Option<String>,
+    pub message: String,
+    /// The tag object carries an OpenPGP or ssh signature, it's not verified
+    #[serde(default)]
+    pub signed: bool,
+}
+
+fn exec_sh(pkgbuild: &[u8], cmd: &str) -> Result<Vec<String>> {
+    let mut bash = Command::new("bash");
+    bash.arg("-c").arg(format!("source /dev/stdin >&2;{}", cmd));
+    exec_with_pkgbuild(bash, pkgbuild, cmd)
+}
+
+/// Run a command that reads the PKGBUILD from stdin, returning its output lines
+fn exec_with_pkgbuild(command: Command, pkgbuild: &[u8], cmd: &str) -> Result<Vec<String>> {
+    let buf = exec_with_pkgbuild_raw(command, pkgbuild, cmd)?;
+    let buf = String::from_utf8(buf).context("Shell output contains invalid utf8")?;
+    Ok(buf.lines().map(String::from).collect())
+}
+
+fn exec_with_pkgbuild_raw(mut command: Command, pkgbuild: &[u8], cmd: &str) -> Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run bash")?;
+
+    let mut stdin = child.stdin.take().context("Failed to get stdin of bash")?;
+    stdin
+        .write_all(pkgbuild)
+        .context("Failed to send PKGBUILD to bash")?;
+    drop(stdin);
+
+    let out = child.wait_with_output()?;
+    // anything the PKGBUILD prints is captured so it can't end up in our own output
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let stderr = stderr.trim_end();
+    if !out.status.success() {
+        bail!(
+            "Process (bash, {:?}) exited with error: {:?}: {}",
+            cmd,
+            out.status,
+            stderr
+        );
+    }
+    if !stderr.is_empty() {
+        debug!("Output of bash ({:?}): {}", cmd, stderr);
     }
 
-    pub fn source_mut(&mut self) -> &mut Source {
-        match self {
-            Input::Url(url) => url,
-            Input::UrlWithFilename((url, _file)) => url,
-        }
+    Ok(out.stdout)
+}
+
+pub fn list_variable(pkgbuild: &[u8], var: &str) -> Result<Vec<String>> {
+    exec_sh(
+        pkgbuild,
+        &format!("for x in \"${{{}[@]}}\"; do echo \"$x\"; done", var),
+    )
+}
+
+/// Variables read from the PKGBUILD, arrays that also exist per architecture get their variants included
+///
+/// The `<alg>sums` checksum arrays are included as well, for both.
+const VARIABLES: &[&str] = &[
+    "pkgbase",
+    "pkgname",
+    "pkgver",
+    "pkgrel",
+    "epoch",
+    "arch",
+    "source",
+    "vcspins",
+    "validpgpkeys",
+];
+const ARCH_VARIABLES: &[&str] = &["source", "vcspins"];
+
+/// All variables of interest, read by sourcing the PKGBUILD once
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Variables(BTreeMap<String, Vec<String>>);
+
+impl Variables {
+    /// Source the PKGBUILD with bash and dump all variables, separated by NUL bytes
+    pub fn dump(pkgbuild: &[u8]) -> Result<Self> {
+        let checksums = assets::ALGORITHMS
+            .iter()
+            .map(|alg| format!("{}sums", alg))
+            .collect::<Vec<_>>();
+        let names = |vars: &[&str]| {
+            vars.iter()
+                .map(|var| var.to_string())
+                .chain(checksums.iter().cloned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let script = format!(
+            r#"source /dev/stdin >&2
+_updvcspins_vars=({vars})
+for _updvcspins_arch in "${{arch[@]}}"; do
+    [[ $_updvcspins_arch == any ]] && continue
+    for _updvcspins_var in {arch_vars}; do
+        _updvcspins_vars+=("${{_updvcspins_var}}_${{_updvcspins_arch}}")
+    done
+done
+for _updvcspins_var in "${{_updvcspins_vars[@]}}"; do
+    declare -n _updvcspins_ref="$_updvcspins_var"
+    printf '%s\0%s\0' "$_updvcspins_var" "${{#_updvcspins_ref[@]}}"
+    (( ${{#_updvcspins_ref[@]}} )) && printf '%s\0' "${{_updvcspins_ref[@]}}"
+    unset -n _updvcspins_ref
+done
+true"#,
+            vars = names(VARIABLES),
+            arch_vars = names(ARCH_VARIABLES),
+        );
+        let mut bash = Command::new("bash");
+        bash.arg("-c").arg(&script);
+        let out = exec_with_pkgbuild_raw(bash, pkgbuild, "dump variables")?;
+        Self::parse(&out)
     }
 
-    pub fn take_source(self) -> Source {
-        match self {
-            Input::Url(url) => url,
-            Input::UrlWithFilename((url, _file)) => url,
+    fn parse(buf: &[u8]) -> Result<Self> {
+        let buf = buf.strip_suffix(b"\0").unwrap_or(buf);
+        if buf.is_empty() {
+            return Ok(Variables::default());
         }
+        let mut fields = buf.split(|b| *b == 0).map(|field| {
+            String::from_utf8(field.to_vec()).context("Shell output contains invalid utf8")
+        });
+        let mut variables = BTreeMap::new();
+        while let Some(name) = fields.next() {
+            let name = name?;
+            let count = fields
+                .next()
+                .context("Missing length of variable in shell output")??;
+            let count = count
+                .parse::<usize>()
+                .with_context(|| anyhow!("Invalid length of variable {:?}: {:?}", name, count))?;
+            let values = fields.by_ref().take(count).collect::<Result<Vec<_>>>()?;
+            if values.len() != count {
+                bail!("Truncated shell output for variable {:?}", name);
+            }
+            variables.insert(name, values);
+        }
+        Ok(Variables(variables))
+    }
+
+    pub fn get(&self, var: &str) -> Option<&[String]> {
+        self.0.get(var).map(Vec::as_slice)
     }
 }
 
-impl fmt::Display for Input {
-    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Input::Url(url) => write!(w, "{}", url),
-            Input::UrlWithFilename((url, file)) => write!(w, "{}::{}", file, url),
-        }
+/// Variables makepkg includes in .SRCINFO, architecture specific variants are detected by prefix
+const SRCINFO_VARIABLES: &[&str] = &[
+    "pkgbase", "pkgname", "pkgver", "pkgrel", "epoch", "arch", "source",
+];
+
+/// Run `makepkg --printsrcinfo` on a PKGBUILD, returning the content for `.SRCINFO`
+pub fn print_srcinfo(pkgbuild: &[u8]) -> Result<String> {
+    let dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+    let path = dir.path().join("PKGBUILD");
+    fs::write(&path, pkgbuild).context("Failed to write temporary PKGBUILD")?;
+
+    let output = Command::new("makepkg")
+        .arg("--printsrcinfo")
+        .arg("-p")
+        .arg(&path)
+        .current_dir(dir.path())
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run makepkg")?;
+    if !output.status.success() {
+        bail!("makepkg --printsrcinfo failed: {:?}", output.status);
     }
+    String::from_utf8(output.stdout).context("makepkg output is not valid utf8")
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Source {
-    File(String),
-    Url(String),
-    Git(GitSource),
-    /*
-    Svn(SvnSource),
-    Hg(HgSource),
-    Bzr(BzrSource),
-    */
+/// The parsed output of `makepkg --printsrcinfo`, only the pkgbase section is kept
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Srcinfo {
+    pub pkgnames: Vec<String>,
+    fields: Vec<(String, String)>,
 }
 
-impl Source {
-    pub fn filename(&self) -> Result<Cow<str>> {
-        let filename = match self {
-            Source::File(path) => {
-                let p = Path::new(path);
-                let filename = p.file_name().context("Missing filename")?;
-                let filename = filename.to_str().context("Filename is invalid utf8")?;
-                Cow::Borrowed(filename)
-            }
-            Source::Url(url) => {
-                let url = url.parse::<Url>()?;
-                let filename = url
-                    .path_segments()
-                    .context("Url contains no path")?
-                    .last()
-                    .context("Path has no filename")?;
-                Cow::Owned(filename.to_string())
-            }
-            Source::Git(git) => {
-                let url = git.url.parse::<Url>()?;
-                let filename = url
-                    .path_segments()
-                    .context("Url contains no path")?
-                    .last()
-                    .context("Path has no filename")?;
-                Cow::Owned(filename.to_string())
-            }
-        };
-        if filename.is_empty() {
-            bail!("Filename can't be empty");
-        }
-        Ok(filename)
+impl Srcinfo {
+    /// Run `makepkg --printsrcinfo` on a PKGBUILD
+    pub fn generate(pkgbuild: &[u8]) -> Result<Self> {
+        print_srcinfo(pkgbuild)?.parse()
     }
-}
 
-impl fmt::Display for Source {
-    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Source::File(s) => write!(w, "{}", s),
-            Source::Url(s) => write!(w, "{}", s),
-            Source::Git(s) => write!(w, "{}", s),
+    pub fn values(&self, key: &str) -> Vec<String> {
+        if key == "pkgname" {
+            return self.pkgnames.clone();
         }
+        self.fields
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .collect()
     }
 }
 
-impl FromStr for Source {
+impl FromStr for Srcinfo {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let scheme = s.split_once("://").map(|x| x.0);
-        Ok(match scheme {
-            Some("https") => Source::Url(s.to_string()),
-            Some("http") => Source::Url(s.to_string()),
-            Some("ftp") => Source::Url(s.to_string()),
-            Some(scheme) if scheme.starts_with("git") => Source::Git(s.parse()?),
-            Some(scheme) => bail!("Unknown scheme: {:?}", scheme),
-            None => Source::File(s.to_string()),
-        })
+        let mut srcinfo = Srcinfo::default();
+        let mut in_pkgbase = false;
+        for line in s.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let (key, value) = trimmed
+                .split_once(" = ")
+                .with_context(|| anyhow!("Invalid line in .SRCINFO: {:?}", line))?;
+            // sections start with an unindented pkgbase or pkgname
+            if !line.starts_with(char::is_whitespace) {
+                in_pkgbase = key == "pkgbase";
+                if key == "pkgname" {
+                    srcinfo.pkgnames.push(value.to_string());
+                    continue;
+                }
+            }
+            if in_pkgbase {
+                srcinfo.fields.push((key.to_string(), value.to_string()));
+            }
+        }
+        Ok(srcinfo)
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct ResolvedPin {
-    pub commit_hash: String,
-    pub tag_hash: String,
-    pub source: Source,
+/// Where variables of the PKGBUILD are read from
+///
+/// Variables that weren't included in the dump are read with an additional bash invocation.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// Source the PKGBUILD with bash once and read the variables from the dump
+    Bash(Variables),
+    /// Use `makepkg --printsrcinfo`, variables makepkg doesn't know about are still read with bash
+    Srcinfo(Srcinfo, Variables),
 }
 
-fn exec_sh(path: &Path, cmd: &str) -> Result<Vec<String>> {
-    let pkgbuild = path.canonicalize()?;
-    let child = Command::new("bash")
-        .arg("-c")
-        .arg(format!("source \"{}\";{}", pkgbuild.display(), cmd))
-        .stdout(Stdio::piped())
-        .spawn()
-        .context("Failed to run bash")?;
+impl Backend {
+    pub fn bash(pkgbuild: &[u8]) -> Result<Self> {
+        Ok(Backend::Bash(Variables::dump(pkgbuild)?))
+    }
 
-    let out = child.wait_with_output()?;
-    if !out.status.success() {
-        bail!(
-            "Process (bash, {:?}) exited with error: {:?}",
-            cmd,
-            out.status
-        );
+    pub fn srcinfo(pkgbuild: &[u8]) -> Result<Self> {
+        Ok(Backend::Srcinfo(
+            Srcinfo::generate(pkgbuild)?,
+            Variables::dump(pkgbuild)?,
+        ))
     }
 
-    let buf = String::from_utf8(out.stdout).context("Shell output contains invalid utf8")?;
-    Ok(buf.lines().map(String::from).collect())
+    /// Read a variable from the dump, the PKGBUILD isn't sourced again
+    pub fn list_variable(&self, _pkgbuild: &[u8], var: &str) -> Result<Vec<String>> {
+        let variables = match self {
+            Backend::Srcinfo(srcinfo, _)
+                if SRCINFO_VARIABLES
+                    .iter()
+                    .any(|v| var == *v || var.starts_with(&format!("{}_", v))) =>
+            {
+                return Ok(srcinfo.values(var));
+            }
+            Backend::Srcinfo(_, variables) => variables,
+            Backend::Bash(variables) => variables,
+        };
+        // variants for architectures that aren't in arch= are ignored by makepkg as well
+        Ok(variables.get(var).map(<[_]>::to_vec).unwrap_or_default())
+    }
 }
 
-pub fn list_variable(folder: &Path, var: &str) -> Result<Vec<String>> {
-    exec_sh(
-        folder,
-        &format!("for x in ${{{}[@]}}; do echo \"$x\"; done", var),
-    )
+/// The pkgbase of the PKGBUILD, falling back to the first pkgname
+pub fn pkgbase(pkgbuild: &[u8], backend: &Backend) -> Result<String> {
+    if let Some(pkgbase) = backend.list_variable(pkgbuild, "pkgbase")?.pop() {
+        return Ok(pkgbase);
+    }
+    backend
+        .list_variable(pkgbuild, "pkgname")?
+        .into_iter()
+        .next()
+        .context("PKGBUILD has no pkgname")
 }
 
-pub fn list_source_list_from_var(path: &Path, var: &str) -> Result<Vec<Input>> {
-    let sources = list_variable(path, var)?;
+pub fn list_source_list_from_var(
+    pkgbuild: &[u8],
+    var: &str,
+    backend: &Backend,
+) -> Result<Vec<Input>> {
+    let sources = backend.list_variable(pkgbuild, var)?;
     let sources = sources
         .into_iter()
-        .map(|line| {
-            if let Some((file, url)) = line.split_once("::") {
-                let source = url.parse()?;
-                Ok(Input::UrlWithFilename((source, file.to_string())))
-            } else {
-                let source = line.parse()?;
-                Ok(Input::Url(source))
-            }
-        })
+        .map(|line| line.parse())
         .collect::<Result<_>>()?;
     Ok(sources)
 }
 
-pub fn list_pins(path: &Path) -> Result<Vec<Input>> {
-    list_source_list_from_var(path, "vcspins")
+/// The name of an array and its architecture specific variants, eg. `source_x86_64`
+pub fn list_arch_variables(pkgbuild: &[u8], var: &str, backend: &Backend) -> Result<Vec<String>> {
+    let mut vars = vec![var.to_string()];
+    for arch in backend.list_variable(pkgbuild, "arch")? {
+        if arch != "any" {
+            vars.push(format!("{}_{}", var, arch));
+        }
+    }
+    Ok(vars)
+}
+
+/// Resolve a pin that references an entry of the source array by filename or index
+///
+/// The url is taken from the source entry, the fragment from the pin, eg. `upstream#tag=v1.2.3`.
+fn resolve_pin_reference(pin: Input, sources: &BTreeMap<String, Vec<Input>>) -> Result<Input> {
+    let Input::Url(Source::File(reference)) = &pin else {
+        return Ok(pin);
+    };
+    let reference = reference.parse::<SourceString>()?;
+    if reference.url.contains('/') {
+        return Ok(pin);
+    }
+
+    let entry = if let Ok(idx) = reference.url.parse::<usize>() {
+        sources
+            .get("source")
+            .and_then(|sources| sources.get(idx))
+            .with_context(|| anyhow!("vcspins= references missing source index: {}", idx))?
+    } else {
+        sources
+            .values()
+            .flatten()
+            .find(|input| input.filename().is_ok_and(|f| f == reference.url))
+            .with_context(|| anyhow!("vcspins= references unknown source: {:?}", reference.url))?
+    };
+
+    let mut entry = entry.clone();
+    let Source::Git(git) = entry.source() else {
+        bail!(
+            "vcspins= references a source that isn't a git repository: {:?}",
+            reference.url
+        );
+    };
+    let query = reference
+        .query
+        .or_else(|| git.signed.then(|| "signed".to_string()));
+    let source = SourceString {
+        name: None,
+        url: git.url.clone(),
+        fragment: reference.fragment,
+        query,
+        query_first: false,
+    };
+    *entry.source_mut() = Source::Git(source.try_into()?);
+    Ok(entry)
+}
+
+/// Use the `name::` override of the matching source entry, so both refer to the same folder
+fn adopt_filename_override(pin: Input, sources: &BTreeMap<String, Vec<Input>>) -> Input {
+    let Input::Url(Source::Git(git)) = &pin else {
+        return pin;
+    };
+    let name = sources.values().flatten().find_map(|input| match input {
+        Input::UrlWithFilename((Source::Git(other), name))
+            if git::normalize_url(&other.url) == git::normalize_url(&git.url) =>
+        {
+            Some(name.clone())
+        }
+        _ => None,
+    });
+    match name {
+        Some(name) => {
+            debug!(
+                "Using filename override {:?} of source for pin {:?}",
+                name, git.url
+            );
+            Input::UrlWithFilename((pin.take_source(), name))
+        }
+        None => pin,
+    }
+}
+
+/// List the pins of `vcspins=`, including architecture specific ones like `vcspins_x86_64=`
+///
+/// Pins may reference an entry of the source array instead of repeating its url.
+pub fn list_pins(pkgbuild: &[u8], backend: &Backend) -> Result<Vec<Input>> {
+    let sources = list_source_arrays(pkgbuild, backend)?;
+    let mut pins = Vec::new();
+    for var in list_arch_variables(pkgbuild, "vcspins", backend)? {
+        for pin in list_source_list_from_var(pkgbuild, &var, backend)? {
+            let pin = resolve_pin_reference(pin, &sources)?;
+            pins.push(adopt_filename_override(pin, &sources));
+        }
+    }
+    // pins share the folder of their repository, even across architectures
+    check_unique_filenames(&pins, "vcspins=")?;
+    Ok(pins)
+}
+
+pub fn list_sources(pkgbuild: &[u8]) -> Result<Vec<Input>> {
+    list_source_list_from_var(pkgbuild, "source", &Backend::bash(pkgbuild)?)
+}
+
+/// Fail if two entries would use the same local folder or file
+fn check_unique_filenames<'a, I: IntoIterator<Item = &'a Input>>(
+    inputs: I,
+    what: &str,
+) -> Result<()> {
+    let mut seen = HashMap::<String, &Input>::new();
+    for input in inputs {
+        let filename = input.filename()?.into_owned();
+        if let Some(other) = seen.get(&filename) {
+            bail!(
+                "Two entries in {} use the same filename {:?}: {:?} and {:?}",
+                what,
+                filename,
+                other.to_string(),
+                input.to_string()
+            );
+        }
+        seen.insert(filename, input);
+    }
+    Ok(())
+}
+
+/// List `source=` and its architecture specific variants, keyed by variable name
+pub fn list_source_arrays(
+    pkgbuild: &[u8],
+    backend: &Backend,
+) -> Result<BTreeMap<String, Vec<Input>>> {
+    let mut arrays = BTreeMap::new();
+    for var in list_arch_variables(pkgbuild, "source", backend)? {
+        let sources = list_source_list_from_var(pkgbuild, &var, backend)?;
+        if var == "source" || !sources.is_empty() {
+            arrays.insert(var, sources);
+        }
+    }
+
+    // makepkg combines source= with the array of the architecture it's building for
+    let generic = arrays.get("source").map(Vec::as_slice).unwrap_or_default();
+    check_unique_filenames(generic, "source=")?;
+    for (var, sources) in &arrays {
+        if var != "source" {
+            check_unique_filenames(
+                generic.iter().chain(sources),
+                &format!("source= and {}=", var),
+            )?;
+        }
+    }
+
+    Ok(arrays)
+}
+
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file())
 }
 
-pub fn list_sources(path: &Path) -> Result<Vec<Input>> {
-    list_source_list_from_var(path, "source")
+/// Execute the pkgver() function of the PKGBUILD, with `srcdir` containing the checked out sources
+///
+/// If bubblewrap is available the function runs without network access and can only write to `srcdir`.
+pub fn run_pkgver(pkgbuild: &[u8], srcdir: &Path, startdir: &Path) -> Result<String> {
+    let cmd = "declare -F pkgver >/dev/null || { echo 'PKGBUILD has no pkgver() function' >&2; exit 1; }; cd \"$srcdir\" && pkgver";
+
+    let mut command = if let Some(bwrap) = find_in_path("bwrap") {
+        let mut command = Command::new(bwrap);
+        command
+            .args(["--ro-bind", "/", "/"])
+            .args(["--dev", "/dev"])
+            .args(["--proc", "/proc"])
+            .args(["--tmpfs", "/tmp"])
+            .arg("--bind")
+            .arg(srcdir)
+            .arg(srcdir)
+            .args(["--unshare-all", "--die-with-parent", "--new-session"])
+            .arg("--chdir")
+            .arg(srcdir)
+            .arg("bash");
+        command
+    } else {
+        warn!("bwrap not found, executing pkgver() without sandbox");
+        Command::new("bash")
+    };
+    command
+        .arg("-c")
+        .arg(format!("source /dev/stdin;{}", cmd))
+        .current_dir(srcdir)
+        .env("srcdir", srcdir)
+        .env("startdir", startdir);
+
+    let out = exec_with_pkgbuild(command, pkgbuild, "pkgver")?;
+    let pkgver = out
+        .last()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .context("pkgver() did not return a version")?;
+
+    // same restrictions as makepkg's check_pkgver
+    if pkgver.contains(|c: char| c == ':' || c == '/' || c == '-' || c.is_whitespace()) {
+        bail!("pkgver() returned an invalid version: {:?}", pkgver);
+    }
+    Ok(pkgver.to_string())
+}
+
+/// Run `makepkg --verifysource` on a PKGBUILD from `startdir`, with the repositories in `srcdest`
+pub fn verify_sources(pkgbuild: &[u8], startdir: &Path, srcdest: &Path) -> Result<()> {
+    let dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+    let path = dir.path().join("PKGBUILD");
+    fs::write(&path, pkgbuild).context("Failed to write temporary PKGBUILD")?;
+    // makepkg looks for existing clones in SRCDEST, which needs to be absolute
+    let srcdest = srcdest
+        .canonicalize()
+        .with_context(|| anyhow!("Failed to resolve path: {:?}", srcdest))?;
+
+    network::guard("makepkg --verifysource");
+    info!("Verifying sources with makepkg...");
+    let status = Command::new("makepkg")
+        .arg("--verifysource")
+        .arg("-p")
+        .arg(&path)
+        .current_dir(startdir)
+        .env("SRCDEST", srcdest)
+        .stdin(Stdio::null())
+        .status()
+        .context("Failed to run makepkg")?;
+    if !status.success() {
+        bail!("makepkg --verifysource failed: {:?}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let pin = ResolvedPin {
+            commit_hash: "c0ffee".to_string(),
+            tag_hash: Some("7a9".to_string()),
+            annotation: None,
+            source: "git+https://example.com/foo.git#tag=v1?signed"
+                .parse()
+                .unwrap(),
+        };
+        let json = serde_json::to_string(&pin).unwrap();
+        assert_eq!(serde_json::from_str::<ResolvedPin>(&json).unwrap(), pin);
+
+        let input = "fix.patch".parse::<Input>().unwrap();
+        let json = serde_json::to_string(&input).unwrap();
+        assert_eq!(json, r#"{"url":{"file":"fix.patch"}}"#);
+        assert_eq!(serde_json::from_str::<Input>(&json).unwrap(), input);
+    }
+
+    #[test]
+    fn test_list_variable() {
+        let pkgbuild = b"echo noise\nsource=(\"a b\" 'c*' d)\n";
+        assert_eq!(
+            list_variable(pkgbuild, "source").unwrap(),
+            vec!["a b".to_string(), "c*".to_string(), "d".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_dump_variables() {
+        let pkgbuild = b"pkgname=foo\narch=(x86_64 any)\nsource=(\"a b\" c)\nsource_x86_64=(d)\nvcspins=()\necho noise\n";
+        let variables = Variables::dump(pkgbuild).unwrap();
+        assert_eq!(variables.get("pkgname"), Some(&["foo".to_string()][..]));
+        assert_eq!(
+            variables.get("source"),
+            Some(&["a b".to_string(), "c".to_string()][..])
+        );
+        assert_eq!(variables.get("source_x86_64"), Some(&["d".to_string()][..]));
+        assert_eq!(variables.get("vcspins"), Some(&[][..]));
+        assert_eq!(variables.get("source_any"), None);
+        assert_eq!(variables.get("sha256sums"), Some(&[][..]));
+        assert_eq!(variables.get("b2sums_x86_64"), Some(&[][..]));
+        assert_eq!(variables.get("_commit"), None);
+
+        let err = Variables::dump(b"echo broken >&2\nexit 1\n").unwrap_err();
+        assert!(format!("{:#}", err).ends_with(": broken"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_parse_variables() {
+        let variables = Variables::parse(b"a\x002\x00x\x00y\x00b\x000\x00").unwrap();
+        assert_eq!(
+            variables.get("a"),
+            Some(&["x".to_string(), "y".to_string()][..])
+        );
+        assert_eq!(variables.get("b"), Some(&[][..]));
+        assert!(Variables::parse(b"a\x002\x00x\x00").is_err());
+    }
+
+    #[test]
+    fn test_run_pkgver() {
+        let srcdir = tempfile::tempdir().unwrap();
+        fs::create_dir(srcdir.path().join("foo")).unwrap();
+        fs::write(srcdir.path().join("foo/VERSION"), "1.2.3\n").unwrap();
+        let startdir = Path::new("/nonexistent");
+
+        let pkgbuild = b"pkgver() {\n  cd foo\n  echo \"building\" >&2\n  printf '%s.r%s\\n' \"$(cat VERSION)\" 4\n}\n";
+        assert_eq!(
+            run_pkgver(pkgbuild, srcdir.path(), startdir).unwrap(),
+            "1.2.3.r4"
+        );
+        // no pkgver() function
+        assert!(run_pkgver(b"pkgver=1\n", srcdir.path(), startdir).is_err());
+        // not a valid pkgver
+        let pkgbuild = b"pkgver() {\n  echo 1.2-3\n}\n";
+        assert!(run_pkgver(pkgbuild, srcdir.path(), startdir).is_err());
+    }
 }
@@ -0,0 +1,47 @@
+use crate::errors::*;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Run namcap on a PKGBUILD and return the messages it reported
+pub fn lint(path: &Path) -> Result<Vec<String>> {
+    debug!("Running namcap on {:?}", path);
+    let output = Command::new("namcap")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run namcap")?;
+
+    let stdout = String::from_utf8(output.stdout).context("namcap output is not valid utf8")?;
+    let messages = stdout
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim();
+    if !output.status.success() {
+        // without any messages namcap couldn't process the PKGBUILD at all
+        if messages.is_empty() {
+            bail!("namcap exited with {}: {}", output.status, stderr);
+        }
+        warn!("namcap exited with {}: {}", output.status, stderr);
+    } else if !stderr.is_empty() {
+        debug!("namcap printed on stderr: {}", stderr);
+    }
+    Ok(messages)
+}
+
+/// Run namcap on a PKGBUILD that isn't written to disk, using a temporary copy in `startdir`
+pub fn lint_content(startdir: &Path, pkgbuild: &[u8]) -> Result<Vec<String>> {
+    let mut tmp = tempfile::Builder::new()
+        .prefix(".updvcspins-")
+        .suffix("-PKGBUILD")
+        .tempfile_in(startdir)
+        .with_context(|| anyhow!("Failed to create temporary PKGBUILD in {:?}", startdir))?;
+    tmp.write_all(pkgbuild)
+        .context("Failed to write temporary PKGBUILD")?;
+    lint(tmp.path())
+}
@@ -0,0 +1,19 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Forbid all network access for the rest of the process, eg. for reproducibility audits
+pub fn disable() {
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_disabled() -> bool {
+    DISABLED.load(Ordering::SeqCst)
+}
+
+/// Panic if network access has been disabled, called before anything talks to the network
+pub fn guard(action: &str) {
+    if is_disabled() {
+        panic!("Attempted network access while it's disabled: {}", action);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::errors::*;
+use crate::http;
+use crate::network;
+use rustls::pki_types::ServerName;
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// How long an IRC server may take to accept us
+const IRC_TIMEOUT: Duration = Duration::from_secs(30);
+const IRC_DEFAULT_NICK: &str = "updvcspins";
+
+/// Where notifications are sent to
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Target {
+    /// A desktop notification with notify-send
+    Desktop,
+    /// A message to a Matrix room, eg. `matrix:https://matrix.org/!room:matrix.org`
+    Matrix { homeserver: Url, room: String },
+    /// A message to an IRC channel, eg. `ircs://irc.libera.chat/archlinux-packaging`
+    Irc {
+        host: String,
+        port: u16,
+        tls: bool,
+        channel: String,
+        nick: String,
+    },
+}
+
+impl Target {
+    fn parse_matrix(s: &str) -> Result<Self> {
+        let Some((homeserver, room)) = s.rsplit_once('/') else {
+            bail!(
+                "Expected matrix target to be matrix:HOMESERVER/ROOM: {:?}",
+                s
+            );
+        };
+        if !room.starts_with('!') && !room.starts_with('#') {
+            bail!("Expected matrix room to be a room id or alias: {:?}", room);
+        }
+        let homeserver = homeserver
+            .parse()
+            .with_context(|| anyhow!("Failed to parse matrix homeserver: {:?}", homeserver))?;
+        Ok(Target::Matrix {
+            homeserver,
+            room: room.to_string(),
+        })
+    }
+
+    fn parse_irc(s: &str) -> Result<Self> {
+        let url = Url::parse(s).with_context(|| anyhow!("Failed to parse irc url: {:?}", s))?;
+        let tls = url.scheme() == "ircs";
+        let host = url.host_str().context("Irc url has no host")?.to_string();
+        let port = url.port().unwrap_or(if tls { 6697 } else { 6667 });
+        // the channel may be given with an url-encoded `#` or as fragment
+        let channel = url
+            .fragment()
+            .map(String::from)
+            .or_else(|| {
+                url.path_segments()?
+                    .next()
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.replace("%23", "#"))
+            })
+            .context("Irc url has no channel")?;
+        let channel = if channel.starts_with(['#', '&']) {
+            channel
+        } else {
+            format!("#{}", channel)
+        };
+        let nick = url
+            .query_pairs()
+            .find(|(key, _)| key == "nick")
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_else(|| IRC_DEFAULT_NICK.to_string());
+        Ok(Target::Irc {
+            host,
+            port,
+            tls,
+            channel,
+            nick,
+        })
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "desktop" {
+            Ok(Target::Desktop)
+        } else if let Some(matrix) = s.strip_prefix("matrix:") {
+            Self::parse_matrix(matrix)
+        } else if s.starts_with("irc://") || s.starts_with("ircs://") {
+            Self::parse_irc(s)
+        } else {
+            bail!(
+                "Unknown notification target, expected desktop, matrix:HOMESERVER/ROOM or irc[s]://HOST/CHANNEL: {:?}",
+                s
+            )
+        }
+    }
+}
+
+/// Credentials for the notification targets
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub matrix_token: Option<String>,
+}
+
+fn desktop(title: &str, message: &str) -> Result<()> {
+    let status = Command::new("notify-send")
+        .args(["--app-name=updvcspins", "--"])
+        .arg(title)
+        .arg(message)
+        .stdin(Stdio::null())
+        .status()
+        .context("Failed to run notify-send")?;
+    if !status.success() {
+        bail!("notify-send exited with error: {:?}", status);
+    }
+    Ok(())
+}
+
+fn matrix_url(homeserver: &Url, segments: &[&str]) -> Result<String> {
+    let mut url = homeserver.clone();
+    url.path_segments_mut()
+        .map_err(|_| {
+            anyhow!(
+                "Matrix homeserver can't be used as base url: {:?}",
+                homeserver
+            )
+        })?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3"])
+        .extend(segments);
+    Ok(url.to_string())
+}
+
+#[derive(Debug, Serialize)]
+struct MatrixMessage<'a> {
+    msgtype: &'a str,
+    body: &'a str,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MatrixRoom {
+    room_id: String,
+}
+
+fn matrix(homeserver: &Url, room: &str, token: &str, text: &str) -> Result<()> {
+    let auth = format!("Bearer {}", token);
+    let room = if room.starts_with('#') {
+        let url = matrix_url(homeserver, &["directory", "room", room])?;
+        let resolved: MatrixRoom = http::get(&url)
+            .header("Authorization", &auth)
+            .call()
+            .with_context(|| anyhow!("Failed to resolve matrix room alias {:?}", room))?
+            .body_mut()
+            .read_json()
+            .context("Failed to decode matrix room alias")?;
+        resolved.room_id
+    } else {
+        room.to_string()
+    };
+
+    // the transaction id only needs to be unique for this access token
+    let txn = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("System time is before the unix epoch")?
+        .as_nanos()
+        .to_string();
+    let url = matrix_url(
+        homeserver,
+        &["rooms", &room, "send", "m.room.message", &txn],
+    )?;
+    http::put(&url)
+        .header("Authorization", &auth)
+        .send_json(&MatrixMessage {
+            msgtype: "m.notice",
+            body: text,
+        })
+        .with_context(|| anyhow!("Failed to send matrix message to {:?}", room))?;
+    Ok(())
+}
+
+fn irc_connect(host: &str, port: u16, tls: bool) -> Result<Box<dyn ReadWrite>> {
+    let stream = TcpStream::connect((host, port))
+        .with_context(|| anyhow!("Failed to connect to irc server {}:{}", host, port))?;
+    stream.set_read_timeout(Some(IRC_TIMEOUT))?;
+    if !tls {
+        return Ok(Box::new(stream));
+    }
+
+    let roots = rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string())
+        .with_context(|| anyhow!("Invalid irc server name: {:?}", host))?;
+    let conn = rustls::ClientConnection::new(Arc::new(config), name)?;
+    Ok(Box::new(rustls::StreamOwned::new(conn, stream)))
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Register with the server, join the channel and send the message, one line per PRIVMSG
+fn irc_session<S: Read + Write>(stream: S, channel: &str, nick: &str, text: &str) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    write!(
+        stream.get_mut(),
+        "NICK {}\r\nUSER {} 0 * :updvcspins\r\n",
+        nick,
+        nick
+    )?;
+    stream.get_mut().flush()?;
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line)? == 0 {
+            bail!("Irc server closed the connection during registration");
+        }
+        let line = line.trim_end();
+        trace!("irc: {:?}", line);
+        let mut words = line.split(' ');
+        let first = words.next().unwrap_or_default();
+        if first == "PING" {
+            let token = line.strip_prefix("PING").map(str::trim_start);
+            write!(stream.get_mut(), "PONG {}\r\n", token.unwrap_or_default())?;
+            stream.get_mut().flush()?;
+            continue;
+        }
+        match words.next() {
+            // RPL_WELCOME, we're registered
+            Some("001") => break,
+            Some("433") => bail!("Irc nick is already in use: {:?}", nick),
+            _ if first == "ERROR" => bail!("Irc server refused the connection: {:?}", line),
+            _ => (),
+        }
+    }
+
+    let out = stream.get_mut();
+    write!(out, "JOIN {}\r\n", channel)?;
+    for line in text.lines().filter(|l| !l.is_empty()) {
+        write!(out, "PRIVMSG {} :{}\r\n", channel, line)?;
+    }
+    write!(out, "QUIT\r\n")?;
+    out.flush()?;
+    // wait for the server to close the connection so the messages aren't dropped
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest);
+    Ok(())
+}
+
+/// Send a notification, eg. about new upstream tags, with one line per tag
+///
+/// Chat messages get the title in front of every line, all lines are sent in one irc session.
+pub fn send(target: &Target, options: &Options, title: &str, message: &str) -> Result<()> {
+    debug!("Sending notification to {:?}: {:?}", target, message);
+    let text = message
+        .lines()
+        .map(|line| format!("{}: {}", title, line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    match target {
+        Target::Desktop => desktop(title, message),
+        Target::Matrix { homeserver, room } => {
+            let token = options
+                .matrix_token
+                .as_deref()
+                .context("Sending to matrix requires --matrix-token")?;
+            matrix(homeserver, room, token, &text)
+        }
+        Target::Irc {
+            host,
+            port,
+            tls,
+            channel,
+            nick,
+        } => {
+            network::guard(&format!("IRC {}:{}", host, port));
+            let stream = irc_connect(host, *port, *tls)?;
+            irc_session(stream, channel, nick, &text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!("desktop".parse::<Target>().unwrap(), Target::Desktop);
+        assert_eq!(
+            "matrix:https://matrix.org/!abc:matrix.org"
+                .parse::<Target>()
+                .unwrap(),
+            Target::Matrix {
+                homeserver: "https://matrix.org".parse().unwrap(),
+                room: "!abc:matrix.org".to_string(),
+            }
+        );
+        assert_eq!(
+            "ircs://irc.libera.chat/archlinux-packaging?nick=pinbot"
+                .parse::<Target>()
+                .unwrap(),
+            Target::Irc {
+                host: "irc.libera.chat".to_string(),
+                port: 6697,
+                tls: true,
+                channel: "#archlinux-packaging".to_string(),
+                nick: "pinbot".to_string(),
+            }
+        );
+        assert_eq!(
+            "irc://localhost:6668/#test".parse::<Target>().unwrap(),
+            Target::Irc {
+                host: "localhost".to_string(),
+                port: 6668,
+                tls: false,
+                channel: "#test".to_string(),
+                nick: "updvcspins".to_string(),
+            }
+        );
+        assert!("pager".parse::<Target>().is_err());
+        assert!("matrix:https://matrix.org/abc".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn test_matrix_url() {
+        let homeserver = "https://matrix.org/".parse().unwrap();
+        let url = matrix_url(&homeserver, &["directory", "room", "#arch:matrix.org"]).unwrap();
+        assert_eq!(
+            url,
+            "https://matrix.org/_matrix/client/v3/directory/room/%23arch:matrix.org"
+        );
+    }
+
+    /// Replays the server side of a session and records what the client sent
+    struct Replay {
+        input: Cursor<&'static [u8]>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Replay {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Replay {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_irc_session() {
+        let mut replay = Replay {
+            input: Cursor::new(b"PING :abc\r\nPING\r\n:irc.example.com 001 bot :Welcome\r\n"),
+            output: Vec::new(),
+        };
+        irc_session(
+            &mut replay,
+            "#test",
+            "bot",
+            "foo: newer tag available: v1.2\nbar: newer tag available: v2.0",
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(replay.output).unwrap(),
+            "NICK bot\r\nUSER bot 0 * :updvcspins\r\nPONG :abc\r\nPONG \r\nJOIN #test\r\nPRIVMSG #test :foo: newer tag available: v1.2\r\nPRIVMSG #test :bar: newer tag available: v2.0\r\nQUIT\r\n"
+        );
+    }
+}
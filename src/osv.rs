@@ -0,0 +1,51 @@
+use crate::errors::*;
+use crate::http;
+use serde::{Deserialize, Serialize};
+
+const OSV_QUERY_URL: &str = "https://api.osv.dev/v1/query";
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Vulnerability {
+    pub id: String,
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Query<'a> {
+    commit: &'a str,
+    page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryResponse {
+    #[serde(default)]
+    vulns: Vec<Vulnerability>,
+    next_page_token: Option<String>,
+}
+
+/// List the known vulnerabilities OSV.dev considers present in a commit
+pub fn query_commit(commit_hash: &str) -> Result<Vec<Vulnerability>> {
+    debug!("Querying OSV.dev for commit {:?}", commit_hash);
+    let mut vulns = Vec::new();
+    let mut page_token = None;
+    loop {
+        let query = Query {
+            commit: commit_hash,
+            page_token,
+        };
+        let response: QueryResponse = http::post(OSV_QUERY_URL)
+            .send_json(&query)
+            .context("Failed to query OSV.dev")?
+            .body_mut()
+            .read_json()
+            .context("Failed to decode response from OSV.dev")?;
+        vulns.extend(response.vulns);
+        page_token = response.next_page_token;
+        if page_token.is_none() {
+            break;
+        }
+    }
+    Ok(vulns)
+}
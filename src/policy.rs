@@ -0,0 +1,104 @@
+use crate::errors::*;
+use crate::git::{self, GitSource};
+use git_repository::bstr::ByteSlice;
+use git_repository::glob::wildmatch::Mode;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Supply-chain rules that are enforced before any pin is written, loaded from a JSON file
+#[derive(Debug, Default, PartialEq, Eq, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Upstream hosts that may be pinned, eg. `github.com` or `*.kernel.org`, all if empty
+    pub allow_hosts: Vec<String>,
+    /// Upstream hosts that may never be pinned, takes precedence over allow_hosts
+    pub deny_hosts: Vec<String>,
+    /// Reject upstream urls that don't use https
+    pub require_https: bool,
+    /// Packages whose pinned sources all need to be `?signed` and verified, by pkgbase or pkgname
+    pub require_signatures: Vec<String>,
+}
+
+/// Match a hostname against glob patterns, ignoring case
+pub(crate) fn matches(patterns: &[String], value: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        git_repository::glob::wildmatch(
+            pattern.as_bytes().as_bstr(),
+            value.as_bytes().as_bstr(),
+            Mode::IGNORE_CASE,
+        )
+    })
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let buf = fs::read(path).with_context(|| anyhow!("Failed to read policy {:?}", path))?;
+        serde_json::from_slice(&buf).with_context(|| anyhow!("Failed to parse policy {:?}", path))
+    }
+
+    /// Check the upstream url of a source, before it's contacted
+    pub fn check_source(&self, source: &GitSource) -> Result<()> {
+        let remote_url = source.remote_url();
+        let (scheme, host) = git::remote_host(&source.url)
+            .with_context(|| anyhow!("Failed to parse url: {:?}", remote_url))?;
+        let host = host.as_str();
+
+        if self.require_https && scheme != "https" {
+            bail!(
+                "Policy requires https, but {:?} uses {}",
+                remote_url,
+                scheme
+            );
+        }
+        if matches(&self.deny_hosts, host) {
+            bail!("Policy denies the host of {:?}", remote_url);
+        }
+        if !self.allow_hosts.is_empty() && !matches(&self.allow_hosts, host) {
+            bail!("Policy doesn't allow the host of {:?}", remote_url);
+        }
+        Ok(())
+    }
+
+    /// If signatures have to be verified for a package with these names
+    pub fn requires_signatures(&self, names: &[String]) -> bool {
+        names
+            .iter()
+            .any(|name| matches(&self.require_signatures, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_source() {
+        let policy = serde_json::from_str::<Policy>(
+            r#"{"allow_hosts": ["github.com", "*.kernel.org"], "deny_hosts": ["evil.kernel.org"], "require_https": true}"#,
+        )
+        .unwrap();
+        let check = |url: &str| {
+            policy.check_source(&GitSource::builder(format!("git+{}", url)).build().unwrap())
+        };
+
+        assert!(check("https://github.com/kpcyrd/updvcspins.git").is_ok());
+        assert!(check("https://git.kernel.org/pub/scm/git/git.git").is_ok());
+        assert!(check("https://evil.kernel.org/foo.git").is_err());
+        assert!(check("https://gitlab.com/foo/bar.git").is_err());
+        assert!(check("git://git.kernel.org/pub/scm/git/git.git").is_err());
+        assert!(check("ssh://git@github.com/kpcyrd/updvcspins.git").is_err());
+        // scp-like remotes are ssh
+        let scp = "git@github.com:kpcyrd/updvcspins.git"
+            .parse::<GitSource>()
+            .unwrap();
+        assert!(policy.check_source(&scp).is_err());
+
+        let policy = serde_json::from_str::<Policy>(r#"{"allow_hosts": ["github.com"]}"#).unwrap();
+        let check = |url: &str| policy.check_source(&url.parse::<GitSource>().unwrap());
+        assert!(check("git@github.com:kpcyrd/updvcspins.git").is_ok());
+        assert!(check("git+git@gitlab.com:foo/bar.git").is_err());
+
+        assert!(serde_json::from_str::<Policy>(r#"{"allow_host": []}"#).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::events::{LogObserver, Observer, Progress};
+use crate::makepkg::ResolvedPin;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Renders progress bars for clones and fetches, other events are logged
+pub struct ProgressObserver {
+    multi: MultiProgress,
+    bars: Mutex<HashMap<PathBuf, ProgressBar>>,
+    log: LogObserver,
+}
+
+impl ProgressObserver {
+    pub fn new() -> Self {
+        ProgressObserver {
+            multi: MultiProgress::with_draw_target(ProgressDrawTarget::stderr()),
+            bars: Mutex::new(HashMap::new()),
+            log: LogObserver,
+        }
+    }
+
+    /// Progress bars are only shown if both stdout and stderr are a terminal
+    pub fn is_supported() -> bool {
+        io::stdout().is_terminal() && io::stderr().is_terminal()
+    }
+
+    fn start(&self, path: &Path) {
+        let bar = self.multi.add(ProgressBar::new_spinner());
+        bar.set_style(
+            ProgressStyle::with_template("{prefix:.bold} {msg} {wide_bar} {pos}/{len}")
+                .expect("Progress bar template is invalid"),
+        );
+        bar.set_prefix(path.display().to_string());
+        self.bars.lock().unwrap().insert(path.to_owned(), bar);
+    }
+}
+
+impl Default for ProgressObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for ProgressObserver {
+    fn clone_started(&self, url: &str, path: &Path) {
+        self.log.clone_started(url, path);
+        self.start(path);
+    }
+
+    fn fetch_started(&self, path: &Path) {
+        self.log.fetch_started(path);
+        self.start(path);
+    }
+
+    fn fetch_progress(&self, path: &Path, progress: &Progress) {
+        if let Some(bar) = self.bars.lock().unwrap().get(path) {
+            bar.set_message(progress.stage.clone());
+            bar.set_length(progress.total.unwrap_or(0));
+            bar.set_position(progress.current);
+        }
+    }
+
+    fn fetch_finished(&self, path: &Path) {
+        if let Some(bar) = self.bars.lock().unwrap().remove(path) {
+            bar.finish_and_clear();
+        }
+        self.log.fetch_finished(path);
+    }
+
+    fn pin_resolved(&self, name: &str, pin: &ResolvedPin) {
+        self.log.pin_resolved(name, pin);
+    }
+
+    fn file_written(&self, path: &Path) {
+        self.log.file_written(path);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::errors::*;
+use crate::makepkg::{ResolvedPin, TagAnnotation};
+use crate::source::Source;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// How a pin was resolved
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resolver {
+    /// Resolved the ref in the git repository
+    Git,
+    /// Resolved the ref with `git ls-remote`, without a local clone
+    LsRemote,
+    /// Resolved the ref with the API of the forge, eg. GitHub
+    ForgeApi,
+    /// Reused from the resolution cache of a previous run
+    Cache,
+    /// Given explicitly with --commit
+    CommitOverride,
+    /// Taken from the submodule commit recorded in a pinned superproject
+    Submodule,
+}
+
+/// One line of the provenance log, describing where a pin written to a PKGBUILD came from
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Record<'a> {
+    pub timestamp: String,
+    pub pkgbuild: &'a Path,
+    pub repo: &'a str,
+    pub url: Option<&'a str>,
+    /// The location the url redirected to, if the repository moved without updating the source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_url: Option<&'a str>,
+    pub tag: Option<&'a str>,
+    pub branch: Option<&'a str>,
+    pub tag_hash: Option<&'a str>,
+    pub commit_hash: &'a str,
+    /// Tagger and message of an annotated tag, release notes are often only found here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<&'a TagAnnotation>,
+    /// If makepkg is going to verify the signature of the tag or commit
+    pub signed: bool,
+    pub resolver: Resolver,
+    /// Index of the statement about this pin in the Rekor transparency log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rekor_log_index: Option<u64>,
+    /// Why the statement couldn't be submitted to Rekor, the PKGBUILD was written regardless
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rekor_error: Option<String>,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(
+        pkgbuild: &'a Path,
+        repo: &'a str,
+        pin: &'a ResolvedPin,
+        resolver: Resolver,
+    ) -> Self {
+        let git = match &pin.source {
+            Source::Git(git) => Some(git),
+            _ => None,
+        };
+        Record {
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            pkgbuild,
+            repo,
+            url: git.map(|git| git.url.as_str()),
+            canonical_url: None,
+            tag: git.and_then(|git| git.tag.as_deref()),
+            branch: git.and_then(|git| git.branch.as_deref()),
+            tag_hash: pin.tag_hash.as_deref(),
+            commit_hash: &pin.commit_hash,
+            annotation: pin.annotation.as_ref(),
+            signed: git.is_some_and(|git| git.signed),
+            resolver,
+            rekor_log_index: None,
+            rekor_error: None,
+        }
+    }
+}
+
+/// Append records to a JSON Lines file, existing content is never modified
+pub fn append(path: &Path, records: &[Record]) -> Result<()> {
+    let mut buf = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut buf, record)?;
+        buf.push(b'\n');
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| anyhow!("Failed to open provenance log {:?}", path))?;
+    // a single write keeps the records of concurrent runs from interleaving
+    file.write_all(&buf)
+        .with_context(|| anyhow!("Failed to append to provenance log {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_annotation() {
+        let mut pin = ResolvedPin {
+            commit_hash: "c0ffee".to_string(),
+            tag_hash: Some("7a9".to_string()),
+            annotation: Some(TagAnnotation {
+                tagger: Some("Jane <jane@example.com>".to_string()),
+                message: "Release 2.0\n\n- Fix crash\n".to_string(),
+                signed: false,
+            }),
+            source: "git+https://example.com/foo.git#tag=v2".parse().unwrap(),
+        };
+        let record = Record::new(Path::new("PKGBUILD"), "foo", &pin, Resolver::Git);
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["tag"], "v2");
+        assert_eq!(
+            json["annotation"],
+            serde_json::json!({
+                "tagger": "Jane <jane@example.com>",
+                "message": "Release 2.0\n\n- Fix crash\n",
+                "signed": false,
+            })
+        );
+
+        // lightweight tags have no annotation
+        pin.annotation = None;
+        let record = Record::new(Path::new("PKGBUILD"), "foo", &pin, Resolver::Git);
+        let json = serde_json::to_value(&record).unwrap();
+        assert!(json.get("annotation").is_none());
+    }
+}
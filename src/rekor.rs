@@ -0,0 +1,103 @@
+use crate::errors::*;
+use crate::http;
+use base64::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub const DEFAULT_URL: &str = "https://rekor.sigstore.dev";
+
+/// Rekor verifies ssh signatures made for this namespace
+const SSH_NAMESPACE: &str = "file";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogEntry {
+    log_index: u64,
+}
+
+/// An ssh key used to sign statements before they are submitted
+#[derive(Debug, Clone)]
+pub struct SigningKey {
+    pub private_key: PathBuf,
+    pub public_key: Vec<u8>,
+}
+
+impl SigningKey {
+    /// Load the key, the public key is expected next to it with a `.pub` suffix
+    pub fn load(private_key: &Path) -> Result<Self> {
+        let mut public_path = private_key.as_os_str().to_owned();
+        public_path.push(".pub");
+        let public_key = fs::read(&public_path)
+            .with_context(|| anyhow!("Failed to read public key {:?}", public_path))?;
+        Ok(SigningKey {
+            private_key: private_key.to_owned(),
+            public_key,
+        })
+    }
+
+    /// Create an ssh signature of the data with `ssh-keygen -Y sign`
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut child = Command::new("ssh-keygen")
+            .args(["-q", "-Y", "sign", "-n", SSH_NAMESPACE, "-f"])
+            .arg(&self.private_key)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run ssh-keygen")?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .context("Failed to get stdin of ssh-keygen")?;
+        stdin.write_all(data)?;
+        drop(stdin);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!("ssh-keygen failed to sign: {:?}", output.status);
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// Sign a statement and add it to the transparency log, returning its log index
+pub fn submit(url: &str, key: &SigningKey, statement: &[u8]) -> Result<u64> {
+    let signature = key.sign(statement)?;
+    let entry = json!({
+        "kind": "rekord",
+        "apiVersion": "0.0.1",
+        "spec": {
+            "signature": {
+                "format": "ssh",
+                "content": BASE64_STANDARD.encode(signature),
+                "publicKey": {
+                    "content": BASE64_STANDARD.encode(&key.public_key),
+                },
+            },
+            "data": {
+                "content": BASE64_STANDARD.encode(statement),
+            },
+        },
+    });
+
+    let endpoint = format!("{}/api/v1/log/entries", url.trim_end_matches('/'));
+    debug!("Submitting statement to transparency log: {:?}", endpoint);
+    let entries: BTreeMap<String, LogEntry> = http::post(&endpoint)
+        .send_json(&entry)
+        .context("Failed to submit to transparency log")?
+        .body_mut()
+        .read_json()
+        .context("Failed to decode response from transparency log")?;
+    let (uuid, entry) = entries
+        .into_iter()
+        .next()
+        .context("Transparency log returned no entry")?;
+    info!(
+        "Added statement to transparency log at index {} (uuid: {:?})",
+        entry.log_index, uuid
+    );
+    Ok(entry.log_index)
+}
@@ -0,0 +1,1786 @@
+use crate::errors::*;
+use crate::git;
+use crate::interactive;
+use crate::makepkg::{self, Backend, ResolvedPin};
+use crate::source::{Input, Source, SourceString};
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// How a regenerated source array is laid out
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArrayLayout {
+    /// One entry per line, the parentheses on lines of their own
+    #[default]
+    Lines,
+    /// The first entry next to the opening parenthesis, the others aligned below it
+    Wrapped,
+}
+
+/// The quotes used for the entries of a regenerated source array
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Quote {
+    #[default]
+    Double,
+    /// Single quotes, entries with variables or a `'` are still double quoted
+    Single,
+}
+
+impl Quote {
+    fn quote(&self, entry: &str) -> String {
+        match self {
+            Quote::Single if !entry.contains('\'') => format!("'{}'", entry),
+            _ => format!("\"{}\"", entry),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub pin_commit: bool,
+    /// The pin that `_commit=`/`_tag=` without a name belong to, the first entry of `vcspins=`
+    ///
+    /// If unset, they can only be used if a single pin was resolved.
+    pub main_pin: Option<String>,
+    /// Pin tag object hashes in sources and require a `_commit=` variable for each of them
+    pub pin_both: bool,
+    /// Replace `pkgver=` with this value
+    pub pkgver: Option<String>,
+    /// Replace `epoch=` with this value, inserting it before `pkgver=` if missing
+    pub epoch: Option<u32>,
+    /// Urls of moved repositories to replace in `vcspins=`, as (old, new)
+    pub redirects: Vec<(String, String)>,
+    /// Abbreviated commit hashes to replace with the full hash, by (filename, short)
+    pub commits: BTreeMap<(String, String), String>,
+    /// Urls of other sources to replace, as (old, new), eg. release tarballs of a pinned tag
+    pub urls: Vec<(String, String)>,
+    /// Checksum arrays to replace with these entries, eg. `sha256sums`
+    pub checksums: BTreeMap<String, Vec<String>>,
+    /// Write a comment with the resolved ref and this date above pinned git sources, eg. `2024-01-31`
+    pub source_comments: Option<String>,
+    /// Fingerprints to add to `validpgpkeys=`, the array is added before `source=` if missing
+    pub validpgpkeys: Vec<String>,
+    /// Add (`true`) or remove (`false`) `?signed` on the sources of these pins
+    pub signed: BTreeMap<String, bool>,
+    pub array_layout: ArrayLayout,
+    /// Indentation of the entries of a regenerated source array, the default depends on the layout
+    pub array_indent: Option<usize>,
+    pub quote: Quote,
+}
+
+/// Write the entries of a regenerated array, without the closing parenthesis
+fn write_array<W: Write>(
+    w: &mut W,
+    var: &str,
+    entries: &[(Option<String>, String)],
+    options: &Options,
+    eol: &str,
+) -> Result<()> {
+    let indent = match (options.array_indent, options.array_layout) {
+        (Some(indent), _) => " ".repeat(indent),
+        (None, ArrayLayout::Lines) => "    ".to_string(),
+        // aligned with the first entry after `source=(`
+        (None, ArrayLayout::Wrapped) => " ".repeat(var.len() + 2),
+    };
+    write!(w, "{}=(", var)?;
+    for (idx, (comment, entry)) in entries.iter().enumerate() {
+        // a comment can't go on the line of the opening parenthesis
+        let own_line = options.array_layout == ArrayLayout::Lines || idx > 0 || comment.is_some();
+        if own_line {
+            write!(w, "{}", eol)?;
+        }
+        if let Some(comment) = comment {
+            write!(w, "{}{}{}", indent, comment, eol)?;
+        }
+        if own_line {
+            write!(w, "{}", indent)?;
+        }
+        write!(w, "{}", entry)?;
+    }
+    if options.array_layout == ArrayLayout::Lines {
+        write!(w, "{}", eol)?;
+    }
+    Ok(())
+}
+
+/// Replace the urls of moved repositories, only if followed by a fragment, query or closing quote
+fn replace_urls(line: &str, redirects: &[(String, String)]) -> String {
+    let mut line = line.to_string();
+    for (old, new) in redirects {
+        for end in ['#', '?', '"', '\''] {
+            line = line.replace(&format!("{}{}", old, end), &format!("{}{}", new, end));
+        }
+    }
+    line
+}
+
+/// The name of a per-repository variable, eg. `_foo_bar_commit` for `foo-bar`
+pub fn variable_name(filename: &str, suffix: &str) -> String {
+    let ident = filename.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    format!("_{}_{}", ident, suffix)
+}
+
+/// Rename a variable in its assignments and references like `$old` or `${old}`
+fn rename_variable(line: &str, old: &str, new: &str) -> String {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(idx) = rest.find(old) {
+        let (before, after) = rest.split_at(idx);
+        let next = after[old.len()..].chars().next();
+        let prev = before.chars().last();
+        let assigned = next == Some('=') && prev.is_none_or(char::is_whitespace);
+        let referenced = prev == Some('$') || before.ends_with("${");
+        out.push_str(before);
+        if !next.is_some_and(is_ident) && (assigned || referenced) {
+            out.push_str(new);
+        } else {
+            out.push_str(old);
+        }
+        rest = &after[old.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Rename the `_tag`/`_commit` style variables of the pins, eg. `_foo_tag` to `_foo_commit`
+///
+/// Variables are only renamed if the new name isn't assigned yet, lines that aren't valid utf8
+/// are kept as-is.
+pub fn rename_pin_variables(
+    pkgbuild: &[u8],
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    from: &str,
+    to: &str,
+) -> Vec<u8> {
+    let mut names = resolved_pins
+        .keys()
+        .map(|key| (variable_name(key, from), variable_name(key, to)))
+        .collect::<Vec<_>>();
+    if resolved_pins.len() == 1 {
+        names.push((format!("_{}", from), format!("_{}", to)));
+    }
+    let lines = pkgbuild
+        .split_inclusive(|b| *b == b'\n')
+        .collect::<Vec<_>>();
+    let assigned = lines
+        .iter()
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .filter_map(|line| Some(Assignment::parse(line)?.name.to_string()))
+        .collect::<BTreeSet<_>>();
+    names.retain(|(old, new)| assigned.contains(old) && !assigned.contains(new));
+
+    let mut out = Vec::new();
+    for line in lines {
+        match std::str::from_utf8(line) {
+            Ok(line) => {
+                let line = names.iter().fold(line.to_string(), |line, (old, new)| {
+                    rename_variable(&line, old, new)
+                });
+                out.extend(line.as_bytes());
+            }
+            Err(_) => out.extend(line),
+        }
+    }
+    out
+}
+
+/// Find the value of a `_commit`/`_tag` style variable from the resolved pins, and the pin
+fn pin_variable<'a>(
+    name: &str,
+    resolved_pins: &'a BTreeMap<String, ResolvedPin>,
+    main_pin: Option<&str>,
+) -> Result<Option<(String, &'a str, &'a ResolvedPin)>> {
+    let (key, pin, suffix) =
+        if let Some(suffix) = ["commit", "tag"].iter().find(|s| name == format!("_{}", s)) {
+            if let Some(main_pin) = main_pin {
+                let Some((key, pin)) = resolved_pins.get_key_value(main_pin) else {
+                    debug!("Not updating {}=, {:?} wasn't resolved", name, main_pin);
+                    return Ok(None);
+                };
+                (key, pin, *suffix)
+            } else {
+                let mut pins = resolved_pins.iter();
+                let (Some((key, pin)), None) = (pins.next(), pins.next()) else {
+                    bail!(
+                        "Can't attribute {}= to a repository with {} pins, use _<name>_{}= instead",
+                        name,
+                        resolved_pins.len(),
+                        suffix
+                    );
+                };
+                (key, pin, *suffix)
+            }
+        } else {
+            let found = resolved_pins.iter().find_map(|(key, pin)| {
+                ["commit", "tag"]
+                    .into_iter()
+                    .find(|suffix| variable_name(key, suffix) == name)
+                    .map(|suffix| (key, pin, suffix))
+            });
+            let Some(found) = found else {
+                return Ok(None);
+            };
+            found
+        };
+
+    debug!("Using repo for {}=: {:?}", name, key);
+    let value = if suffix == "commit" {
+        pin.commit_hash.clone()
+    } else {
+        pin.tag_hash
+            .clone()
+            .with_context(|| anyhow!("Can't use {}= because {:?} is not a tag", name, key))?
+    };
+    Ok(Some((value, key, pin)))
+}
+
+/// The full hash for the abbreviated commit in a `_<name>_commit=` or `_commit=` variable
+fn expanded_commit<'a>(name: &str, value: &str, options: &'a Options) -> Option<&'a String> {
+    options
+        .commits
+        .iter()
+        .find(|((filename, short), _)| {
+            let bare = name == "_commit" && options.main_pin.as_ref() == Some(filename);
+            short == value && (bare || name == variable_name(filename, "commit"))
+        })
+        .map(|(_, full)| full)
+}
+
+/// The name of the variable if the value is nothing but a reference to it, eg. `${_commit}`
+fn referenced_variable(value: &str) -> Option<&str> {
+    let name = value.strip_prefix('$')?;
+    let name = match name.strip_prefix('{') {
+        Some(name) => name.strip_suffix('}')?,
+        None => name,
+    };
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
+
+/// Split indentation and declaration keywords like `local` or `declare -r` from an assignment
+fn split_declaration(line: &str) -> Option<(&str, &str)> {
+    let mut assignment = line.trim_start();
+    for keyword in ["declare", "local", "readonly", "typeset", "export"] {
+        let Some(args) = assignment.strip_prefix(keyword) else {
+            continue;
+        };
+        if !args.starts_with(char::is_whitespace) {
+            continue;
+        }
+        assignment = args.trim_start();
+        // skip flags like `-r`
+        while let Some(flags) = assignment.strip_prefix('-') {
+            let end = flags.find(char::is_whitespace)?;
+            assignment = flags[end..].trim_start();
+        }
+        break;
+    }
+    Some(line.split_at(line.len() - assignment.len()))
+}
+
+/// Make sure an array is assigned exactly once, as a plain top-level assignment
+///
+/// Arrays that are assigned conditionally, appended to or built by other code can't be
+/// rewritten without changing the meaning of the PKGBUILD.
+fn check_array_assignment(lines: &[(Line, &str)], var: &str) -> Result<()> {
+    let mut found = Vec::new();
+    for (idx, (line, _)) in lines.iter().enumerate() {
+        let line = text(line);
+        let Some((prefix, assignment)) = split_declaration(&line) else {
+            continue;
+        };
+        let Some(rest) = assignment.strip_prefix(var) else {
+            continue;
+        };
+        if rest.starts_with('=') || rest.starts_with("+=") {
+            found.push((idx + 1, prefix.is_empty() && rest.starts_with('=')));
+        }
+    }
+    match found[..] {
+        [(_, true)] => Ok(()),
+        [] => bail!(
+            "{}= is not assigned as a plain array in the PKGBUILD, refusing to rewrite it",
+            var
+        ),
+        [(line, false)] => bail!(
+            "{}= on line {} is indented, declared or appended to, refusing to rewrite a conditional or computed array",
+            var,
+            line
+        ),
+        _ => bail!(
+            "{}= is assigned more than once (lines {}), refusing to rewrite a conditional or computed array",
+            var,
+            found
+                .iter()
+                .map(|(line, _)| line.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Check if any entry of the array is going to be changed
+fn needs_update(
+    inputs: &[Input],
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    options: &Options,
+) -> bool {
+    inputs.iter().any(|input| {
+        let pinned = input
+            .filename()
+            .is_ok_and(|filename| resolved_pins.contains_key(&*filename));
+        let changed = match (input.source(), input.filename()) {
+            (Source::Git(git), Ok(filename)) => git.commit.as_ref().is_some_and(|commit| {
+                options
+                    .commits
+                    .contains_key(&(filename.into_owned(), commit.clone()))
+            }),
+            (Source::Url(url), _) => options.urls.iter().any(|(old, _)| old == url),
+            _ => false,
+        };
+        pinned || changed
+    })
+}
+
+/// Parse a comment above a source entry as (filename, ref, date), eg. `# foo: v1.2.3, resolved 2024-01-31`
+fn parse_source_comment(line: &str) -> Option<(&str, &str, &str)> {
+    let comment = line.trim().strip_prefix("# ")?;
+    let (filename, rest) = comment.split_once(": ")?;
+    let (refname, date) = rest.split_once(", resolved ")?;
+    Some((filename, refname, date))
+}
+
+/// Check if a word of an array is computed by code instead of listing one entry
+fn is_computed(word: &str) -> bool {
+    ["$(", "`", "[@]", "[*]"].iter().any(|x| word.contains(x))
+}
+
+/// A variable assignment like `_commit="abc123"  # v1.2.3`
+#[derive(Debug, PartialEq, Eq)]
+struct Assignment<'a> {
+    /// Indentation and declaration keyword, eg. `  declare -r `
+    prefix: &'a str,
+    name: &'a str,
+    quote: &'a str,
+    value: &'a str,
+    /// Everything after the value, eg. a trailing comment
+    rest: &'a str,
+}
+
+impl<'a> Assignment<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let (prefix, assignment) = split_declaration(line)?;
+        let (name, value) = assignment.split_once('=')?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return None;
+        }
+        let (quote, value, rest) = match value.chars().next() {
+            Some(q @ ('"' | '\'')) => {
+                let (inner, rest) = value[1..].split_once(q)?;
+                (&value[..1], inner, rest)
+            }
+            _ => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                ("", &value[..end], &value[end..])
+            }
+        };
+        Some(Assignment {
+            prefix,
+            name,
+            quote,
+            value,
+            rest,
+        })
+    }
+
+    /// Write the assignment with a new value, keeping quotes and comment
+    fn write<W: Write>(&self, w: &mut W, value: &str, eol: &str) -> Result<()> {
+        write!(
+            w,
+            "{}{}={}{}{}{}{}",
+            self.prefix, self.name, self.quote, value, self.quote, self.rest, eol
+        )?;
+        Ok(())
+    }
+}
+
+/// Replace the last word of a trailing comment with the new ref, eg. `# tag: v1.2.3`
+fn update_comment(rest: &str, refname: Option<&str>) -> String {
+    let Some(refname) = refname else {
+        return rest.to_string();
+    };
+    let Some((code, comment)) = rest.split_once('#') else {
+        return rest.to_string();
+    };
+    let comment = comment.trim_end();
+    let (prefix, old) = match comment.rsplit_once(char::is_whitespace) {
+        Some((prefix, old)) => (&comment[..prefix.len() + 1], old),
+        None => ("", comment),
+    };
+    // only replace things that look like a version, keep other comments
+    if !old.contains(|c: char| c.is_ascii_digit()) {
+        return rest.to_string();
+    }
+    // keep the style of the old comment if it used versions without the `v` prefix
+    let new = match refname.strip_prefix('v') {
+        Some(version)
+            if !old.starts_with('v') && version.starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            version
+        }
+        _ => refname,
+    };
+    format!("{}#{}{}", code, prefix, new)
+}
+
+/// Check if the line ends an array, ignoring a trailing comment
+fn closes_array(line: &str) -> bool {
+    strip_comment(line).trim_end().ends_with(')')
+}
+
+/// The line without a trailing comment
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (idx, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '#') if line[..idx].trim_end().len() < idx || idx == 0 => {
+                return &line[..idx];
+            }
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Add entries to the end of an array, keeping the existing lines and their comments
+fn append_to_array(lines: &[String], entries: &[String]) -> Result<Vec<String>> {
+    let (last, previous) = lines.split_last().context("Array has no lines")?;
+    let code = strip_comment(last).trim_end();
+    let Some(before) = code.strip_suffix(')') else {
+        bail!("Array is never closed");
+    };
+    let close = &last[before.len()..];
+    let entry_before_close = !before.trim().is_empty();
+    let leading = |line: &str| line[..line.len() - line.trim_start().len()].to_string();
+    // align with the entries above, or with the opening parenthesis
+    let indent = if entry_before_close && !previous.is_empty() {
+        leading(last)
+    } else if !entry_before_close && previous.len() > 1 {
+        leading(&previous[previous.len() - 1])
+    } else {
+        " ".repeat(lines[0].find('(').map_or(4, |idx| idx + 1))
+    };
+    let mut out = previous.to_vec();
+    if entry_before_close {
+        out.push(before.trim_end().to_string());
+        for (idx, entry) in entries.iter().enumerate() {
+            if idx + 1 == entries.len() {
+                out.push(format!("{}'{}'{}", indent, entry, close));
+            } else {
+                out.push(format!("{}'{}'", indent, entry));
+            }
+        }
+    } else {
+        out.extend(entries.iter().map(|entry| format!("{}'{}'", indent, entry)));
+        out.push(last.to_string());
+    }
+    Ok(out)
+}
+
+/// Check if the line assigns the array or one of its architecture specific variants
+fn is_array(line: &str, var: &str) -> bool {
+    line.strip_prefix(var)
+        .is_some_and(|rest| rest.starts_with('=') || rest.starts_with('_'))
+}
+
+/// The words of a bash array as written, `None` for words that need no expansion
+///
+/// Quotes are removed, everything else (eg. `$pkgver`) is kept as-is.
+fn array_words(body: &str) -> Vec<Option<String>> {
+    split_words(body)
+        .into_iter()
+        .map(|(word, expands)| expands.then_some(word))
+        .collect()
+}
+
+/// Split into words like bash, with a flag if the word needs expansion
+fn split_words(body: &str) -> Vec<(String, bool)> {
+    split_word_spans(body)
+        .into_iter()
+        .map(|(_, word, expands)| (word, expands))
+        .collect()
+}
+
+/// Split into words like bash, with the byte range each word was written at
+fn split_word_spans(body: &str) -> Vec<(Range<usize>, String, bool)> {
+    let mut words = Vec::new();
+    let mut word = None::<(Range<usize>, String, bool)>;
+    let mut chars = body.char_indices();
+    let mut quote = None;
+    while let Some((idx, c)) = chars.next() {
+        let end = idx + c.len_utf8();
+        match (quote, c) {
+            (None, '#') if word.is_none() => {
+                // skip comment until end of line
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            (None, c) if c.is_whitespace() => {
+                words.extend(word.take());
+                continue;
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (Some('\''), c) => word
+                .get_or_insert_with(|| (idx..idx, String::new(), false))
+                .1
+                .push(c),
+            (_, '\\') => {
+                let w = word.get_or_insert_with(|| (idx..idx, String::new(), false));
+                w.1.push(c);
+                if let Some((idx, c)) = chars.next() {
+                    w.1.push(c);
+                    w.0.end = idx + c.len_utf8();
+                    continue;
+                }
+            }
+            (_, c) => {
+                let w = word.get_or_insert_with(|| (idx..idx, String::new(), false));
+                w.2 |= c == '$' || c == '`';
+                w.1.push(c);
+            }
+        }
+        word.get_or_insert_with(|| (idx..idx, String::new(), false))
+            .0
+            .end = end;
+    }
+    words.extend(word);
+    words
+}
+
+/// The top-level assignments of a PKGBUILD with the words of their value, eg. `source=(...)`
+pub fn assignments(pkgbuild: &str) -> Vec<(String, Vec<String>)> {
+    let mut assignments = Vec::new();
+    let mut lines = pkgbuild.lines();
+    while let Some(line) = lines.next() {
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_name {
+            continue;
+        }
+        let value = match value.strip_prefix('(') {
+            Some(body) => {
+                let mut body = body.to_string();
+                let mut closed = closes_array(line);
+                while !closed {
+                    let Some(line) = lines.next() else {
+                        break;
+                    };
+                    body.push('\n');
+                    body.push_str(line);
+                    closed = closes_array(line);
+                }
+                match body.rsplit_once(')') {
+                    Some((body, _)) => body.to_string(),
+                    None => body,
+                }
+            }
+            None => value.to_string(),
+        };
+        let words = split_words(&value).into_iter().map(|(word, _)| word);
+        assignments.push((name.to_string(), words.collect()));
+    }
+    assignments
+}
+
+/// A line of the PKGBUILD, kept as bytes if it's not valid utf8
+type Line = std::result::Result<String, Vec<u8>>;
+
+/// Decode a line for inspection, invalid utf8 is replaced
+fn text(line: &Line) -> Cow<'_, str> {
+    match line {
+        Ok(line) => Cow::Borrowed(line),
+        Err(bytes) => String::from_utf8_lossy(bytes),
+    }
+}
+
+/// Write a line unchanged, including bytes that aren't valid utf8
+fn write_line<W: Write>(w: &mut W, line: &Line, eol: &str) -> Result<()> {
+    match line {
+        Ok(line) => w.write_all(line.as_bytes())?,
+        Err(bytes) => w.write_all(bytes)?,
+    }
+    w.write_all(eol.as_bytes())?;
+    Ok(())
+}
+
+/// Write a line with the urls of moved repositories replaced, this requires valid utf8
+fn write_replaced_urls<W: Write>(
+    w: &mut W,
+    line: &Line,
+    eol: &str,
+    redirects: &[(String, String)],
+) -> Result<()> {
+    let text = text(line);
+    let replaced = replace_urls(&text, redirects);
+    if line.is_err() && replaced != text {
+        bail!(
+            "Can't replace urls in line that isn't valid utf8: {:?}",
+            text
+        );
+    }
+    if replaced == text {
+        write_line(w, line, eol)
+    } else {
+        write!(w, "{}{}", replaced, eol)?;
+        Ok(())
+    }
+}
+
+/// Read the next line, returning its content and the line ending that terminated it
+fn read_line<R: BufRead>(r: &mut R) -> Result<Option<(Line, &'static str)>> {
+    let mut line = Vec::new();
+    if r.read_until(b'\n', &mut line)
+        .context("Failed to read line")?
+        == 0
+    {
+        return Ok(None);
+    }
+    let eol = if line.ends_with(b"\r\n") {
+        "\r\n"
+    } else if line.ends_with(b"\n") {
+        "\n"
+    } else {
+        ""
+    };
+    line.truncate(line.len() - eol.len());
+    let line = String::from_utf8(line).map_err(|err| err.into_bytes());
+    Ok(Some((line, eol)))
+}
+
+pub fn rewrite<R: BufRead, W: Write>(
+    mut r: R,
+    mut w: W,
+    sources: &mut BTreeMap<String, Vec<Input>>,
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    options: &Options,
+) -> Result<()> {
+    let mut lines = Vec::new();
+    while let Some(line) = read_line(&mut r)? {
+        lines.push(line);
+    }
+    for (var, inputs) in sources.iter() {
+        if needs_update(inputs, resolved_pins, options) {
+            check_array_assignment(&lines, var)?;
+        }
+    }
+    if options.pin_both {
+        if let Some((key, _)) = resolved_pins.iter().find(|(_, pin)| pin.tag_hash.is_none()) {
+            bail!(
+                "Can't use --pin-both because {:?} is not pinned to a tag",
+                key
+            );
+        }
+    }
+    let mut commit_variables = BTreeSet::new();
+    let has_epoch = lines
+        .iter()
+        .any(|(line, _)| text(line).starts_with("epoch="));
+    let mut add_validpgpkeys = !options.validpgpkeys.is_empty()
+        && !lines
+            .iter()
+            .any(|(line, _)| text(line).starts_with("validpgpkeys="));
+
+    let mut lines = lines.into_iter();
+    while let Some((original, eol)) = lines.next() {
+        let line = text(&original);
+        trace!("Read line from PKGBUILD: {:?}", line);
+
+        let assignment = Assignment::parse(&line).filter(|a| a.name.starts_with('_'));
+        let variable = assignment
+            .as_ref()
+            .map(|a| pin_variable(a.name, resolved_pins, options.main_pin.as_deref()))
+            .transpose()?
+            .flatten();
+        let short_commit = assignment
+            .as_ref()
+            .and_then(|a| expanded_commit(a.name, a.value, options));
+        if (variable.is_some() || short_commit.is_some()) && original.is_err() {
+            bail!("Can't update line that isn't valid utf8: {:?}", line);
+        }
+
+        if add_validpgpkeys && is_array(&line, "source") {
+            let indent = " ".repeat("validpgpkeys=(".len());
+            let keys = options
+                .validpgpkeys
+                .iter()
+                .map(|key| format!("'{}'", key))
+                .collect::<Vec<_>>();
+            write!(
+                w,
+                "validpgpkeys=({}){}",
+                keys.join(&format!("{}{}", eol, indent)),
+                eol
+            )?;
+            add_validpgpkeys = false;
+        }
+
+        if let (Some(assignment), Some((value, key, pin))) = (&assignment, variable) {
+            if assignment.name.ends_with("_commit") {
+                commit_variables.insert(key);
+            }
+            let (tag, refname) = match &pin.source {
+                Source::Git(git) => (
+                    git.tag.as_deref(),
+                    git.tag.as_deref().or(git.branch.as_deref()),
+                ),
+                _ => (None, None),
+            };
+            let rest = match tag {
+                // commit hashes aren't readable, so name the tag they correspond to
+                Some(tag)
+                    if assignment.name.ends_with("commit") && !assignment.rest.contains('#') =>
+                {
+                    format!("{} # {}", assignment.rest.trim_end(), tag)
+                }
+                _ => update_comment(assignment.rest, refname),
+            };
+            Assignment {
+                rest: &rest,
+                ..*assignment
+            }
+            .write(&mut w, &value, eol)?;
+        } else if let (Some(assignment), Some(full)) = (&assignment, short_commit) {
+            assignment.write(&mut w, full, eol)?;
+        } else if let (true, Some(epoch)) = (line.starts_with("epoch="), options.epoch) {
+            write!(w, "epoch={}{}", epoch, eol)?;
+        } else if is_array(&line, "vcspins") && !options.redirects.is_empty() {
+            write_replaced_urls(&mut w, &original, eol, &options.redirects)?;
+            if !line.ends_with(')') {
+                for (line, eol) in lines.by_ref() {
+                    write_replaced_urls(&mut w, &line, eol, &options.redirects)?;
+                    if text(&line).ends_with(')') {
+                        break;
+                    }
+                }
+            }
+        } else if line.starts_with("pkgver=") {
+            if let (false, Some(epoch)) = (has_epoch, options.epoch) {
+                write!(w, "epoch={}{}", epoch, eol)?;
+            }
+            if let Some(pkgver) = &options.pkgver {
+                write!(w, "pkgver={}{}", pkgver, eol)?;
+            } else {
+                write_line(&mut w, &original, eol)?;
+            }
+        } else if line.starts_with("validpgpkeys=") && !options.validpgpkeys.is_empty() {
+            let mut array = vec![text(&original).into_owned()];
+            let mut end = eol;
+            while !closes_array(&array[array.len() - 1]) {
+                let Some((line, eol)) = lines.next() else {
+                    bail!("validpgpkeys= array is never closed");
+                };
+                array.push(text(&line).into_owned());
+                end = eol;
+            }
+            let joined = array.join("\n");
+            let listed = split_words(joined.split_once('(').map_or("", |(_, body)| body))
+                .into_iter()
+                .map(|(word, _)| word.trim_end_matches(')').to_string())
+                .collect::<Vec<_>>();
+            let missing = options
+                .validpgpkeys
+                .iter()
+                .filter(|key| !listed.iter().any(|l| l.eq_ignore_ascii_case(key)))
+                .cloned()
+                .collect::<Vec<_>>();
+            let array = if missing.is_empty() {
+                array
+            } else {
+                append_to_array(&array, &missing).context("Failed to add keys to validpgpkeys=")?
+            };
+            for (idx, line) in array.iter().enumerate() {
+                let eol = if idx + 1 == array.len() { end } else { eol };
+                write!(w, "{}{}", line, eol)?;
+            }
+        } else if let Some((var, values)) = line
+            .split_once('=')
+            .and_then(|(var, _)| options.checksums.get_key_value(var))
+        {
+            let mut array = text(&original).into_owned();
+            let mut closed = closes_array(&array);
+            let mut end = eol;
+            while !closed {
+                let Some((line, eol)) = lines.next() else {
+                    bail!("{}= array is never closed", var);
+                };
+                array.push('\n');
+                array.push_str(&text(&line));
+                closed = closes_array(&text(&line));
+                end = eol;
+            }
+            let body = array
+                .split_once('=')
+                .map(|(_, x)| x.trim_start())
+                .and_then(|body| body.strip_prefix('('))
+                .and_then(|body| body.rsplit_once(')'))
+                .map(|(body, _)| body)
+                .with_context(|| {
+                    anyhow!("{}= is not a plain array, refusing to rewrite it", var)
+                })?;
+            let offset = body.as_ptr() as usize - array.as_ptr() as usize;
+            let words = split_word_spans(body);
+            if words.len() != values.len() {
+                bail!(
+                    "{}= lists {} entries but has {} after evaluating the PKGBUILD, refusing to rewrite a conditional or computed array",
+                    var,
+                    words.len(),
+                    values.len()
+                );
+            }
+            // only replace the checksums that changed, keeping the layout and comments
+            let mut array = array.clone();
+            for ((span, word, expands), value) in words.iter().zip(values).rev() {
+                if !expands && word == value {
+                    continue;
+                }
+                let span = span.start + offset..span.end + offset;
+                let quote = match array[span.clone()].chars().next() {
+                    Some(q @ ('"' | '\'')) => q.to_string(),
+                    _ => String::new(),
+                };
+                array.replace_range(span, &format!("{quote}{value}{quote}"));
+            }
+            let lines = array.split('\n').collect::<Vec<_>>();
+            for (idx, line) in lines.iter().enumerate() {
+                let eol = if idx + 1 == lines.len() { end } else { eol };
+                write!(w, "{}{}", line, eol)?;
+            }
+        } else if let Some(sources) = line
+            .split_once('=')
+            .and_then(|(var, _)| sources.get_mut(var))
+        {
+            let var = line.split_once('=').map(|(var, _)| var).unwrap_or_default();
+            // read original source array, remembering how it was written
+            let mut end = eol;
+            let mut array = vec![(original.clone(), eol)];
+            let mut closed = closes_array(&line);
+            if !closed {
+                for (line, eol) in lines.by_ref() {
+                    end = eol;
+                    closed = closes_array(&text(&line));
+                    array.push((line, eol));
+                    if closed {
+                        break;
+                    }
+                }
+            }
+            if !closed {
+                bail!("{}= array is never closed", var);
+            }
+
+            if !needs_update(sources, resolved_pins, options) {
+                for (line, eol) in &array {
+                    write_line(&mut w, line, eol)?;
+                }
+                continue;
+            }
+
+            let original = array
+                .iter()
+                .map(|(line, _)| text(line))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let body = original
+                .split_once('=')
+                .map(|(_, x)| x.trim_start())
+                .unwrap_or_default();
+            let Some(body) = body
+                .strip_prefix('(')
+                .and_then(|body| body.rsplit_once(')'))
+                .map(|(body, _)| body)
+            else {
+                bail!("{}= is not a plain array, refusing to rewrite it", var);
+            };
+            let mut words = array_words(body);
+            if let Some(word) = words.iter().flatten().find(|word| is_computed(word)) {
+                bail!(
+                    "{}= contains an entry that is computed by code, refusing to rewrite it: {:?}",
+                    var,
+                    word
+                );
+            }
+            if words.len() != sources.len() {
+                bail!(
+                    "{}= lists {} entries but has {} after evaluating the PKGBUILD, refusing to rewrite a conditional or computed array",
+                    var,
+                    words.len(),
+                    sources.len()
+                );
+            }
+            // comments of an earlier run, to keep the date of entries that didn't change
+            let source_comments = array
+                .iter()
+                .filter_map(|(line, _)| {
+                    let line = text(line);
+                    let (filename, refname, date) = parse_source_comment(&line)?;
+                    Some((
+                        filename.to_string(),
+                        (refname.to_string(), date.to_string()),
+                    ))
+                })
+                .collect::<BTreeMap<_, _>>();
+            // the new entries, with a comment to write above them
+            let mut entries = Vec::new();
+            for (idx, input) in sources.iter_mut().enumerate() {
+                let mut comment = None;
+                let mut raw = words.get_mut(idx).and_then(Option::take);
+                // check if this is one of the repo's we updated our pin for
+                let filename = input.filename()?.into_owned();
+                if let Some(pin) = resolved_pins.get(&filename) {
+                    let before = input.to_string();
+                    let src = input.source_mut();
+                    match (&mut *src, &pin.source) {
+                        // keep everything but the url and ref of the existing entry, eg. `?signed`,
+                        // the url only changes if it's a different repository, not a different spelling
+                        (Source::Git(git), Source::Git(pinned)) => {
+                            if git::normalize_url(&git.url) != git::normalize_url(&pinned.url) {
+                                raw = None;
+                                git.url = pinned.url.clone();
+                            }
+                        }
+                        (src, pinned) => {
+                            raw = None;
+                            *src = pinned.clone();
+                        }
+                    }
+                    if let Source::Git(git) = src {
+                        if let Some(signed) = options.signed.get(&filename) {
+                            git.signed = *signed;
+                        }
+                        git.branch = None;
+                        match &pin.tag_hash {
+                            Some(tag_hash) if !options.pin_commit => {
+                                git.commit = None;
+                                git.tag = Some(tag_hash.clone());
+                            }
+                            _ => {
+                                git.tag = None;
+                                git.commit = Some(pin.commit_hash.clone());
+                            }
+                        }
+                    }
+                    // keep the original filename if the url of a moved repository changed it
+                    if let Input::Url(source) = input {
+                        if source.filename()? != filename {
+                            let source = source.clone();
+                            *input = Input::UrlWithFilename((source, filename.clone()));
+                        }
+                    }
+                    // keep variables in the original text, only replace the ref and `?signed`
+                    if let Some(text) = raw.take() {
+                        let mut original = text.parse::<SourceString>()?;
+                        let updated = input.to_string().parse::<SourceString>()?;
+                        // a ref like `#commit=$_commit` is updated through the variable instead
+                        let pinned_by_variable = original
+                            .fragment
+                            .as_deref()
+                            .and_then(|fragment| fragment.split_once('='))
+                            .and_then(|(_, value)| referenced_variable(value))
+                            .map(|name| {
+                                pin_variable(name, resolved_pins, options.main_pin.as_deref())
+                            })
+                            .transpose()?
+                            .flatten()
+                            .is_some_and(|(_, key, _)| key == filename);
+                        if !pinned_by_variable {
+                            original.fragment = updated.fragment;
+                        }
+                        original.query = updated.query;
+                        raw = Some(original.to_string());
+                    }
+                    let refname = match &pin.source {
+                        Source::Git(git) => git.tag.as_deref().or(git.branch.as_deref()),
+                        _ => None,
+                    };
+                    if let (Some(date), Some(refname)) = (&options.source_comments, refname) {
+                        let date = match source_comments.get(&filename) {
+                            Some((old, date)) if old == refname && input.to_string() == before => {
+                                date
+                            }
+                            _ => date,
+                        };
+                        comment = Some(format!("# {}: {}, resolved {}", filename, refname, date));
+                    }
+                } else if let Source::Url(url) = input.source_mut() {
+                    if let Some((_, new)) = options.urls.iter().find(|(old, _)| old == url) {
+                        raw = None;
+                        *url = new.clone();
+                    }
+                } else if let Source::Git(git) = input.source_mut() {
+                    let commit = git.commit.clone();
+                    if let Some((short, full)) = commit.and_then(|c| {
+                        options
+                            .commits
+                            .get(&(filename.clone(), c.clone()))
+                            .map(|full| (c, full))
+                    }) {
+                        raw = raw.map(|raw| {
+                            raw.replace(&format!("commit={}", short), &format!("commit={}", full))
+                        });
+                        git.commit = Some(full.clone());
+                    }
+                }
+
+                let entry = match raw {
+                    // variables are only expanded in double quotes
+                    Some(raw) => format!("\"{}\"", raw),
+                    None => options.quote.quote(&input.to_string()),
+                };
+                entries.push((comment, entry));
+            }
+            write_array(&mut w, var, &entries, options, eol)?;
+            write!(w, "){}", end)?;
+        } else {
+            write_line(&mut w, &original, eol)?;
+        }
+    }
+    if add_validpgpkeys {
+        bail!("Can't add validpgpkeys= to a PKGBUILD without source=");
+    }
+    if options.pin_both {
+        if let Some(key) = resolved_pins
+            .keys()
+            .find(|key| !commit_variables.contains(key.as_str()))
+        {
+            bail!(
+                "--pin-both needs a {}= or _commit= variable to write the commit hash of {:?} to",
+                variable_name(key, "commit"),
+                key
+            );
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Parse the rewritten PKGBUILD again and make sure it contains the resolved pins
+///
+/// Rewriting it a second time must not change anything, otherwise the rewrite isn't stable.
+pub fn verify(
+    pkgbuild: &[u8],
+    resolved_pins: &BTreeMap<String, ResolvedPin>,
+    options: &Options,
+) -> Result<()> {
+    let mut sources = makepkg::list_source_arrays(pkgbuild, &Backend::bash(pkgbuild)?)
+        .context("Failed to parse sources of rewritten PKGBUILD")?;
+
+    for input in sources.values().flatten() {
+        let filename = input.filename()?;
+        let Some(pin) = resolved_pins.get(&*filename) else {
+            continue;
+        };
+        let Source::Git(git) = input.source() else {
+            continue;
+        };
+        let expected = match &pin.tag_hash {
+            Some(tag_hash) if !options.pin_commit => git.tag.as_ref() == Some(tag_hash),
+            _ => git.commit.as_ref() == Some(&pin.commit_hash),
+        };
+        if !expected || git.branch.is_some() {
+            bail!(
+                "Rewritten PKGBUILD doesn't pin {:?} to the resolved commit: {}",
+                filename,
+                input
+            );
+        }
+    }
+
+    let mut again = Vec::new();
+    rewrite(pkgbuild, &mut again, &mut sources, resolved_pins, options)
+        .context("Failed to rewrite the rewritten PKGBUILD again")?;
+    if again != pkgbuild {
+        debug!(
+            "Second rewrite of PKGBUILD diverged:\n{}",
+            interactive::diff(Path::new("PKGBUILD"), pkgbuild, &again)
+        );
+        bail!("Rewriting the PKGBUILD isn't stable, a second run would change it again");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(source: &str, commit: &str, tag: Option<&str>) -> ResolvedPin {
+        ResolvedPin {
+            commit_hash: commit.to_string(),
+            tag_hash: tag.map(String::from),
+            annotation: None,
+            source: source.parse().unwrap(),
+        }
+    }
+
+    fn rewrite_str(
+        pkgbuild: &str,
+        sources: &[&str],
+        pins: Vec<(&str, ResolvedPin)>,
+        options: &Options,
+    ) -> Result<String> {
+        let sources = sources.iter().map(|s| s.parse().unwrap()).collect();
+        let mut sources = BTreeMap::from([("source".to_string(), sources)]);
+        let pins = pins
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect::<BTreeMap<_, _>>();
+        let mut out = Vec::new();
+        rewrite(pkgbuild.as_bytes(), &mut out, &mut sources, &pins, options)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_streaming() {
+        let pkgbuild = "pkgname=foo\nsource=(\"git+https://example.com/foo.git#tag=v1\")\nbuild() {\n    make\n}\n";
+        let mut sources = BTreeMap::from([(
+            "source".to_string(),
+            vec!["git+https://example.com/foo.git#tag=v1".parse().unwrap()],
+        )]);
+        let pins = BTreeMap::from([(
+            "foo".to_string(),
+            pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+        )]);
+        // read through a tiny buffer to make sure nothing depends on having the whole file
+        let r = std::io::BufReader::with_capacity(1, pkgbuild.as_bytes());
+        let mut out = Vec::new();
+        rewrite(r, &mut out, &mut sources, &pins, &Options::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "pkgname=foo\nsource=(\n    \"git+https://example.com/foo.git#commit=c0ffee\"\n)\nbuild() {\n    make\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let out = rewrite_str(
+            "_foo_commit=old\r\nsource=(\"git+https://example.com/foo.git#tag=v1\")\r\n# no newline",
+            &["git+https://example.com/foo.git#tag=v1"],
+            vec![(
+                "foo",
+                pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "_foo_commit=c0ffee # v2\r\nsource=(\r\n    \"git+https://example.com/foo.git#commit=c0ffee\"\r\n)\r\n# no newline"
+        );
+    }
+
+    #[test]
+    fn test_keep_filename_override() {
+        let out = rewrite_str(
+            "source=(\"bar::git+https://example.com/foo.git#tag=v1\" \"fix.patch\")\n",
+            &["bar::git+https://example.com/foo.git#tag=v1", "fix.patch"],
+            vec![(
+                "bar",
+                pin(
+                    "git+https://example.com/foo.git#tag=v2",
+                    "c0ffee",
+                    Some("7a9"),
+                ),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "source=(\n    \"bar::git+https://example.com/foo.git#tag=7a9\"\n    \"fix.patch\"\n)\n"
+        );
+    }
+
+    #[test]
+    fn test_pin_both() {
+        let pkgbuild = "_foo_commit=old\nsource=(\"git+https://example.com/foo.git#tag=v1\")\n";
+        let rewrite = |pkgbuild: &str, tag: Option<&str>| {
+            rewrite_str(
+                pkgbuild,
+                &["git+https://example.com/foo.git#tag=v1"],
+                vec![(
+                    "foo",
+                    pin("git+https://example.com/foo.git#tag=v2", "c0ffee", tag),
+                )],
+                &Options {
+                    pin_both: true,
+                    ..Default::default()
+                },
+            )
+        };
+
+        assert_eq!(
+            rewrite(pkgbuild, Some("7a9")).unwrap(),
+            "_foo_commit=c0ffee # v2\nsource=(\n    \"git+https://example.com/foo.git#tag=7a9\"\n)\n"
+        );
+        assert!(rewrite(pkgbuild, None).is_err());
+        let pkgbuild = pkgbuild.replace("_foo_commit", "_foo_tag");
+        assert!(rewrite(&pkgbuild, Some("7a9")).is_err());
+    }
+
+    #[test]
+    fn test_main_pin() {
+        let rewrite = |main_pin: Option<&str>| {
+            rewrite_str(
+                "_commit=old\n_foo_data_commit=old\n",
+                &[],
+                vec![
+                    (
+                        "foo",
+                        pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+                    ),
+                    (
+                        "foo-data",
+                        pin("git+https://example.com/foo-data.git#tag=v3", "7a9", None),
+                    ),
+                ],
+                &Options {
+                    main_pin: main_pin.map(String::from),
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(
+            rewrite(Some("foo")).unwrap(),
+            "_commit=c0ffee # v2\n_foo_data_commit=7a9 # v3\n"
+        );
+        // the first pin of vcspins= wasn't selected, eg. with --repo
+        assert_eq!(
+            rewrite(Some("bar")).unwrap(),
+            "_commit=old\n_foo_data_commit=7a9 # v3\n"
+        );
+        assert!(rewrite(None).is_err());
+    }
+
+    #[test]
+    fn test_variable_declarations() {
+        let out = rewrite_str(
+            "declare -r _commit=old\n_pins() {\n    local _tag=\"old\"\n}\nreadonly _foo_commit=old\n",
+            &[],
+            vec![(
+                "foo",
+                pin(
+                    "git+https://example.com/foo.git#tag=v2",
+                    "c0ffee",
+                    Some("7a9"),
+                ),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "declare -r _commit=c0ffee # v2\n_pins() {\n    local _tag=\"7a9\"\n}\nreadonly _foo_commit=c0ffee # v2\n"
+        );
+    }
+
+    #[test]
+    fn test_variables_keep_style() {
+        let out = rewrite_str(
+            "_commit=\"old\" # 1.0.0\n_tag='old'  # tag: v1.0.0\n_foo_commit=old # pinned\n",
+            &[],
+            vec![(
+                "foo",
+                pin(
+                    "git+https://example.com/foo.git#tag=v2.0.0",
+                    "c0ffee",
+                    Some("7a9"),
+                ),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "_commit=\"c0ffee\" # 2.0.0\n_tag='7a9'  # tag: v2.0.0\n_foo_commit=c0ffee # pinned\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_assignment() {
+        assert_eq!(
+            Assignment::parse("_commit=\"abc\" # x"),
+            Some(Assignment {
+                prefix: "",
+                name: "_commit",
+                quote: "\"",
+                value: "abc",
+                rest: " # x",
+            })
+        );
+        assert_eq!(
+            Assignment::parse("_commit=abc"),
+            Some(Assignment {
+                prefix: "",
+                name: "_commit",
+                quote: "",
+                value: "abc",
+                rest: "",
+            })
+        );
+        assert_eq!(
+            Assignment::parse("  declare -r -g _commit=abc"),
+            Some(Assignment {
+                prefix: "  declare -r -g ",
+                name: "_commit",
+                quote: "",
+                value: "abc",
+                rest: "",
+            })
+        );
+        assert_eq!(Assignment::parse("_commit='abc"), None);
+        assert_eq!(Assignment::parse("foo bar=abc"), None);
+    }
+
+    #[test]
+    fn test_assignments() {
+        let pkgbuild = "pkgver=1.2.3 # comment\n_commit='abc'\nsource=(\"a::git+https://example.com/a.git#commit=$_commit\"\n        'b.patch')\n\npackage() {\n  local x=1\n}\n";
+        assert_eq!(
+            assignments(pkgbuild),
+            vec![
+                ("pkgver".to_string(), vec!["1.2.3".to_string()]),
+                ("_commit".to_string(), vec!["abc".to_string()]),
+                (
+                    "source".to_string(),
+                    vec![
+                        "a::git+https://example.com/a.git#commit=$_commit".to_string(),
+                        "b.patch".to_string(),
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_array_words() {
+        assert_eq!(
+            array_words("\"a\" 'b$x' \"c${d}\" # e $f\n  g$h\\ i"),
+            vec![
+                None,
+                None,
+                Some("c${d}".to_string()),
+                Some("g$h\\ i".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keep_variables() {
+        let out = rewrite_str(
+            "source=(\"git+https://github.com/$_owner/$pkgname.git#tag=v$pkgver\"\n        \"$pkgname.patch\")\n",
+            &[
+                "git+https://github.com/foo/foo.git#tag=v1",
+                "foo.patch",
+            ],
+            vec![(
+                "foo",
+                pin(
+                    "git+https://github.com/foo/foo.git#tag=v2",
+                    "c0ffee",
+                    Some("7a9"),
+                ),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "source=(\n    \"git+https://github.com/$_owner/$pkgname.git#tag=7a9\"\n    \"$pkgname.patch\"\n)\n"
+        );
+        // the ref is updated through the variable it references
+        let out = rewrite_str(
+            "_commit=old\n_bar_tag=old\nsource=(\"git+https://example.com/foo.git#commit=$_commit\"\n        \"git+https://example.com/bar.git#tag=${_bar_tag}?signed\")\n",
+            &[
+                "git+https://example.com/foo.git#commit=old",
+                "git+https://example.com/bar.git#tag=old?signed",
+            ],
+            vec![
+                (
+                    "foo",
+                    pin(
+                        "git+https://example.com/foo.git#tag=v2",
+                        "c0ffee",
+                        Some("7a9"),
+                    ),
+                ),
+                (
+                    "bar",
+                    pin(
+                        "git+https://example.com/bar.git#tag=v3",
+                        "f00",
+                        Some("ba5"),
+                    ),
+                ),
+            ],
+            &Options {
+                main_pin: Some("foo".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "_commit=c0ffee # v2\n_bar_tag=ba5\nsource=(\n    \"git+https://example.com/foo.git#commit=$_commit\"\n    \"git+https://example.com/bar.git#tag=${_bar_tag}?signed\"\n)\n"
+        );
+    }
+
+    #[test]
+    fn test_expand_commits() {
+        let bar = "abc1234def".repeat(4);
+        let baz = "abc1234aaa".repeat(4);
+        let out = rewrite_str(
+            "_bar_commit='abc1234'\nsource=(\"git+https://example.com/bar.git#commit=$_bar_commit\"\n        \"git+https://example.com/baz.git#commit=abc1234\"\n        \"git+https://example.com/qux.git#commit=abc1234\")\n",
+            &[
+                "git+https://example.com/bar.git#commit=abc1234",
+                "git+https://example.com/baz.git#commit=abc1234",
+                "git+https://example.com/qux.git#commit=abc1234",
+            ],
+            vec![],
+            &Options {
+                // the same abbreviation can be a different commit in another repository
+                commits: BTreeMap::from([
+                    (("bar".to_string(), "abc1234".to_string()), bar.clone()),
+                    (("baz".to_string(), "abc1234".to_string()), baz.clone()),
+                ]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "_bar_commit='{bar}'\nsource=(\n    \"git+https://example.com/bar.git#commit=$_bar_commit\"\n    \"git+https://example.com/baz.git#commit={baz}\"\n    \"git+https://example.com/qux.git#commit=abc1234\"\n)\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_update_assets() {
+        let old = "https://example.com/foo/archive/refs/tags/v1.tar.gz";
+        let new = "https://example.com/foo/archive/refs/tags/v2.tar.gz";
+        let out = rewrite_str(
+            &format!(
+                "source=(\"{old}\"\n        \"fix.patch\")\nsha256sums=(\n  \"aaaa\" # tarball\n  SKIP\n)\n"
+            ),
+            &[old, "fix.patch"],
+            vec![],
+            &Options {
+                urls: vec![(old.to_string(), new.to_string())],
+                checksums: BTreeMap::from([(
+                    "sha256sums".to_string(),
+                    vec!["bbbb".to_string(), "SKIP".to_string()],
+                )]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            // only the changed checksum is replaced, the layout of the array is kept
+            format!("source=(\n    \"{new}\"\n    \"fix.patch\"\n)\nsha256sums=(\n  \"bbbb\" # tarball\n  SKIP\n)\n")
+        );
+    }
+
+    #[test]
+    fn test_non_utf8() {
+        let mut pkgbuild = b"# Maintainer: J\xf6rg\r\n_commit=old\n".to_vec();
+        pkgbuild.extend(b"source=(\"git+https://example.com/foo.git#tag=v1\" # \xff\n)\n");
+        let mut sources = BTreeMap::from([(
+            "source".to_string(),
+            vec!["git+https://example.com/foo.git#tag=v1".parse().unwrap()],
+        )]);
+        let pins = BTreeMap::from([(
+            "foo".to_string(),
+            pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+        )]);
+        let mut out = Vec::new();
+        rewrite(
+            &pkgbuild[..],
+            &mut out,
+            &mut sources,
+            &pins,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            b"# Maintainer: J\xf6rg\r\n_commit=c0ffee # v2\nsource=(\n    \"git+https://example.com/foo.git#commit=c0ffee\"\n)\n"
+        );
+    }
+
+    fn rewrite_err(pkgbuild: &str, sources: &[&str]) -> String {
+        let sources = sources.iter().map(|s| s.parse().unwrap()).collect();
+        let mut sources = BTreeMap::from([("source".to_string(), sources)]);
+        let pins = BTreeMap::from([(
+            "foo".to_string(),
+            pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+        )]);
+        let err = rewrite(
+            pkgbuild.as_bytes(),
+            Vec::new(),
+            &mut sources,
+            &pins,
+            &Options::default(),
+        )
+        .unwrap_err();
+        format!("{:#}", err)
+    }
+
+    #[test]
+    fn test_conditional_arrays() {
+        let foo = "git+https://example.com/foo.git#tag=v1";
+        let err = rewrite_err(
+            &format!("if true; then\nsource=(\"{foo}\")\nelse\nsource=(\"{foo}\" \"x\")\nfi\n"),
+            &[foo],
+        );
+        assert!(err.contains("assigned more than once"), "{}", err);
+        let err = rewrite_err(
+            &format!("if true; then\n  source=(\"{foo}\")\nfi\n"),
+            &[foo],
+        );
+        assert!(err.contains("line 2 is indented"), "{}", err);
+        let err = rewrite_err(
+            &format!("source=(\"a\")\nsource+=(\"{foo}\")\n"),
+            &["a", foo],
+        );
+        assert!(err.contains("assigned more than once"), "{}", err);
+        let err = rewrite_err(
+            &format!("_srcs=(\"{foo}\")\nsource=(\"${{_srcs[@]}}\")\n"),
+            &[foo],
+        );
+        assert!(err.contains("computed by code"), "{}", err);
+        let err = rewrite_err("source=($(cat sources))\n", &[foo]);
+        assert!(err.contains("computed by code"), "{}", err);
+        let err = rewrite_err(&format!("source=({foo} x{{1,2}})\n"), &[foo, "x1", "x2"]);
+        assert!(err.contains("lists 2 entries but has 3"), "{}", err);
+    }
+
+    #[test]
+    fn test_array_comments() {
+        let out = rewrite_str(
+            "source=(\"git+https://example.com/foo.git#tag=v1\") # (pinned)\nsource_x86_64=(\"a#b\" # c)\n  \"d\") # e\n",
+            &["git+https://example.com/foo.git#tag=v1"],
+            vec![(
+                "foo",
+                pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "source=(\n    \"git+https://example.com/foo.git#commit=c0ffee\"\n)\nsource_x86_64=(\"a#b\" # c)\n  \"d\") # e\n"
+        );
+    }
+
+    #[test]
+    fn test_source_comments() {
+        let rewrite = |pkgbuild: &str, source: &str, commit: &str, date: &str| {
+            rewrite_str(
+                pkgbuild,
+                &[source],
+                vec![(
+                    "foo",
+                    pin("git+https://example.com/foo.git#tag=v2", commit, None),
+                )],
+                &Options {
+                    source_comments: Some(date.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let out = rewrite(
+            "source=(\"git+https://example.com/foo.git#tag=v1\")\n",
+            "git+https://example.com/foo.git#tag=v1",
+            "c0ffee",
+            "2024-01-31",
+        );
+        let expected = "source=(\n    # foo: v2, resolved 2024-01-31\n    \"git+https://example.com/foo.git#commit=c0ffee\"\n)\n";
+        assert_eq!(out, expected);
+
+        // unchanged entries keep the date they were resolved at
+        let source = "git+https://example.com/foo.git#commit=c0ffee";
+        assert_eq!(rewrite(&out, source, "c0ffee", "2024-02-29"), expected);
+        assert_eq!(
+            rewrite(&out, source, "7a9", "2024-02-29"),
+            "source=(\n    # foo: v2, resolved 2024-02-29\n    \"git+https://example.com/foo.git#commit=7a9\"\n)\n"
+        );
+    }
+
+    #[test]
+    fn test_add_validpgpkeys() {
+        let rewrite = |pkgbuild: &str| {
+            let options = Options {
+                validpgpkeys: vec!["AAAA".to_string(), "BBBB".to_string()],
+                ..Default::default()
+            };
+            rewrite_str(pkgbuild, &[], vec![], &options).unwrap()
+        };
+        assert_eq!(
+            rewrite("validpgpkeys=('CCCC') # Jane\n"),
+            "validpgpkeys=('CCCC'\n              'AAAA'\n              'BBBB') # Jane\n"
+        );
+        assert_eq!(
+            rewrite("validpgpkeys=(\n  'CCCC' # Jane\n  'aaaa' # John\n)\n"),
+            "validpgpkeys=(\n  'CCCC' # Jane\n  'aaaa' # John\n  'BBBB'\n)\n"
+        );
+        assert_eq!(
+            rewrite("pkgver=1\nsource=(\"foo\")\n"),
+            "pkgver=1\nvalidpgpkeys=('AAAA'\n              'BBBB')\nsource=(\"foo\")\n"
+        );
+        let pkgbuild = "validpgpkeys=('AAAA'  # Jane\n              'BBBB') # John\n";
+        assert_eq!(rewrite(pkgbuild), pkgbuild);
+    }
+
+    #[test]
+    fn test_toggle_signed() {
+        let out = rewrite_str(
+            "_url=https://example.com\nsource=(\"git+$_url/foo.git#tag=v1\"\n        \"git+https://example.com/bar.git?signed#tag=v1\")\n",
+            &[
+                "git+https://example.com/foo.git#tag=v1",
+                "git+https://example.com/bar.git?signed#tag=v1",
+            ],
+            vec![
+                (
+                    "foo",
+                    pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+                ),
+                (
+                    "bar",
+                    pin("git+https://example.com/bar.git#tag=v2", "7a9", None),
+                ),
+            ],
+            &Options {
+                signed: BTreeMap::from([("foo".to_string(), true), ("bar".to_string(), false)]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "_url=https://example.com\nsource=(\n    \"git+$_url/foo.git#commit=c0ffee?signed\"\n    \"git+https://example.com/bar.git#commit=7a9\"\n)\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_pin_variables() {
+        let pins = BTreeMap::from([(
+            "foo".to_string(),
+            pin(
+                "git+https://example.com/foo.git#tag=v2",
+                "c0ffee",
+                Some("7a9"),
+            ),
+        )]);
+        let pkgbuild = "_tag=7a9 # v2\n_tagline=x\nsource=(\"git+https://example.com/foo.git#tag=$_tag\")\nprepare() {\n  echo ${_tag} $_tagline _tag\n}\n";
+        assert_eq!(
+            String::from_utf8(rename_pin_variables(pkgbuild.as_bytes(), &pins, "tag", "commit")).unwrap(),
+            "_commit=7a9 # v2\n_tagline=x\nsource=(\"git+https://example.com/foo.git#tag=$_commit\")\nprepare() {\n  echo ${_commit} $_tagline _tag\n}\n"
+        );
+        // an existing variable with the new name is kept
+        let pkgbuild = "_tag=7a9\n_commit=c0ffee\n";
+        assert_eq!(
+            rename_pin_variables(pkgbuild.as_bytes(), &pins, "tag", "commit"),
+            pkgbuild.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_keep_url_spelling() {
+        let out = rewrite_str(
+            "source=(\"git+https://example.com/foo.git/#tag=v1\")\n",
+            &["git+https://example.com/foo.git/#tag=v1"],
+            vec![(
+                "foo",
+                pin("git+https://example.com/foo#tag=v2", "c0ffee", None),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "source=(\n    \"git+https://example.com/foo.git/#commit=c0ffee\"\n)\n"
+        );
+    }
+
+    #[test]
+    fn test_array_layout() {
+        let rewrite = |options: Options| {
+            rewrite_str(
+                "source=(\"git+https://example.com/foo.git#tag=v1\" \"foo-$pkgver.patch\")\n",
+                &["git+https://example.com/foo.git#tag=v1", "foo-1.patch"],
+                vec![(
+                    "foo",
+                    pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+                )],
+                &options,
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            rewrite(Options {
+                array_layout: ArrayLayout::Wrapped,
+                quote: Quote::Single,
+                ..Default::default()
+            }),
+            "source=('git+https://example.com/foo.git#commit=c0ffee'\n        \"foo-$pkgver.patch\")\n"
+        );
+        assert_eq!(
+            rewrite(Options {
+                array_indent: Some(2),
+                ..Default::default()
+            }),
+            "source=(\n  \"git+https://example.com/foo.git#commit=c0ffee\"\n  \"foo-$pkgver.patch\"\n)\n"
+        );
+        // the comment of the first entry moves it to the next line
+        assert_eq!(
+            rewrite(Options {
+                array_layout: ArrayLayout::Wrapped,
+                source_comments: Some("2024-01-31".to_string()),
+                ..Default::default()
+            }),
+            "source=(\n        # foo: v2, resolved 2024-01-31\n        \"git+https://example.com/foo.git#commit=c0ffee\"\n        \"foo-$pkgver.patch\")\n"
+        );
+    }
+
+    #[test]
+    fn test_unchanged_arrays() {
+        let pkgbuild =
+            "source=(\"fix.patch\"\n        \"git+https://example.com/bar.git#tag=v1\")\n";
+        let out = rewrite_str(
+            pkgbuild,
+            &["fix.patch", "git+https://example.com/bar.git#tag=v1"],
+            vec![(
+                "foo",
+                pin("git+https://example.com/foo.git#tag=v2", "c0ffee", None),
+            )],
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(out, pkgbuild);
+    }
+}
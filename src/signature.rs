@@ -0,0 +1,269 @@
+use crate::errors::*;
+use crate::keyring;
+use crate::makepkg::ResolvedPin;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Which keys are trusted to sign the pinned tags and commits
+#[derive(Debug, Default, Clone)]
+pub struct Policy {
+    /// OpenPGP fingerprints of the primary keys, from `validpgpkeys=`
+    pub validpgpkeys: Vec<String>,
+    /// File in the format of `ssh-keygen -Y verify` listing the trusted ssh keys
+    pub allowed_signers: Option<PathBuf>,
+    /// Use this gpg home instead of the default keyring
+    pub gnupghome: Option<PathBuf>,
+    /// Keyrings with keys that are trusted even if they aren't in validpgpkeys=, eg. archlinux-keyring
+    pub trusted_keyrings: Vec<PathBuf>,
+}
+
+impl Policy {
+    fn is_trusted(&self, fingerprint: &str) -> Result<bool> {
+        if self
+            .validpgpkeys
+            .iter()
+            .any(|key| key.eq_ignore_ascii_case(fingerprint))
+        {
+            return Ok(true);
+        }
+        for keyring in &self.trusted_keyrings {
+            if keyring::is_valid(keyring, fingerprint)? {
+                debug!("Key {:?} is trusted by {:?}", fingerprint, keyring);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The kind of tag a repository has to be pinned to
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RequiredTag {
+    /// A tag object, lightweight tags are rejected
+    Annotated,
+    /// A tag object with a signature, use --verify-signatures to also check who made it
+    Signed,
+}
+
+impl FromStr for RequiredTag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "annotated" => Ok(RequiredTag::Annotated),
+            "signed" => Ok(RequiredTag::Signed),
+            _ => bail!(
+                "Unknown tag requirement, expected annotated or signed: {:?}",
+                s
+            ),
+        }
+    }
+}
+
+impl RequiredTag {
+    pub fn check(&self, pin: &ResolvedPin) -> Result<()> {
+        if pin.tag_hash.is_none() {
+            bail!("Pin is not a tag, but a {:?} tag is required", self);
+        }
+        let Some(annotation) = &pin.annotation else {
+            bail!("Tag is a lightweight tag, but an annotated tag is required");
+        };
+        if *self == RequiredTag::Signed && !annotation.signed {
+            bail!("Tag has no signature, but a signed tag is required");
+        }
+        Ok(())
+    }
+}
+
+/// The signature was made by an OpenPGP key that isn't in the keyring
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MissingKey(pub String);
+
+impl fmt::Display for MissingKey {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "Signing key {} is not in the keyring", self.0)
+    }
+}
+
+impl std::error::Error for MissingKey {}
+
+/// The signature is valid, but made by an OpenPGP key that isn't trusted
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UntrustedKey(pub String);
+
+impl fmt::Display for UntrustedKey {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            w,
+            "Signing key {} is neither listed in validpgpkeys= nor in a trusted keyring",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UntrustedKey {}
+
+/// The kind of object that carries the signature
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Object {
+    Tag,
+    Commit,
+}
+
+impl Object {
+    fn verify_command(&self) -> &'static str {
+        match self {
+            Object::Tag => "verify-tag",
+            Object::Commit => "verify-commit",
+        }
+    }
+}
+
+/// The key a valid signature was made with
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Signer {
+    /// Fingerprint of the primary key
+    OpenPgp(String),
+    /// The line of `ssh-keygen` describing the principal and key
+    Ssh(String),
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Signer::OpenPgp(fingerprint) => write!(w, "OpenPGP key {}", fingerprint),
+            Signer::Ssh(line) => write!(w, "{}", line),
+        }
+    }
+}
+
+/// Find the primary key fingerprint of a good signature in gpg's `--status-fd` output
+///
+/// Same as makepkg, expired or revoked keys and bad signatures are rejected.
+fn parse_gpg_status(status: &str) -> Result<Option<String>> {
+    let mut good = false;
+    let mut fingerprint = None;
+    for line in status.lines() {
+        let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+            continue;
+        };
+        let mut fields = line.split(' ');
+        match fields.next() {
+            Some("GOODSIG") => good = true,
+            Some("VALIDSIG") => {
+                // the primary key fingerprint is the last field, 10 fields in
+                let fields = fields.collect::<Vec<_>>();
+                fingerprint = fields.get(9).or(fields.first()).map(|f| f.to_string());
+            }
+            Some(status @ ("BADSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG" | "ERRSIG")) => {
+                bail!("Signature can't be trusted: {}", status);
+            }
+            _ => (),
+        }
+    }
+    Ok(fingerprint.filter(|_| good))
+}
+
+/// Find the successful verification line in the output of `ssh-keygen -Y verify`
+fn parse_ssh_output(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find(|line| line.starts_with("Good \"git\" signature for "))
+        .map(String::from)
+}
+
+/// Verify the signature of a tag or commit in the repository against the policy
+pub fn verify(repo_path: &Path, object: Object, hash: &str, policy: &Policy) -> Result<Signer> {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(repo_path);
+    if let Some(allowed_signers) = &policy.allowed_signers {
+        let mut config = OsStr::new("gpg.ssh.allowedSignersFile=").to_owned();
+        config.push(allowed_signers);
+        cmd.arg("-c").arg(config);
+    }
+    if let Some(gnupghome) = &policy.gnupghome {
+        cmd.env("GNUPGHOME", gnupghome);
+    }
+    cmd.args([object.verify_command(), "--raw", "--", hash]);
+    debug!("Executing git: {:?}", cmd);
+    let output = cmd
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run git")?;
+    // both gpg and ssh-keygen report on stderr with --raw
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!("Signature verification output: {:?}", stderr);
+
+    if let Some(key) = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] NO_PUBKEY "))
+    {
+        return Err(MissingKey(key.trim().to_string()).into());
+    }
+    if !output.status.success() {
+        bail!(
+            "No valid signature on {:?}: {}",
+            hash,
+            stderr.lines().last().unwrap_or_default()
+        );
+    }
+
+    if let Some(fingerprint) = parse_gpg_status(&stderr)? {
+        if !policy.is_trusted(&fingerprint)? {
+            return Err(UntrustedKey(fingerprint).into());
+        }
+        Ok(Signer::OpenPgp(fingerprint))
+    } else if let Some(line) = parse_ssh_output(&stderr) {
+        // ssh-keygen only accepts keys from the allowed signers file
+        if policy.allowed_signers.is_none() {
+            bail!(
+                "Found an ssh signature on {:?} but no allowed signers are configured",
+                hash
+            );
+        }
+        Ok(Signer::Ssh(line))
+    } else {
+        bail!("Failed to find a valid signature on {:?}", hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gpg_status() {
+        let status = "[GNUPG:] NEWSIG\n[GNUPG:] KEY_CONSIDERED 64B13F7117D6E07D661BBCE0FE763A64F5E54FD6 0\n[GNUPG:] SIG_ID x 2023-01-01 1672531200\n[GNUPG:] GOODSIG FE763A64F5E54FD6 kpcyrd <git@rxv.cc>\n[GNUPG:] VALIDSIG 4A5B2FD7A3E1C4D4E8D8B9F0A1B2C3D4E5F6A7B8 2023-01-01 1672531200 0 4 0 22 10 00 64B13F7117D6E07D661BBCE0FE763A64F5E54FD6\n[GNUPG:] TRUST_UNDEFINED 0 pgp\n";
+        assert_eq!(
+            parse_gpg_status(status).unwrap().as_deref(),
+            Some("64B13F7117D6E07D661BBCE0FE763A64F5E54FD6")
+        );
+        assert!(parse_gpg_status(&status.replace("GOODSIG", "EXPKEYSIG")).is_err());
+        assert_eq!(parse_gpg_status("[GNUPG:] NEWSIG\n").unwrap(), None);
+    }
+
+    #[test]
+    fn test_required_tag() {
+        let pin = |tag_hash: Option<&str>, signed: Option<bool>| ResolvedPin {
+            commit_hash: "c0ffee".to_string(),
+            tag_hash: tag_hash.map(String::from),
+            annotation: signed.map(|signed| crate::makepkg::TagAnnotation {
+                tagger: None,
+                message: "v1\n".to_string(),
+                signed,
+            }),
+            source: "git+https://example.com/foo.git#tag=v1".parse().unwrap(),
+        };
+        let annotated = "annotated".parse::<RequiredTag>().unwrap();
+        let signed = "signed".parse::<RequiredTag>().unwrap();
+
+        assert!(annotated.check(&pin(Some("7a9"), Some(false))).is_ok());
+        assert!(signed.check(&pin(Some("7a9"), Some(false))).is_err());
+        assert!(signed.check(&pin(Some("7a9"), Some(true))).is_ok());
+        assert!(annotated.check(&pin(Some("c0ffee"), None)).is_err());
+        assert!(annotated.check(&pin(None, None)).is_err());
+    }
+}
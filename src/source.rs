@@ -0,0 +1,408 @@
+use crate::errors::*;
+use crate::git::{self, GitSource};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use url::Url;
+
+/// A makepkg source string split into its components: `name::proto+url#fragment?query`
+///
+/// The fragment and query can be given in either order, the original order is kept so the
+/// string is reproduced exactly by `Display`.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SourceString {
+    pub name: Option<String>,
+    pub url: String,
+    pub fragment: Option<String>,
+    pub query: Option<String>,
+    pub query_first: bool,
+}
+
+impl SourceString {
+    /// The protocol makepkg uses to pick a download agent, eg. `git` for `git+https://`
+    pub fn protocol(&self) -> Option<&str> {
+        if git::split_scp_url(&self.url).is_some() {
+            return Some("git");
+        }
+        let (scheme, _) = self.url.split_once("://")?;
+        Some(scheme.split_once('+').map_or(scheme, |x| x.0))
+    }
+}
+
+impl fmt::Display for SourceString {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(w, "{}::", name)?;
+        }
+        write!(w, "{}", self.url)?;
+        if self.query_first {
+            if let Some(query) = &self.query {
+                write!(w, "?{}", query)?;
+            }
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(w, "#{}", fragment)?;
+        }
+        if !self.query_first {
+            if let Some(query) = &self.query {
+                write!(w, "?{}", query)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SourceString {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, s) = match s.split_once("::") {
+            Some((name, s)) => (Some(name.to_string()), s),
+            None => (None, s),
+        };
+
+        // same semantics as makepkg's get_uri_fragment and get_uri_query:
+        // the fragment starts after the first `#` and ends at the last `?`,
+        // the query starts after the first `?` and ends at the first `#`
+        let url = &s[..s.find(['#', '?']).unwrap_or(s.len())];
+        let fragment = s
+            .split_once('#')
+            .map(|(_, f)| f.rsplit_once('?').map_or(f, |(f, _)| f))
+            .filter(|f| !f.is_empty())
+            .map(String::from);
+        let query = s
+            .split_once('?')
+            .map(|(_, q)| q.split_once('#').map_or(q, |(q, _)| q))
+            .filter(|q| !q.is_empty())
+            .map(String::from);
+        let query_first = matches!((s.find('?'), s.find('#')), (Some(q), Some(f)) if q < f);
+
+        if url.is_empty() {
+            bail!("Source url can't be empty");
+        }
+
+        Ok(SourceString {
+            name,
+            url: url.to_string(),
+            fragment,
+            query,
+            query_first,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Input {
+    Url(Source),
+    UrlWithFilename((Source, String)),
+}
+
+impl Input {
+    pub fn filename(&self) -> Result<Cow<'_, str>> {
+        match self {
+            Input::Url(source) => source.filename(),
+            Input::UrlWithFilename((_, filename)) => Ok(Cow::Borrowed(filename)),
+        }
+    }
+
+    pub fn source(&self) -> &Source {
+        match self {
+            Input::Url(url) => url,
+            Input::UrlWithFilename((url, _file)) => url,
+        }
+    }
+
+    pub fn source_mut(&mut self) -> &mut Source {
+        match self {
+            Input::Url(url) => url,
+            Input::UrlWithFilename((url, _file)) => url,
+        }
+    }
+
+    pub fn take_source(self) -> Source {
+        match self {
+            Input::Url(url) => url,
+            Input::UrlWithFilename((url, _file)) => url,
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Url(url) => write!(w, "{}", url),
+            Input::UrlWithFilename((url, file)) => write!(w, "{}::{}", file, url),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    File(String),
+    Url(String),
+    Git(GitSource),
+    /*
+    Svn(SvnSource),
+    Hg(HgSource),
+    Bzr(BzrSource),
+    */
+}
+
+impl Source {
+    pub fn filename(&self) -> Result<Cow<'_, str>> {
+        let filename = match self {
+            Source::File(path) => {
+                let p = Path::new(path);
+                let filename = p.file_name().context("Missing filename")?;
+                let filename = filename.to_str().context("Filename is invalid utf8")?;
+                Cow::Borrowed(filename)
+            }
+            Source::Url(url) => {
+                let url = url.parse::<Url>()?;
+                let filename = url
+                    .path_segments()
+                    .context("Url contains no path")?
+                    .next_back()
+                    .context("Path has no filename")?;
+                Cow::Owned(filename.to_string())
+            }
+            Source::Git(git) => {
+                let url;
+                let filename = if let Some((_host, path)) = git::split_scp_url(&git.url) {
+                    path.trim_end_matches('/')
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or(path)
+                } else {
+                    url = git.url.parse::<Url>()?;
+                    // like makepkg, a trailing slash is ignored
+                    let path = url.path();
+                    path.strip_suffix('/')
+                        .unwrap_or(path)
+                        .rsplit('/')
+                        .next()
+                        .context("Path has no filename")?
+                };
+                // makepkg strips everything starting at `.git`
+                let filename = match filename.find(".git") {
+                    Some(idx) => &filename[..idx],
+                    None => filename,
+                };
+                Cow::Owned(filename.to_string())
+            }
+        };
+        if filename.is_empty() {
+            bail!("Filename can't be empty");
+        }
+        Ok(filename)
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(s) => write!(w, "{}", s),
+            Source::Url(s) => write!(w, "{}", s),
+            Source::Git(s) => write!(w, "{}", s),
+        }
+    }
+}
+
+impl FromStr for Input {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parsed = s.parse::<SourceString>()?;
+        if let Some(name) = parsed.name.take() {
+            let source = Source::try_from(parsed)?;
+            Ok(Input::UrlWithFilename((source, name)))
+        } else {
+            let source = Source::try_from(parsed)?;
+            Ok(Input::Url(source))
+        }
+    }
+}
+
+impl TryFrom<SourceString> for Source {
+    type Error = Error;
+
+    fn try_from(s: SourceString) -> Result<Self> {
+        if let Some(name) = &s.name {
+            bail!("Source has an unexpected filename override: {:?}", name);
+        }
+        Ok(match s.protocol() {
+            Some("https") => Source::Url(s.to_string()),
+            Some("http") => Source::Url(s.to_string()),
+            Some("ftp") => Source::Url(s.to_string()),
+            Some("git") => Source::Git(s.try_into()?),
+            Some(protocol) => bail!("Unknown protocol: {:?}", protocol),
+            None => Source::File(s.to_string()),
+        })
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<SourceString>()?.try_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> SourceString {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for s in [
+            "foo.patch",
+            "https://example.com/foo.tar.gz",
+            "git+https://example.com/foo.git",
+            "git+https://example.com/foo.git#tag=v1.0.0",
+            "git+https://example.com/foo.git#commit=abc?signed",
+            "git+https://example.com/foo.git?signed#tag=v1.0.0",
+            "git+https://example.com/foo.git?signed",
+            "foo::git+https://example.com/bar.git#branch=main",
+            "foo::git+https://example.com/bar.git?signed#tag=release/v1",
+        ] {
+            assert_eq!(parse(s).to_string(), s);
+            assert_eq!(s.parse::<Input>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_source_protocol() {
+        assert_eq!(
+            "foo.patch".parse::<Source>().unwrap(),
+            Source::File("foo.patch".to_string())
+        );
+        assert_eq!(
+            "https://example.com/foo.tar.gz".parse::<Source>().unwrap(),
+            Source::Url("https://example.com/foo.tar.gz".to_string())
+        );
+        assert!(matches!(
+            "git://example.com/foo.git".parse::<Source>().unwrap(),
+            Source::Git(_)
+        ));
+        assert!("svn+https://example.com/foo".parse::<Source>().is_err());
+        assert!("foo::foo.patch".parse::<Source>().is_err());
+        assert_eq!(
+            "foo::foo.patch".parse::<Input>().unwrap(),
+            Input::UrlWithFilename((Source::File("foo.patch".to_string()), "foo".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_fragment_first() {
+        let s = parse("git+https://example.com/foo#tag=v1?signed");
+        assert_eq!(s.url, "git+https://example.com/foo");
+        assert_eq!(s.fragment.as_deref(), Some("tag=v1"));
+        assert_eq!(s.query.as_deref(), Some("signed"));
+        assert!(!s.query_first);
+    }
+
+    #[test]
+    fn test_parse_query_first() {
+        let s = parse("git+https://example.com/foo?signed#tag=v1");
+        assert_eq!(s.url, "git+https://example.com/foo");
+        assert_eq!(s.fragment.as_deref(), Some("tag=v1"));
+        assert_eq!(s.query.as_deref(), Some("signed"));
+        assert!(s.query_first);
+    }
+
+    #[test]
+    fn test_parse_like_makepkg() {
+        // fragment ends at the last `?`, query ends at the first `#`
+        let s = parse("git+https://example.com/foo#commit=abc?signed#tag=v1");
+        assert_eq!(s.url, "git+https://example.com/foo");
+        assert_eq!(s.fragment.as_deref(), Some("commit=abc"));
+        assert_eq!(s.query.as_deref(), Some("signed"));
+
+        let s = parse("git+https://example.com/foo?signed#tag=v1?x");
+        assert_eq!(s.fragment.as_deref(), Some("tag=v1"));
+        assert_eq!(s.query.as_deref(), Some("signed"));
+
+        let s = parse("git+https://example.com/foo?signed#tag=a#b");
+        assert_eq!(s.fragment.as_deref(), Some("tag=a#b"));
+        assert_eq!(s.query.as_deref(), Some("signed"));
+        assert_eq!(s.to_string(), "git+https://example.com/foo?signed#tag=a#b");
+    }
+
+    #[test]
+    fn test_parse_empty_fragment() {
+        let s = parse("git+https://example.com/foo#");
+        assert_eq!(s.fragment, None);
+        assert_eq!(s.query, None);
+    }
+
+    #[test]
+    fn test_parse_name() {
+        let s = parse("foo::git+https://example.com/bar#tag=v1");
+        assert_eq!(s.name.as_deref(), Some("foo"));
+        assert_eq!(s.url, "git+https://example.com/bar");
+        assert_eq!(s.protocol(), Some("git"));
+    }
+
+    #[test]
+    fn test_filename() {
+        let input = "git+https://example.com/foo.git#tag=v1"
+            .parse::<Input>()
+            .unwrap();
+        assert_eq!(input.filename().unwrap(), "foo");
+        let input = "bar::git+https://example.com/foo.git"
+            .parse::<Input>()
+            .unwrap();
+        assert_eq!(input.filename().unwrap(), "bar");
+        let input = "https://example.com/foo.tar.gz".parse::<Input>().unwrap();
+        assert_eq!(input.filename().unwrap(), "foo.tar.gz");
+        for s in [
+            "git+https://example.com/foo",
+            "git+https://example.com/foo/",
+            "git+https://example.com/foo.git/#tag=v1",
+        ] {
+            assert_eq!(s.parse::<Input>().unwrap().filename().unwrap(), "foo");
+        }
+    }
+
+    #[test]
+    fn test_git_filename() {
+        for (s, filename) in [
+            ("git+https://example.com/foo.git#tag=v1", "foo"),
+            ("git+https://example.com/foo", "foo"),
+            ("git+https://example.com/foo.github.io.git", "foo"),
+            ("bar::git+https://example.com/foo.git", "bar"),
+        ] {
+            let input = s.parse::<Input>().unwrap();
+            assert_eq!(input.filename().unwrap(), filename);
+        }
+    }
+
+    #[test]
+    fn test_scp_url() {
+        for (s, filename) in [
+            ("git@github.com:org/repo.git#tag=v1", "repo"),
+            ("git+git@github.com:org/repo#tag=v1", "repo"),
+            ("example.com:repo.git/", "repo"),
+        ] {
+            let input = s.parse::<Input>().unwrap();
+            assert!(matches!(input.source(), Source::Git(_)));
+            assert_eq!(input.filename().unwrap(), filename);
+            assert_eq!(input.to_string(), s);
+        }
+        for s in ["./foo:bar.patch", "foo:bar.patch", "fix:typo.patch"] {
+            let input = s.parse::<Input>().unwrap();
+            assert!(matches!(input.source(), Source::File(_)), "{s}");
+        }
+        let input = "git+foo:bar.git".parse::<Input>().unwrap();
+        assert!(matches!(input.source(), Source::Git(_)));
+    }
+}
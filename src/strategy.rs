@@ -0,0 +1,196 @@
+use crate::errors::*;
+use crate::events::Observer;
+use crate::forge::{self, Forge};
+use crate::git::{self, GitSource};
+use crate::makepkg::ResolvedPin;
+use crate::policy;
+use crate::provenance::Resolver;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A way to resolve the ref of a source
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Strategy {
+    /// Query the API of the forge, eg. GitHub or GitLab
+    Api,
+    /// Run `git ls-remote`
+    LsRemote,
+    /// Clone the repository, or fetch an existing clone
+    Clone,
+}
+
+impl FromStr for Strategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "api" => Ok(Strategy::Api),
+            "ls-remote" => Ok(Strategy::LsRemote),
+            "clone" => Ok(Strategy::Clone),
+            _ => bail!(
+                "Unknown strategy, expected api, ls-remote or clone: {:?}",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strategy::Api => write!(w, "api"),
+            Strategy::LsRemote => write!(w, "ls-remote"),
+            Strategy::Clone => write!(w, "clone"),
+        }
+    }
+}
+
+/// The strategies to try in order by host pattern, the first matching pattern is used
+#[derive(Debug, Default, Clone)]
+pub struct Chains {
+    chains: Vec<(String, Vec<Strategy>)>,
+}
+
+impl Chains {
+    /// Parse `PATTERN=STRATEGY[,STRATEGY...]` entries, eg. `github.com=api,ls-remote`
+    pub fn parse(values: &[String]) -> Result<Self> {
+        let mut chains = Vec::new();
+        for value in values {
+            let Some((pattern, strategies)) = value.split_once('=') else {
+                bail!("Expected PATTERN=STRATEGY[,STRATEGY...]: {:?}", value);
+            };
+            let strategies = strategies
+                .split(',')
+                .map(Strategy::from_str)
+                .collect::<Result<Vec<_>>>()?;
+            chains.push((pattern.to_string(), strategies));
+        }
+        Ok(Chains { chains })
+    }
+
+    /// If every configured strategy resolves in a local clone
+    pub fn only_clone(&self) -> bool {
+        self.chains
+            .iter()
+            .all(|(_, strategies)| strategies.iter().all(|s| *s == Strategy::Clone))
+    }
+
+    /// The strategies configured for the host of a remote url, if any
+    pub fn for_url(&self, url: &str) -> Option<&[Strategy]> {
+        let (_scheme, host) = git::remote_host(url)?;
+        self.chains
+            .iter()
+            .find(|(pattern, _)| policy::matches(std::slice::from_ref(pattern), &host))
+            .map(|(_, strategies)| strategies.as_slice())
+    }
+}
+
+fn resolve_with(
+    strategy: Strategy,
+    source: GitSource,
+    repo_path: &Path,
+    options: &git::Options,
+    hosts: &forge::Hosts,
+    observer: &dyn Observer,
+) -> Result<(ResolvedPin, Resolver)> {
+    match strategy {
+        Strategy::Api => {
+            let Some(forge) = Forge::detect(source.remote_url(), hosts) else {
+                bail!("No supported forge for {:?}", source.remote_url());
+            };
+            let name = source.tag.clone().or(source.branch.clone());
+            let pin = forge.resolve(source)?;
+            observer.pin_resolved(name.as_deref().unwrap_or_default(), &pin);
+            Ok((pin, Resolver::ForgeApi))
+        }
+        Strategy::LsRemote => Ok((
+            git::ls_remote(source, options, observer)?,
+            Resolver::LsRemote,
+        )),
+        Strategy::Clone => {
+            let options = git::Options {
+                ls_remote: false,
+                ..options.clone()
+            };
+            Ok((
+                git::run(source, repo_path, &options, observer)?,
+                Resolver::Git,
+            ))
+        }
+    }
+}
+
+/// Resolve the ref of a source with the strategies configured for its host, trying them in order
+pub fn resolve(
+    chains: &Chains,
+    source: GitSource,
+    repo_path: &Path,
+    options: &git::Options,
+    hosts: &forge::Hosts,
+    observer: &dyn Observer,
+) -> Result<(ResolvedPin, Resolver)> {
+    let default = [if options.ls_remote {
+        Strategy::LsRemote
+    } else {
+        Strategy::Clone
+    }];
+    let strategies = chains.for_url(&source.url).unwrap_or(&default);
+    let mut strategies = strategies.iter().peekable();
+    while let Some(strategy) = strategies.next() {
+        debug!("Resolving {:?} with {}", source.remote_url(), strategy);
+        match resolve_with(
+            *strategy,
+            source.clone(),
+            repo_path,
+            options,
+            hosts,
+            observer,
+        ) {
+            Err(err) if strategies.peek().is_some() => {
+                warn!(
+                    "Failed to resolve {:?} with {}, trying next strategy: {:#}",
+                    source.remote_url(),
+                    strategy,
+                    err
+                );
+            }
+            result => {
+                return result.with_context(|| anyhow!("Failed to resolve with {}", strategy))
+            }
+        }
+    }
+    bail!("No strategies configured for {:?}", source.remote_url())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chains() {
+        let chains = Chains::parse(&[
+            "github.com=api,ls-remote".to_string(),
+            "*.internal=clone".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            chains.for_url("https://github.com/kpcyrd/updvcspins.git"),
+            Some(&[Strategy::Api, Strategy::LsRemote][..])
+        );
+        assert_eq!(
+            chains.for_url("ssh://git@git.corp.internal/foo.git"),
+            Some(&[Strategy::Clone][..])
+        );
+        assert_eq!(
+            chains.for_url("git@git.corp.internal:foo.git"),
+            Some(&[Strategy::Clone][..])
+        );
+        assert_eq!(chains.for_url("https://gitlab.com/foo/bar.git"), None);
+        assert!(!chains.only_clone());
+        assert!(Chains::parse(&["*.internal=clone".to_string()])
+            .unwrap()
+            .only_clone());
+        assert!(Chains::parse(&["github.com=api,svn".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::events::{Observer, Progress};
+use crate::makepkg::ResolvedPin;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Wall-time spent in each phase of a run, printed to stderr when dropped if enabled
+#[derive(Debug)]
+pub struct Timings {
+    enabled: bool,
+    start: Instant,
+    phases: Mutex<Vec<(String, Duration)>>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Self {
+        Timings {
+            enabled,
+            start: Instant::now(),
+            phases: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record<S: Into<String>>(&self, phase: S, duration: Duration) {
+        self.phases.lock().unwrap().push((phase.into(), duration));
+    }
+
+    /// Run the function and record how long it took
+    pub fn measure<S: Into<String>, T, F: FnOnce() -> T>(&self, phase: S, f: F) -> T {
+        let start = Instant::now();
+        let ret = f();
+        self.record(phase, start.elapsed());
+        ret
+    }
+
+    pub fn report(&self) -> String {
+        let phases = self.phases.lock().unwrap();
+        let width = phases.iter().map(|(p, _)| p.len()).max().unwrap_or(0);
+        let mut report = String::from("Timings:\n");
+        for (phase, duration) in phases.iter() {
+            report.push_str(&format!(
+                "    {:width$}  {:>8.3}s\n",
+                phase,
+                duration.as_secs_f64(),
+                width = width
+            ));
+        }
+        report.push_str(&format!(
+            "    {:width$}  {:>8.3}s\n",
+            "total",
+            self.start.elapsed().as_secs_f64(),
+            width = width
+        ));
+        report
+    }
+}
+
+impl Drop for Timings {
+    fn drop(&mut self) {
+        if self.enabled {
+            eprint!("{}", self.report());
+        }
+    }
+}
+
+/// Records how long clones and fetches take, all events are forwarded to the inner observer
+pub struct TimingObserver<'a> {
+    inner: &'a dyn Observer,
+    timings: &'a Timings,
+    started: Mutex<HashMap<PathBuf, (&'static str, Instant)>>,
+}
+
+impl<'a> TimingObserver<'a> {
+    pub fn new(inner: &'a dyn Observer, timings: &'a Timings) -> Self {
+        TimingObserver {
+            inner,
+            timings,
+            started: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn start(&self, action: &'static str, path: &Path) {
+        self.started
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), (action, Instant::now()));
+    }
+}
+
+impl Observer for TimingObserver<'_> {
+    fn clone_started(&self, url: &str, path: &Path) {
+        self.start("clone", path);
+        self.inner.clone_started(url, path);
+    }
+
+    fn fetch_started(&self, path: &Path) {
+        self.start("fetch", path);
+        self.inner.fetch_started(path);
+    }
+
+    fn fetch_progress(&self, path: &Path, progress: &Progress) {
+        self.inner.fetch_progress(path, progress);
+    }
+
+    fn fetch_finished(&self, path: &Path) {
+        if let Some((action, start)) = self.started.lock().unwrap().remove(path) {
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            self.timings.record(
+                format!("{} {}", action, name.to_string_lossy()),
+                start.elapsed(),
+            );
+        }
+        self.inner.fetch_finished(path);
+    }
+
+    fn pin_resolved(&self, name: &str, pin: &ResolvedPin) {
+        self.inner.pin_resolved(name, pin);
+    }
+
+    fn file_written(&self, path: &Path) {
+        self.inner.file_written(path);
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+
+/// Compare two pkgver strings the same way pacman's `vercmp` does (rpmvercmp)
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let mut one = a.as_bytes();
+    let mut two = b.as_bytes();
+
+    while !one.is_empty() && !two.is_empty() {
+        // skip separators, remember how many we've seen
+        let sep1 = one
+            .iter()
+            .take_while(|c| !c.is_ascii_alphanumeric())
+            .count();
+        let sep2 = two
+            .iter()
+            .take_while(|c| !c.is_ascii_alphanumeric())
+            .count();
+        one = &one[sep1..];
+        two = &two[sep2..];
+
+        if one.is_empty() || two.is_empty() {
+            break;
+        }
+
+        // a different number of separators means the segment with more wins
+        if sep1 != sep2 {
+            return sep1.cmp(&sep2);
+        }
+
+        let is_num = one[0].is_ascii_digit();
+        let segment = |s: &[u8]| {
+            if is_num {
+                s.iter().take_while(|c| c.is_ascii_digit()).count()
+            } else {
+                s.iter().take_while(|c| c.is_ascii_alphabetic()).count()
+            }
+        };
+        let len1 = segment(one);
+        let len2 = segment(two);
+        let (seg1, rest1) = one.split_at(len1);
+        let (seg2, rest2) = two.split_at(len2);
+
+        // segments of different types, numeric is newer
+        if len2 == 0 {
+            return if is_num {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let ord = if is_num {
+            let trim = |s: &[u8]| {
+                let zeros = s.iter().take_while(|c| **c == b'0').count();
+                s[zeros..].to_vec()
+            };
+            let seg1 = trim(seg1);
+            let seg2 = trim(seg2);
+            seg1.len().cmp(&seg2.len()).then_with(|| seg1.cmp(&seg2))
+        } else {
+            seg1.cmp(seg2)
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+
+        one = rest1;
+        two = rest2;
+    }
+
+    match (one.first(), two.first()) {
+        (None, None) => Ordering::Equal,
+        // a trailing alpha segment (eg. 1.0alpha) is older than nothing (1.0)
+        (None, Some(c)) if !c.is_ascii_alphabetic() => Ordering::Less,
+        (Some(c), _) if c.is_ascii_alphabetic() => Ordering::Less,
+        _ => Ordering::Greater,
+    }
+}
+
+/// Split a full version into epoch, pkgver and pkgrel, like pacman's `parseEVR`
+fn parse_evr(version: &str) -> (&str, &str, Option<&str>) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) if epoch.bytes().all(|c| c.is_ascii_digit()) => {
+            (if epoch.is_empty() { "0" } else { epoch }, rest)
+        }
+        _ => ("0", version),
+    };
+    match rest.rsplit_once('-') {
+        Some((pkgver, pkgrel)) => (epoch, pkgver, Some(pkgrel)),
+        None => (epoch, rest, None),
+    }
+}
+
+/// Compare two full versions like `1:2.3-4` the same way pacman's `vercmp` does
+///
+/// A missing epoch is `0`, the pkgrel is only compared if both versions have one.
+pub fn pkg_vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (epoch1, pkgver1, pkgrel1) = parse_evr(a);
+    let (epoch2, pkgver2, pkgrel2) = parse_evr(b);
+    vercmp(epoch1, epoch2)
+        .then_with(|| vercmp(pkgver1, pkgver2))
+        .then_with(|| match (pkgrel1, pkgrel2) {
+            (Some(pkgrel1), Some(pkgrel2)) => vercmp(pkgrel1, pkgrel2),
+            _ => Ordering::Equal,
+        })
+}
+
+/// Check if the version is marked as alpha, beta, release candidate or similar
+pub fn is_prerelease(version: &str) -> bool {
+    version
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .any(|word| {
+            matches!(
+                word.to_ascii_lowercase().as_str(),
+                "alpha" | "beta" | "rc" | "pre" | "preview" | "dev"
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vercmp() {
+        // test vectors of pacman's test/util/vercmptest.sh
+        let vectors = [
+            // all similar length, no pkgrel
+            ("1.5.0", "1.5.0", 0),
+            ("1.5.1", "1.5.0", 1),
+            // mixed length
+            ("1.5.1", "1.5", 1),
+            // with pkgrel, simple
+            ("1.5.0-1", "1.5.0-1", 0),
+            ("1.5.0-1", "1.5.0-2", -1),
+            ("1.5.0-1", "1.5.1-1", -1),
+            ("1.5.0-2", "1.5.1-1", -1),
+            // with pkgrel, mixed lengths
+            ("1.5-1", "1.5.1-1", -1),
+            ("1.5-2", "1.5.1-1", -1),
+            ("1.5-2", "1.5.1-2", -1),
+            // mixed pkgrel inclusion
+            ("1.5", "1.5-1", 0),
+            ("1.5-1", "1.5", 0),
+            ("1.1-1", "1.1", 0),
+            ("1.0-1", "1.1", -1),
+            ("1.1-1", "1.0", 1),
+            // alphanumeric versions
+            ("1.5b-1", "1.5-1", -1),
+            ("1.5b", "1.5", -1),
+            ("1.5b-1", "1.5", -1),
+            ("1.5b", "1.5.1", -1),
+            // from the manpage
+            ("1.0a", "1.0alpha", -1),
+            ("1.0alpha", "1.0b", -1),
+            ("1.0b", "1.0beta", -1),
+            ("1.0beta", "1.0rc", -1),
+            ("1.0rc", "1.0", -1),
+            // going crazy? alpha-dotted versions
+            ("1.5.a", "1.5", 1),
+            ("1.5.b", "1.5.a", 1),
+            ("1.5.1", "1.5.b", 1),
+            // alpha dots and dashes
+            ("1.5.b-1", "1.5.b", 0),
+            ("1.5-1", "1.5.b", -1),
+            // same/similar content, differing separators
+            ("2.0", "2_0", 0),
+            ("2.0_a", "2_0.a", 0),
+            ("2.0a", "2.0.a", -1),
+            ("2___a", "2_a", 1),
+            // epoch included version comparisons
+            ("0:1.0", "0:1.0", 0),
+            ("0:1.0", "0:1.1", -1),
+            ("1:1.0", "0:1.0", 1),
+            ("1:1.0", "0:1.1", 1),
+            ("1:1.0", "2:1.1", -1),
+            // epoch + sometimes present pkgrel
+            ("1:1.0", "0:1.0-1", 1),
+            ("1:1.0-1", "0:1.1-1", 1),
+            // epoch included on one version
+            ("0:1.0", "1.0", 0),
+            ("0:1.0", "1.1", -1),
+            ("0:1.1", "1.0", 1),
+            ("1:1.0", "1.0", 1),
+            ("1:1.0", "1.1", 1),
+            ("1:1.1", "1.1", 1),
+            // unlike rpm and dpkg, a tilde is a separator like any other
+            ("1.0~rc1", "1.0", 1),
+            ("1.0~rc1", "1.0.rc1", 0),
+        ];
+        for (a, b, expected) in vectors {
+            let expected = expected.cmp(&0);
+            assert_eq!(pkg_vercmp(a, b), expected, "vercmp {} {}", a, b);
+            assert_eq!(pkg_vercmp(b, a), expected.reverse(), "vercmp {} {}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_is_prerelease() {
+        for version in [
+            "1.0rc1",
+            "1.0-beta.2",
+            "2.0.0-RC.1",
+            "3.0~alpha",
+            "1.1.dev3",
+        ] {
+            assert!(is_prerelease(version), "{}", version);
+        }
+        for version in ["1.0", "1.0.1", "2024.01.31", "1.0a", "1.0.r12.gabcdef"] {
+            assert!(!is_prerelease(version), "{}", version);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Run git with a fixed identity, ignoring the config of the user
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args([
+            "-c",
+            "user.name=updvcspins",
+            "-c",
+            "user.email=updvcspins@example.com",
+            "-c",
+            "commit.gpgsign=false",
+            "-c",
+            "tag.gpgsign=false",
+            "-c",
+            "init.defaultBranch=main",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {:?}",
+        args,
+        output
+    );
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .trim_end()
+        .to_string()
+}
+
+/// An upstream repository with a single commit, tagged `v1.0.0`
+fn upstream(dir: &Path) -> PathBuf {
+    let path = dir.join("upstream");
+    fs::create_dir(&path).unwrap();
+    git(&path, &["init", "-q"]);
+    git(
+        &path,
+        &["commit", "-q", "--allow-empty", "-m", "Initial commit"],
+    );
+    git(&path, &["tag", "-a", "v1.0.0", "-m", "Release 1.0.0"]);
+    path
+}
+
+fn pkgbuild(upstream: &Path) -> String {
+    format!(
+        "pkgname=foo\npkgver=1.0.0\nsource=(\"foo::git+file://{0}#tag=v1.0.0\")\nvcspins=(\"foo::git+file://{0}#tag=v1.0.0\")\n",
+        upstream.display()
+    )
+}
+
+fn updvcspins(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    run(
+        Command::new(env!("CARGO_BIN_EXE_updvcspins")),
+        dir,
+        args,
+        stdin,
+    )
+}
+
+/// Run in a new session, so there's no controlling terminal to open
+fn updvcspins_without_tty(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut command = Command::new("setsid");
+    command.arg(env!("CARGO_BIN_EXE_updvcspins"));
+    run(command, dir, args, stdin)
+}
+
+fn run(mut command: Command, dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = command
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_filter_stdin() {
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = upstream(dir.path());
+    let tag_hash = git(&upstream, &["rev-parse", "v1.0.0"]);
+    let pkgbuild = pkgbuild(&upstream);
+
+    // the repositories can't be located next to a PKGBUILD read from stdin
+    let output = updvcspins(dir.path(), &["-p", "-"], pkgbuild.as_bytes());
+    assert!(!output.status.success());
+
+    let output = updvcspins(dir.path(), &["-p", "-", "-C", "."], pkgbuild.as_bytes());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "pkgname=foo\npkgver=1.0.0\nsource=(\n    \"foo::git+file://{0}#tag={1}\"\n)\nvcspins=(\"foo::git+file://{0}#tag=v1.0.0\")\n",
+            upstream.display(),
+            tag_hash
+        )
+    );
+    assert!(dir.path().join("foo").exists());
+    assert!(!dir.path().join("PKGBUILD").exists());
+}
+
+#[test]
+fn test_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = upstream(dir.path());
+    let tag_hash = git(&upstream, &["rev-parse", "v1.0.0"]);
+    let pkgbuild = pkgbuild(&upstream);
+    fs::write(dir.path().join("PKGBUILD"), &pkgbuild).unwrap();
+    let expected = pkgbuild.replacen(
+        &format!(
+            "source=(\"foo::git+file://{}#tag=v1.0.0\")",
+            upstream.display()
+        ),
+        &format!(
+            "source=(\n    \"foo::git+file://{}#tag={}\"\n)",
+            upstream.display(),
+            tag_hash
+        ),
+        1,
+    );
+
+    let output = updvcspins(dir.path(), &["-o", "-"], b"");
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    let output = updvcspins(dir.path(), &["-o", "PKGBUILD.new"], b"");
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("PKGBUILD.new")).unwrap(),
+        expected
+    );
+    // the input is left untouched
+    assert_eq!(
+        fs::read_to_string(dir.path().join("PKGBUILD")).unwrap(),
+        pkgbuild
+    );
+}
+
+#[test]
+fn test_paths_from_stdin_interactive() {
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = upstream(dir.path());
+    let pkgbuild = pkgbuild(&upstream);
+    fs::write(dir.path().join("PKGBUILD"), &pkgbuild).unwrap();
+
+    // the confirmation is read from the terminal, not from the paths on stdin
+    let output = updvcspins_without_tty(
+        dir.path(),
+        &["--paths-from", "-", "--interactive"],
+        b"PKGBUILD\n",
+    );
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Failed to open terminal for confirmation"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("PKGBUILD")).unwrap(),
+        pkgbuild
+    );
+}
+
+#[test]
+fn test_resolve_order_needs_clone() {
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = upstream(dir.path());
+    fs::write(dir.path().join("PKGBUILD"), pkgbuild(&upstream)).unwrap();
+
+    let output = updvcspins(
+        dir.path(),
+        &["--resolve-order", "*=ls-remote", "--changelog"],
+        b"",
+    );
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("--changelog needs a local clone"),
+        "{}",
+        stderr
+    );
+}